@@ -0,0 +1,1545 @@
+//! The runtime of the Clojure programs compiled to Rust: their values and
+//! the functions of `clojure.core` they call.
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::Hash;
+use std::iter::FromIterator;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::{Rc, Weak};
+use std::slice::Iter;
+#[derive(Debug, Clone)]
+pub enum CljVal {
+    CljNil,
+    CljBool(bool),
+    CljInt(i64),
+    CljFloat(f64),
+    CljString(String),
+    CljSymbol(String),
+    CljKeyword(String),
+    CljVec(Vec<CljVal>),
+    CljList(Vec<CljVal>),
+    CljMap(Vec<(CljVal, CljVal)>),
+    CljMeta(Vec<(CljVal, CljVal)>),
+    CljCommentLine(String),
+    CljFn(CljFn),
+    CljRecord(CljRecord),
+    CljMultiFn(MultiFn),
+    CljException(Rc<Exception>),
+}
+
+/// The Rust function of a function value.
+pub type FnBody = dyn Fn(&[CljVal]) -> CljVal;
+
+/// A function value, called with its arguments as a slice.
+#[derive(Clone)]
+pub struct CljFn(pub Rc<FnBody>);
+
+impl fmt::Debug for CljFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CljFn")
+    }
+}
+
+/// A multimethod, calling the method for the value its dispatch function
+/// makes of the arguments, or for the closest value that one `isa?` in the
+/// global hierarchy, or else its default method. Clones share their
+/// methods.
+#[derive(Clone)]
+pub struct MultiFn(Rc<MultiTable>);
+
+struct MultiTable {
+    name: String,
+    dispatch: CljVal,
+    /// The dispatch value of the default method.
+    default: CljVal,
+    methods: RefCell<Vec<(CljVal, CljVal)>>,
+    /// Dispatch values with those they are preferred to when both match.
+    prefers: RefCell<Vec<(CljVal, CljVal)>>,
+}
+
+impl fmt::Debug for MultiFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MultiFn({})", self.0.name)
+    }
+}
+
+impl MultiFn {
+    pub fn call(&self, args: &[CljVal]) -> CljVal {
+        let value = self.0.dispatch.call(args);
+        match self.method(&value) {
+            Some(method) => method.call(args),
+            None => panic!(
+                "No method in multimethod '{}' for dispatch value: {}",
+                self.0.name, value
+            ),
+        }
+    }
+    /// The method for a dispatch value: its own, or the one of the value it
+    /// `isa?` that dominates the others, or the default method.
+    fn method(&self, value: &CljVal) -> Option<CljVal> {
+        let methods = self.0.methods.borrow();
+        let mut best: Option<&(CljVal, CljVal)> = None;
+        for entry in methods.iter().filter(|(k, _)| isa(value, k).truthy()) {
+            best = match best {
+                Some(b) if self.dominates(&b.0, &entry.0) => Some(b),
+                Some(b) if !self.dominates(&entry.0, &b.0) => panic!(
+                    "Multiple methods in multimethod '{}' match dispatch value: {} -> {} and {}, and neither is preferred",
+                    self.0.name, value, entry.0, b.0
+                ),
+                _ => Some(entry),
+            };
+        }
+        best.or_else(|| methods.iter().find(|(k, _)| *k == self.0.default))
+            .map(|(_, method)| method.clone())
+    }
+    fn dominates(&self, x: &CljVal, y: &CljVal) -> bool {
+        self.prefers(x, y) || isa(x, y).truthy()
+    }
+    /// Whether `x` is preferred to `y`, or to a parent of it, or a parent of
+    /// it is preferred to `y`.
+    fn prefers(&self, x: &CljVal, y: &CljVal) -> bool {
+        if self
+            .0
+            .prefers
+            .borrow()
+            .iter()
+            .any(|(a, b)| a == x && b == y)
+        {
+            return true;
+        }
+        parent_tags(y).iter().any(|p| self.prefers(x, p))
+            || parent_tags(x).iter().any(|p| self.prefers(p, y))
+    }
+}
+
+/// What `throw` throws: an `ex-info`, with its data, or an error of the
+/// runtime that was caught.
+#[derive(Debug)]
+pub struct Exception {
+    pub message: String,
+    /// The map of an `ex-info`, nil for an error of the runtime.
+    pub data: CljVal,
+    pub cause: CljVal,
+}
+
+impl Exception {
+    pub fn is_info(&self) -> bool {
+        self.data != CljVal::CljNil
+    }
+}
+
+/// The struct of a `defrecord`, which is also a map of its fields.
+pub trait Record: fmt::Debug {
+    /// Its name, qualified with the namespace defining it.
+    fn type_name(&self) -> &str;
+    /// Its fields, by keyword, in the order they are declared in.
+    fn fields(&self) -> Vec<(CljVal, CljVal)>;
+    /// A copy with the field `key` set to `value`, unless it has no such
+    /// field.
+    fn with_field(&self, key: &str, value: &CljVal) -> Option<Rc<dyn Record>>;
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// A record as a value, with the entries `assoc`ed to it that are not its
+/// fields.
+#[derive(Debug, Clone)]
+pub struct CljRecord {
+    pub record: Rc<dyn Record>,
+    pub extra: Vec<(CljVal, CljVal)>,
+}
+
+impl CljRecord {
+    /// Its fields, then its other entries.
+    pub fn entries(&self) -> Vec<(CljVal, CljVal)> {
+        let mut entries = self.record.fields();
+        entries.extend(self.extra.iter().cloned());
+        entries
+    }
+}
+
+/// A record of a type only known at run time, which is how the REPL and the
+/// `test` interpreter define them.
+#[derive(Debug, Clone)]
+pub struct DynRecord {
+    pub type_name: String,
+    pub fields: Vec<(CljVal, CljVal)>,
+}
+
+impl Record for DynRecord {
+    fn type_name(&self) -> &str {
+        &self.type_name
+    }
+    fn fields(&self) -> Vec<(CljVal, CljVal)> {
+        self.fields.clone()
+    }
+    fn with_field(&self, key: &str, value: &CljVal) -> Option<Rc<dyn Record>> {
+        let i = self
+            .fields
+            .iter()
+            .position(|(k, _)| matches!(k, CljVal::CljKeyword(k) if k == key))?;
+        let mut record = self.clone();
+        record.fields[i].1 = value.clone();
+        Some(Rc::new(record))
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl CljVal {
+    pub fn new_list() -> CljVal {
+        CljVal::CljList(vec![])
+    }
+    pub fn new_map() -> CljVal {
+        CljVal::CljMap(vec![])
+    }
+    pub fn new_vec() -> CljVal {
+        CljVal::CljVec(vec![])
+    }
+    pub fn new_meta() -> CljVal {
+        CljVal::CljMeta(vec![])
+    }
+    pub fn new_fn<F: Fn(&[CljVal]) -> CljVal + 'static>(f: F) -> CljVal {
+        CljVal::CljFn(CljFn(Rc::new(f)))
+    }
+    /// A function called with itself before its arguments, as a named `fn`
+    /// calls itself by its name. It holds itself weakly, not to leak.
+    pub fn new_named_fn<F: Fn(&CljVal, &[CljVal]) -> CljVal + 'static>(f: F) -> CljVal {
+        fn cyclic<C: Fn(&[CljVal]) -> CljVal + 'static>(
+            make: impl FnOnce(Weak<FnBody>) -> C,
+        ) -> Rc<C> {
+            Rc::new_cyclic(|me: &Weak<C>| make(me.clone()))
+        }
+        CljVal::CljFn(CljFn(cyclic(move |me| {
+            move |args: &[CljVal]| f(&CljVal::CljFn(CljFn(me.upgrade().unwrap())), args)
+        })))
+    }
+    pub fn record<R: Record + 'static>(r: R) -> CljVal {
+        CljVal::CljRecord(CljRecord {
+            record: Rc::new(r),
+            extra: Vec::new(),
+        })
+    }
+    /// The struct of the record it is, if it is one of type `R`.
+    pub fn as_record<R: Record + 'static>(&self) -> Option<&R> {
+        match self {
+            CljVal::CljRecord(r) => r.record.as_any().downcast_ref(),
+            _ => None,
+        }
+    }
+    /// Everything but `nil` and `false` is true.
+    pub fn truthy(&self) -> bool {
+        !matches!(self, CljVal::CljNil | CljVal::CljBool(false))
+    }
+    /// Call a function, or look a keyword up in a map, as with `get`.
+    pub fn call(&self, args: &[CljVal]) -> CljVal {
+        match self {
+            CljVal::CljFn(f) => (f.0)(args),
+            CljVal::CljMultiFn(m) => m.call(args),
+            CljVal::CljKeyword(_) => match args {
+                [map] => get__2(map, self),
+                [map, default] => get__3(map, self, default),
+                _ => arity_error(&self.to_string(), args.len()),
+            },
+            _ => panic!("not a function"),
+        }
+    }
+    /// The arguments bound to the parameter after `&`: nil when there are none.
+    pub fn rest_args(args: &[CljVal]) -> CljVal {
+        if args.is_empty() {
+            CljVal::CljNil
+        } else {
+            CljVal::CljList(args.to_vec())
+        }
+    }
+    pub fn items(&self) -> Vec<CljVal> {
+        match self {
+            CljVal::CljVec(v) | CljVal::CljList(v) => v.clone(),
+            CljVal::CljNil => vec![],
+            _ => panic!("not a seq"),
+        }
+    }
+    pub fn list_type(&self) -> Option<String> {
+        match self {
+            CljVal::CljList(v) => match &v[0] {
+                CljVal::CljSymbol(s) => Some(s.clone()),
+                _ => panic!("not callable list"),
+            },
+            _ => None,
+        }
+    }
+    pub fn map_to_meta(self) -> CljVal {
+        match self {
+            CljVal::CljMap(v) => CljVal::CljMeta(v),
+            _ => panic!("not map"),
+        }
+    }
+    pub fn insert(&mut self, k: CljVal, v: CljVal) {
+        match self {
+            CljVal::CljMap(v1) | CljVal::CljMeta(v1) => {
+                v1.push((k, v));
+            }
+            _ => panic!("can't insert"),
+        }
+    }
+    pub fn push(&mut self, x: CljVal) {
+        match self {
+            CljVal::CljVec(v) | CljVal::CljList(v) => {
+                v.push(x);
+            }
+            _ => panic!("can't push"),
+        }
+    }
+    pub fn pop(&mut self) -> CljVal {
+        match self {
+            CljVal::CljList(v) => match v.pop() {
+                Some(x) => x,
+                None => CljVal::CljNil,
+            },
+            CljVal::CljVec(v) => match v.pop() {
+                Some(x) => x,
+                None => CljVal::CljNil,
+            },
+
+            _ => panic!("can't pop"),
+        }
+    }
+    pub fn is_atom(&self) -> bool {
+        !matches!(self, CljVal::CljList(_))
+    }
+    pub fn is_nil(&self) -> bool {
+        match self {
+            CljVal::CljVec(v) => v.is_empty(),
+            CljVal::CljNil => true,
+            CljVal::CljList(v) => v.is_empty(),
+            CljVal::CljMap(v) | CljVal::CljMeta(v) if v.is_empty() => true,
+            CljVal::CljBool(false) => true,
+            _ => false,
+        }
+    }
+    /// What kind of value it is, for the errors about it.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CljVal::CljNil => "nil",
+            CljVal::CljBool(_) => "a boolean",
+            CljVal::CljInt(_) => "an integer",
+            CljVal::CljFloat(_) => "a float",
+            CljVal::CljString(_) => "a string",
+            CljVal::CljSymbol(_) => "a symbol",
+            CljVal::CljKeyword(_) => "a keyword",
+            CljVal::CljVec(_) => "a vector",
+            CljVal::CljList(_) => "a list",
+            CljVal::CljMap(_) => "a map",
+            CljVal::CljMeta(_) => "metadata",
+            CljVal::CljCommentLine(_) => "a comment",
+            CljVal::CljFn(_) => "a function",
+            CljVal::CljRecord(_) => "a record",
+            CljVal::CljMultiFn(_) => "a multimethod",
+            CljVal::CljException(_) => "an exception",
+        }
+    }
+    /// The type of the value, by the name protocols are extended to it with:
+    /// `Long`, `String`, and so on, or the qualified name of its record.
+    pub fn type_name(&self) -> &str {
+        match self {
+            CljVal::CljNil => "nil",
+            CljVal::CljBool(_) => "Boolean",
+            CljVal::CljInt(_) => "Long",
+            CljVal::CljFloat(_) => "Double",
+            CljVal::CljString(_) => "String",
+            CljVal::CljSymbol(_) => "Symbol",
+            CljVal::CljKeyword(_) => "Keyword",
+            CljVal::CljVec(_) => "PersistentVector",
+            CljVal::CljList(_) => "PersistentList",
+            CljVal::CljMap(_) => "PersistentArrayMap",
+            CljVal::CljMeta(_) => "Meta",
+            CljVal::CljCommentLine(_) => "Comment",
+            CljVal::CljFn(_) => "Fn",
+            CljVal::CljRecord(r) => r.record.type_name(),
+            CljVal::CljMultiFn(_) => "MultiFn",
+            CljVal::CljException(e) if e.is_info() => "ExceptionInfo",
+            CljVal::CljException(_) => "RuntimeException",
+        }
+    }
+    /// How many items a collection has, or characters a string, as `count`.
+    pub fn len(&self) -> usize {
+        match self {
+            CljVal::CljNil => 0,
+            CljVal::CljString(s) => s.chars().count(),
+            CljVal::CljVec(v) | CljVal::CljList(v) => v.len(),
+            CljVal::CljMap(v) | CljVal::CljMeta(v) => v.len(),
+            _ => panic!("no len method"),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_leaf_list(&self) -> bool {
+        match self {
+            CljVal::CljList(v) => {
+                for i in v {
+                    match i {
+                        CljVal::CljList(v1) => {
+                            if (!v1.is_empty()) & (CljVal::CljSymbol(String::from("\'")) == v1[0]) {
+                                continue;
+                            } else {
+                                return false;
+                            }
+                        }
+                        _ => continue,
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        match self {
+            CljVal::CljList(_) => self.is_leaf_list(),
+            _ => true,
+        }
+    }
+    pub fn is_list(&self) -> bool {
+        matches!(self, CljVal::CljList(_))
+    }
+
+    pub fn text(&self) -> String {
+        format!("{}", self)
+    }
+    pub fn first(&self) -> CljVal {
+        match self {
+            CljVal::CljList(v) => {
+                if v.is_empty() {
+                    CljVal::CljNil
+                } else {
+                    v[0].clone()
+                }
+            }
+            _ => panic!("no method first"),
+        }
+    }
+    pub fn rest(&self) -> CljVal {
+        match self {
+            CljVal::CljList(v) => {
+                if v.len() > 1 {
+                    CljVal::CljList(v[1..].to_vec())
+                } else {
+                    CljVal::CljNil
+                }
+            }
+            _ => panic!("no method rest"),
+        }
+    }
+    pub fn cons(&self, a: CljVal) -> CljVal {
+        match self {
+            CljVal::CljList(v) => {
+                let mut v = v.clone();
+                let mut va = vec![a];
+                va.append(&mut v);
+                CljVal::CljList(va)
+            }
+            _ => panic!("no method cons"),
+        }
+    }
+    pub fn cons_mut(&mut self, a: CljVal) {
+        match self {
+            CljVal::CljList(v) => {
+                v.insert(0, a);
+            }
+            _ => panic!("no method cons_mut"),
+        }
+    }
+    pub fn iter(&self) -> Iter<'_, CljVal> {
+        match self {
+            CljVal::CljList(v) => v.iter(),
+            _ => panic!("no method iter"),
+        }
+    }
+}
+
+impl Iterator for CljVal {
+    type Item = CljVal;
+    fn next(&mut self) -> Option<CljVal> {
+        match self {
+            CljVal::CljList(_) => {
+                let first = self.first();
+                if self.is_nil() {
+                    return None;
+                }
+                let rest = self.rest();
+                *self = rest;
+                Some(first)
+            }
+            CljVal::CljNil => None,
+            _ => panic!("no method next"),
+        }
+    }
+}
+impl FromIterator<CljVal> for CljVal {
+    fn from_iter<I: IntoIterator<Item = CljVal>>(iter: I) -> Self {
+        let mut l = CljVal::new_list();
+        for i in iter {
+            l.cons_mut(i);
+        }
+        l
+    }
+}
+
+impl PartialEq for CljVal {
+    fn eq(&self, other: &CljVal) -> bool {
+        match (self, other) {
+            (CljVal::CljNil, CljVal::CljNil) => true,
+            (CljVal::CljNil, CljVal::CljList(v)) => v.is_empty(),
+            (CljVal::CljList(v), CljVal::CljNil) => v.is_empty(),
+            (CljVal::CljString(s1), CljVal::CljString(s2)) => s1 == s2,
+            (CljVal::CljSymbol(s1), CljVal::CljSymbol(s2)) => s1 == s2,
+            (CljVal::CljKeyword(s1), CljVal::CljKeyword(s2)) => s1 == s2,
+            (CljVal::CljVec(v1), CljVal::CljVec(v2))
+            | (CljVal::CljVec(v1), CljVal::CljList(v2))
+            | (CljVal::CljList(v1), CljVal::CljVec(v2))
+            | (CljVal::CljList(v1), CljVal::CljList(v2)) => v1 == v2,
+            (CljVal::CljMap(m1), CljVal::CljMap(m2)) => {
+                m1.len() == m2.len()
+                    && m1
+                        .iter()
+                        .all(|(k, v)| m2.iter().any(|(k2, v2)| k2 == k && v2 == v))
+            }
+            (CljVal::CljBool(b1), CljVal::CljBool(b2)) => b1 == b2,
+            (CljVal::CljInt(i1), CljVal::CljInt(i2)) => i1 == i2,
+            (CljVal::CljFloat(f1), CljVal::CljFloat(f2)) => f1 == f2,
+            (CljVal::CljNil, CljVal::CljVec(_)) => other.is_nil(),
+            (CljVal::CljVec(_), CljVal::CljNil) => self.is_nil(),
+            (CljVal::CljMap(_), CljVal::CljNil) => self.is_nil(),
+            (CljVal::CljNil, CljVal::CljMap(_)) => other.is_nil(),
+            (CljVal::CljMeta(_), CljVal::CljNil) => self.is_nil(),
+            (CljVal::CljNil, CljVal::CljMeta(_)) => other.is_nil(),
+            (CljVal::CljFn(f1), CljVal::CljFn(f2)) => Rc::ptr_eq(&f1.0, &f2.0),
+            (CljVal::CljMultiFn(m1), CljVal::CljMultiFn(m2)) => Rc::ptr_eq(&m1.0, &m2.0),
+            (CljVal::CljException(e1), CljVal::CljException(e2)) => Rc::ptr_eq(e1, e2),
+            // Records of the same type with the same entries, but not maps.
+            (CljVal::CljRecord(r1), CljVal::CljRecord(r2)) => {
+                r1.record.type_name() == r2.record.type_name()
+                    && CljVal::CljMap(r1.entries()) == CljVal::CljMap(r2.entries())
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for CljVal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CljVal::CljBool(s) => write!(f, "{}", s),
+            CljVal::CljInt(s) => write!(f, "{}", s),
+            CljVal::CljFloat(s) => write!(f, "{}", s),
+            CljVal::CljString(s) => write!(f, "{}", s),
+            CljVal::CljSymbol(s) => write!(f, "{}", s),
+            CljVal::CljKeyword(s) => write!(f, ":{}", s),
+            CljVal::CljCommentLine(s) => write!(f, "{}", s),
+            CljVal::CljNil => write!(f, "nil"),
+            CljVal::CljList(v) => write_items(f, "(", v, ")"),
+            CljVal::CljVec(v) => write_items(f, "[", v, "]"),
+            CljVal::CljFn(_) => write!(f, "#function"),
+            CljVal::CljMultiFn(m) => write!(f, "#multifn[{}]", m.0.name),
+            CljVal::CljException(e) if e.is_info() => {
+                write!(f, "clojure.lang.ExceptionInfo: {} {}", e.message, e.data)
+            }
+            CljVal::CljException(e) => write!(f, "java.lang.RuntimeException: {}", e.message),
+            CljVal::CljMap(v) => {
+                write!(f, "{{")?;
+                for (i, (k, item)) in v.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{} {}", k, item)?;
+                }
+                write!(f, "}}")
+            }
+            CljVal::CljRecord(r) => write!(
+                f,
+                "#{}{}",
+                r.record.type_name(),
+                CljVal::CljMap(r.entries())
+            ),
+            _ => panic!("can't do that"),
+        }
+    }
+}
+
+fn write_items(f: &mut fmt::Formatter, open: &str, v: &[CljVal], close: &str) -> fmt::Result {
+    write!(f, "{}", open)?;
+    for (i, item) in v.iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    write!(f, "{}", close)
+}
+
+/// The conversion of a value passed to Rust code, into the Rust type it is
+/// taken as, by the rules of `TryFrom`. A value that is not of that type
+/// panics.
+pub trait FromClj: Sized {
+    fn from_clj(v: &CljVal) -> Self;
+}
+impl<T> FromClj for T
+where
+    T: TryFrom<CljVal>,
+    T::Error: Into<ConversionError>,
+{
+    fn from_clj(v: &CljVal) -> T {
+        T::try_from(v.clone()).unwrap_or_else(|e| panic!("{}", e.into()))
+    }
+}
+
+/// The conversion of what Rust code returns back into a value, by `From`.
+pub trait IntoClj {
+    fn into_clj(self) -> CljVal;
+}
+impl<T: Into<CljVal>> IntoClj for T {
+    fn into_clj(self) -> CljVal {
+        self.into()
+    }
+}
+
+/// Why a value could not be converted into a Rust type: what was expected,
+/// what was found instead, and the keys of the maps it was found in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionError {
+    pub expected: &'static str,
+    pub found: String,
+    pub path: Vec<String>,
+}
+
+impl ConversionError {
+    pub fn new(expected: &'static str, found: &CljVal) -> ConversionError {
+        ConversionError {
+            expected,
+            found: format!("{} ({})", found, found.kind()),
+            path: Vec::new(),
+        }
+    }
+    /// The error, found in the value of `key` in a map.
+    pub fn in_key(mut self, key: &str) -> ConversionError {
+        self.path.insert(0, key.to_string());
+        self
+    }
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for key in &self.path {
+            write!(f, "in {}: ", key)?;
+        }
+        write!(f, "expected {}, found {}", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+/// Converting into a value itself can not fail.
+impl From<std::convert::Infallible> for ConversionError {
+    fn from(never: std::convert::Infallible) -> ConversionError {
+        match never {}
+    }
+}
+
+/// Integers are kept as `i64`, and panic out of its range. They convert
+/// back when they are in the range of the type, and floats when they are
+/// whole numbers in it too, as the value would change otherwise.
+macro_rules! int_conversions {
+    ($($t:ty),*) => {$(
+        impl From<$t> for CljVal {
+            fn from(x: $t) -> CljVal {
+                match i64::try_from(x) {
+                    Ok(i) => CljVal::CljInt(i),
+                    Err(_) => panic!("{} is out of the range of an integer", x),
+                }
+            }
+        }
+        impl TryFrom<CljVal> for $t {
+            type Error = ConversionError;
+            fn try_from(v: CljVal) -> Result<$t, ConversionError> {
+                let i = match v {
+                    CljVal::CljInt(i) => i128::from(i),
+                    CljVal::CljFloat(f) if f.fract() == 0.0 => f as i128,
+                    _ => return Err(ConversionError::new("an integer", &v)),
+                };
+                <$t>::try_from(i).map_err(|_| {
+                    ConversionError::new(concat!("an integer in the ", stringify!($t), " range"), &v)
+                })
+            }
+        }
+    )*};
+}
+int_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// Floats are kept as `f64`, and integers convert to them.
+macro_rules! float_conversions {
+    ($($t:ty),*) => {$(
+        impl From<$t> for CljVal {
+            fn from(x: $t) -> CljVal {
+                CljVal::CljFloat(f64::from(x))
+            }
+        }
+        impl TryFrom<CljVal> for $t {
+            type Error = ConversionError;
+            fn try_from(v: CljVal) -> Result<$t, ConversionError> {
+                match v {
+                    CljVal::CljInt(i) => Ok(i as $t),
+                    CljVal::CljFloat(f) => Ok(f as $t),
+                    _ => Err(ConversionError::new("a number", &v)),
+                }
+            }
+        }
+    )*};
+}
+float_conversions!(f32, f64);
+
+impl From<bool> for CljVal {
+    fn from(x: bool) -> CljVal {
+        CljVal::CljBool(x)
+    }
+}
+impl TryFrom<CljVal> for bool {
+    type Error = ConversionError;
+    fn try_from(v: CljVal) -> Result<bool, ConversionError> {
+        match v {
+            CljVal::CljBool(b) => Ok(b),
+            _ => Err(ConversionError::new("a boolean", &v)),
+        }
+    }
+}
+
+impl From<String> for CljVal {
+    fn from(x: String) -> CljVal {
+        CljVal::CljString(x)
+    }
+}
+impl<'a> From<&'a str> for CljVal {
+    fn from(x: &'a str) -> CljVal {
+        CljVal::CljString(x.to_string())
+    }
+}
+impl TryFrom<CljVal> for String {
+    type Error = ConversionError;
+    fn try_from(v: CljVal) -> Result<String, ConversionError> {
+        match v {
+            CljVal::CljString(s) => Ok(s),
+            _ => Err(ConversionError::new("a string", &v)),
+        }
+    }
+}
+
+/// A character is a string of one.
+impl From<char> for CljVal {
+    fn from(x: char) -> CljVal {
+        CljVal::CljString(x.to_string())
+    }
+}
+impl TryFrom<CljVal> for char {
+    type Error = ConversionError;
+    fn try_from(v: CljVal) -> Result<char, ConversionError> {
+        match &v {
+            CljVal::CljString(s) if s.chars().count() == 1 => Ok(s.chars().next().unwrap()),
+            _ => Err(ConversionError::new("a string of one character", &v)),
+        }
+    }
+}
+
+impl From<()> for CljVal {
+    fn from(_: ()) -> CljVal {
+        CljVal::CljNil
+    }
+}
+
+impl<T: Into<CljVal>> From<Vec<T>> for CljVal {
+    fn from(x: Vec<T>) -> CljVal {
+        CljVal::CljVec(x.into_iter().map(Into::into).collect())
+    }
+}
+impl<T> TryFrom<CljVal> for Vec<T>
+where
+    T: TryFrom<CljVal>,
+    T::Error: Into<ConversionError>,
+{
+    type Error = ConversionError;
+    fn try_from(v: CljVal) -> Result<Vec<T>, ConversionError> {
+        match v {
+            CljVal::CljVec(items) | CljVal::CljList(items) => items
+                .into_iter()
+                .enumerate()
+                .map(|(i, x)| T::try_from(x).map_err(|e| e.into().in_key(&i.to_string())))
+                .collect(),
+            CljVal::CljNil => Ok(Vec::new()),
+            _ => Err(ConversionError::new("a vector or list", &v)),
+        }
+    }
+}
+
+impl<K: Into<CljVal>, V: Into<CljVal>> From<HashMap<K, V>> for CljVal {
+    fn from(x: HashMap<K, V>) -> CljVal {
+        CljVal::CljMap(x.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
+impl<K, V> TryFrom<CljVal> for HashMap<K, V>
+where
+    K: TryFrom<CljVal> + Eq + Hash,
+    K::Error: Into<ConversionError>,
+    V: TryFrom<CljVal>,
+    V::Error: Into<ConversionError>,
+{
+    type Error = ConversionError;
+    fn try_from(v: CljVal) -> Result<HashMap<K, V>, ConversionError> {
+        match v {
+            CljVal::CljMap(entries) => entries
+                .into_iter()
+                .map(|(k, x)| {
+                    let key = k.to_string();
+                    let k = K::try_from(k).map_err(|e| e.into().in_key(&key))?;
+                    let x = V::try_from(x).map_err(|e| e.into().in_key(&key))?;
+                    Ok((k, x))
+                })
+                .collect(),
+            CljVal::CljNil => Ok(HashMap::new()),
+            _ => Err(ConversionError::new("a map", &v)),
+        }
+    }
+}
+
+/// `None` is `nil`.
+impl<T: Into<CljVal>> From<Option<T>> for CljVal {
+    fn from(x: Option<T>) -> CljVal {
+        x.map_or(CljVal::CljNil, Into::into)
+    }
+}
+/// `nil` is `None`. Not for any `Option<T>`, which would take in
+/// `Option<CljVal>`, that a value already converts into.
+macro_rules! option_conversions {
+    ($(impl<$($p:ident),*> for $t:ty where [$($bounds:tt)*])*) => {$(
+        impl<$($p),*> TryFrom<CljVal> for Option<$t> where $($bounds)* {
+            type Error = ConversionError;
+            fn try_from(v: CljVal) -> Result<Option<$t>, ConversionError> {
+                match v {
+                    CljVal::CljNil => Ok(None),
+                    _ => <$t>::try_from(v).map(Some),
+                }
+            }
+        }
+    )*};
+}
+option_conversions! {
+    impl<> for i8 where []
+    impl<> for i16 where []
+    impl<> for i32 where []
+    impl<> for i64 where []
+    impl<> for isize where []
+    impl<> for u8 where []
+    impl<> for u16 where []
+    impl<> for u32 where []
+    impl<> for u64 where []
+    impl<> for usize where []
+    impl<> for f32 where []
+    impl<> for f64 where []
+    impl<> for bool where []
+    impl<> for String where []
+    impl<> for char where []
+    impl<T> for Vec<T> where [T: TryFrom<CljVal>, T::Error: Into<ConversionError>]
+    impl<K, V> for HashMap<K, V> where [
+        K: TryFrom<CljVal> + Eq + Hash,
+        K::Error: Into<ConversionError>,
+        V: TryFrom<CljVal>,
+        V::Error: Into<ConversionError>,
+    ]
+}
+
+/// The value of the keyword `key` in the entries of a map, converted, `nil`
+/// if there is none: what `#[derive(CljMap)]` reads each field with.
+pub fn map_field<T>(entries: &[(CljVal, CljVal)], key: &str) -> Result<T, ConversionError>
+where
+    T: TryFrom<CljVal>,
+    T::Error: Into<ConversionError>,
+{
+    let value = entries
+        .iter()
+        .find(|(k, _)| matches!(k, CljVal::CljKeyword(k) if k == key))
+        .map_or(CljVal::CljNil, |(_, v)| v.clone());
+    T::try_from(value).map_err(|e| e.into().in_key(&format!(":{}", key)))
+}
+
+/// The value of the keyword `key` in the entries of a map, converted unless
+/// it is `nil` or there is none: an `Option` field of `#[derive(CljMap)]`.
+pub fn map_field_opt<T>(
+    entries: &[(CljVal, CljVal)],
+    key: &str,
+) -> Result<Option<T>, ConversionError>
+where
+    T: TryFrom<CljVal>,
+    T::Error: Into<ConversionError>,
+{
+    match map_field::<CljVal>(entries, key)? {
+        CljVal::CljNil => Ok(None),
+        value => T::try_from(value)
+            .map(Some)
+            .map_err(|e| e.into().in_key(&format!(":{}", key))),
+    }
+}
+
+pub fn arity_error(name: &str, n: usize) -> ! {
+    panic!("Wrong number of args ({}) passed to: {}", n, name)
+}
+
+pub fn map(f: &CljVal, coll: &CljVal) -> CljVal {
+    CljVal::CljList(
+        coll.items()
+            .iter()
+            .map(|x| f.call(std::slice::from_ref(x)))
+            .collect(),
+    )
+}
+
+#[allow(non_snake_case)]
+pub fn reduce__2(f: &CljVal, coll: &CljVal) -> CljVal {
+    let items = coll.items();
+    match items.split_first() {
+        None => f.call(&[]),
+        Some((first, rest)) => rest
+            .iter()
+            .fold(first.clone(), |acc, x| f.call(&[acc, x.clone()])),
+    }
+}
+
+#[allow(non_snake_case)]
+pub fn reduce__3(f: &CljVal, init: &CljVal, coll: &CljVal) -> CljVal {
+    coll.items()
+        .into_iter()
+        .fold(init.clone(), |acc, x| f.call(&[acc, x]))
+}
+
+pub fn reduce(args: &[CljVal]) -> CljVal {
+    match args.len() {
+        2 => reduce__2(&args[0], &args[1]),
+        3 => reduce__3(&args[0], &args[1], &args[2]),
+        n => arity_error("reduce", n),
+    }
+}
+
+#[allow(non_snake_case)]
+pub fn get__2(m: &CljVal, k: &CljVal) -> CljVal {
+    get__3(m, k, &CljVal::CljNil)
+}
+
+#[allow(non_snake_case)]
+pub fn get__3(m: &CljVal, k: &CljVal, not_found: &CljVal) -> CljVal {
+    let found = match (m, k) {
+        (CljVal::CljMap(v), _) => v.iter().find(|(k1, _)| k1 == k).map(|(_, v1)| v1.clone()),
+        (CljVal::CljRecord(r), _) => r
+            .entries()
+            .into_iter()
+            .find(|(k1, _)| k1 == k)
+            .map(|(_, v1)| v1),
+        (CljVal::CljVec(v), CljVal::CljInt(i)) if *i >= 0 => v.get(*i as usize).cloned(),
+        _ => None,
+    };
+    found.unwrap_or_else(|| not_found.clone())
+}
+
+pub fn get(args: &[CljVal]) -> CljVal {
+    match args.len() {
+        2 => get__2(&args[0], &args[1]),
+        3 => get__3(&args[0], &args[1], &args[2]),
+        n => arity_error("get", n),
+    }
+}
+
+/// The map with `k` set to `v`, or the vector with the item at index `k`
+/// replaced. A record stays one, with the field set if `k` names one.
+pub fn assoc(m: &CljVal, k: &CljVal, v: &CljVal) -> CljVal {
+    let set = |entries: &[(CljVal, CljVal)]| {
+        let mut entries = entries.to_vec();
+        match entries.iter_mut().find(|(k1, _)| k1 == k) {
+            Some(entry) => entry.1 = v.clone(),
+            None => entries.push((k.clone(), v.clone())),
+        }
+        entries
+    };
+    match (m, k) {
+        (CljVal::CljMap(entries), _) => CljVal::CljMap(set(entries)),
+        (CljVal::CljNil, _) => CljVal::CljMap(vec![(k.clone(), v.clone())]),
+        (CljVal::CljVec(items), CljVal::CljInt(i)) if *i >= 0 && *i as usize <= items.len() => {
+            let mut items = items.clone();
+            match items.get_mut(*i as usize) {
+                Some(item) => *item = v.clone(),
+                None => items.push(v.clone()),
+            }
+            CljVal::CljVec(items)
+        }
+        (CljVal::CljVec(_), _) => panic!("Index {} out of bounds", k),
+        (CljVal::CljRecord(r), _) => {
+            let field = match k {
+                CljVal::CljKeyword(name) => r.record.with_field(name, v),
+                _ => None,
+            };
+            CljVal::CljRecord(match field {
+                Some(record) => CljRecord {
+                    record,
+                    extra: r.extra.clone(),
+                },
+                None => CljRecord {
+                    record: r.record.clone(),
+                    extra: set(&r.extra),
+                },
+            })
+        }
+        _ => panic!("can not assoc to {}", m.kind()),
+    }
+}
+
+/// The entries of a map or record, `nil` for none.
+fn entries(m: &CljVal) -> Vec<(CljVal, CljVal)> {
+    match m {
+        CljVal::CljMap(entries) => entries.clone(),
+        CljVal::CljRecord(r) => r.entries(),
+        CljVal::CljNil => Vec::new(),
+        _ => panic!("Don't know how to create ISeq from: {}", m.kind()),
+    }
+}
+
+/// `m` with every entry of the map `from` `assoc`ed to it.
+pub fn assoc_all(m: &CljVal, from: &CljVal) -> CljVal {
+    entries(from)
+        .iter()
+        .fold(m.clone(), |m, (k, v)| assoc(&m, k, v))
+}
+
+pub fn keys(m: &CljVal) -> CljVal {
+    match entries(m) {
+        entries if entries.is_empty() => CljVal::CljNil,
+        entries => CljVal::CljList(entries.into_iter().map(|(k, _)| k).collect()),
+    }
+}
+
+pub fn vals(m: &CljVal) -> CljVal {
+    match entries(m) {
+        entries if entries.is_empty() => CljVal::CljNil,
+        entries => CljVal::CljList(entries.into_iter().map(|(_, v)| v).collect()),
+    }
+}
+
+/// A multimethod named `name` with the dispatch function `dispatch`, and no
+/// methods yet.
+pub fn multi_fn(name: &CljVal, dispatch: &CljVal, default: &CljVal) -> CljVal {
+    CljVal::CljMultiFn(MultiFn(Rc::new(MultiTable {
+        name: name.to_string(),
+        dispatch: dispatch.clone(),
+        default: default.clone(),
+        methods: RefCell::new(Vec::new()),
+        prefers: RefCell::new(Vec::new()),
+    })))
+}
+
+fn multi(m: &CljVal) -> &MultiFn {
+    match m {
+        CljVal::CljMultiFn(m) => m,
+        _ => panic!("{} is not a multimethod", m),
+    }
+}
+
+/// Add the method `f` for `value`, replacing the one it had.
+pub fn add_method(m: &CljVal, value: &CljVal, f: &CljVal) -> CljVal {
+    let mut methods = multi(m).0.methods.borrow_mut();
+    methods.retain(|(k, _)| k != value);
+    methods.push((value.clone(), f.clone()));
+    m.clone()
+}
+
+pub fn remove_method(m: &CljVal, value: &CljVal) -> CljVal {
+    multi(m).0.methods.borrow_mut().retain(|(k, _)| k != value);
+    m.clone()
+}
+
+/// The method a multimethod calls for a dispatch value, or nil.
+pub fn get_method(m: &CljVal, value: &CljVal) -> CljVal {
+    multi(m).method(value).unwrap_or(CljVal::CljNil)
+}
+
+/// Prefer the method for `x` to the one for `y` when both match.
+pub fn prefer_method(m: &CljVal, x: &CljVal, y: &CljVal) -> CljVal {
+    let multi = multi(m);
+    if multi.prefers(y, x) {
+        panic!(
+            "Preference conflict in multimethod '{}': {} is already preferred to {}",
+            multi.0.name, y, x
+        )
+    }
+    multi.0.prefers.borrow_mut().push((x.clone(), y.clone()));
+    m.clone()
+}
+
+thread_local! {
+    /// The global hierarchy: each tag `derive`d, with a parent of it.
+    static HIERARCHY: RefCell<Vec<(CljVal, CljVal)>> = const { RefCell::new(Vec::new()) };
+}
+
+fn parent_tags(tag: &CljVal) -> Vec<CljVal> {
+    HIERARCHY.with(|h| {
+        h.borrow()
+            .iter()
+            .filter(|(child, _)| child == tag)
+            .map(|(_, parent)| parent.clone())
+            .collect()
+    })
+}
+
+/// The tags reached from `tag` by `step`, again and again, in the order
+/// they are found.
+fn reach(tag: &CljVal, step: fn(&CljVal) -> Vec<CljVal>) -> Vec<CljVal> {
+    let mut found: Vec<CljVal> = Vec::new();
+    let mut next = step(tag);
+    while let Some(t) = next.pop() {
+        if !found.contains(&t) {
+            next.extend(step(&t));
+            found.push(t);
+        }
+    }
+    found
+}
+
+fn child_tags(tag: &CljVal) -> Vec<CljVal> {
+    HIERARCHY.with(|h| {
+        h.borrow()
+            .iter()
+            .filter(|(_, parent)| parent == tag)
+            .map(|(child, _)| child.clone())
+            .collect()
+    })
+}
+
+fn tags(tags: Vec<CljVal>) -> CljVal {
+    match tags.is_empty() {
+        true => CljVal::CljNil,
+        false => CljVal::CljList(tags),
+    }
+}
+
+/// Make `parent` a parent of `child` in the global hierarchy.
+pub fn derive(child: &CljVal, parent: &CljVal) -> CljVal {
+    if child == parent {
+        panic!("Assert failed: (not= tag parent)")
+    }
+    if reach(parent, parent_tags).contains(child) {
+        panic!("Cyclic derivation: {} has {} as ancestor", parent, child)
+    }
+    if !parent_tags(child).contains(parent) {
+        HIERARCHY.with(|h| h.borrow_mut().push((child.clone(), parent.clone())));
+    }
+    CljVal::CljNil
+}
+
+pub fn underive(child: &CljVal, parent: &CljVal) -> CljVal {
+    HIERARCHY.with(|h| h.borrow_mut().retain(|(c, p)| !(c == child && p == parent)));
+    CljVal::CljNil
+}
+
+pub fn parents(tag: &CljVal) -> CljVal {
+    tags(parent_tags(tag))
+}
+
+pub fn ancestors(tag: &CljVal) -> CljVal {
+    tags(reach(tag, parent_tags))
+}
+
+pub fn descendants(tag: &CljVal) -> CljVal {
+    tags(reach(tag, child_tags))
+}
+
+/// Whether `child` is `parent`, derives from it, or is a vector of tags
+/// each of which `isa?` the one of `parent` in its place.
+pub fn isa(child: &CljVal, parent: &CljVal) -> CljVal {
+    let is = child == parent
+        || reach(child, parent_tags).contains(parent)
+        || match (child, parent) {
+            (CljVal::CljVec(c), CljVal::CljVec(p)) => {
+                c.len() == p.len() && c.iter().zip(p).all(|(c, p)| isa(c, p).truthy())
+            }
+            _ => false,
+        };
+    CljVal::CljBool(is)
+}
+
+#[allow(non_snake_case)]
+pub fn nth__2(coll: &CljVal, index: &CljVal) -> CljVal {
+    match (coll.items(), index) {
+        (v, CljVal::CljInt(i)) if *i >= 0 && (*i as usize) < v.len() => v[*i as usize].clone(),
+        _ => panic!("Index out of bounds"),
+    }
+}
+
+#[allow(non_snake_case)]
+pub fn nth__3(coll: &CljVal, index: &CljVal, not_found: &CljVal) -> CljVal {
+    match (coll.items(), index) {
+        (v, CljVal::CljInt(i)) if *i >= 0 && (*i as usize) < v.len() => v[*i as usize].clone(),
+        _ => not_found.clone(),
+    }
+}
+
+pub fn nth(args: &[CljVal]) -> CljVal {
+    match args.len() {
+        2 => nth__2(&args[0], &args[1]),
+        3 => nth__3(&args[0], &args[1], &args[2]),
+        n => arity_error("nth", n),
+    }
+}
+
+pub fn nthnext(coll: &CljVal, n: &CljVal) -> CljVal {
+    match n {
+        CljVal::CljInt(n) => CljVal::rest_args(coll.items().get(*n as usize..).unwrap_or(&[])),
+        _ => panic!("not a number"),
+    }
+}
+
+/// Map destructuring also takes a seq of keys and values, the rest
+/// arguments of a function called with keyword arguments.
+pub fn seq_to_map_for_destructuring(x: &CljVal) -> CljVal {
+    match x {
+        CljVal::CljList(v) if v.len() == 1 => v[0].clone(),
+        CljVal::CljList(v) => {
+            let mut m = CljVal::new_map();
+            for pair in v.chunks(2) {
+                if pair.len() == 2 {
+                    m.insert(pair[0].clone(), pair[1].clone());
+                }
+            }
+            m
+        }
+        _ => x.clone(),
+    }
+}
+
+/// What running code threw: an exception thrown with `throw`, or the
+/// message of an error of the runtime.
+#[derive(Debug)]
+pub enum CljErr {
+    ErrString(String),
+    ErrCljVal(CljVal),
+}
+
+pub type CljResult = Result<CljVal, CljErr>;
+
+impl CljErr {
+    /// What a `catch` binds: the exception thrown, or one made of the error.
+    pub fn exception(&self) -> CljVal {
+        match self {
+            CljErr::ErrString(message) => CljVal::CljException(Rc::new(Exception {
+                message: message.clone(),
+                data: CljVal::CljNil,
+                cause: CljVal::CljNil,
+            })),
+            CljErr::ErrCljVal(e) => e.clone(),
+        }
+    }
+    /// Throw it again, as it was thrown.
+    pub fn raise(self) -> ! {
+        match self {
+            CljErr::ErrString(message) => panic!("{}", message),
+            CljErr::ErrCljVal(e) => throw(&e),
+        }
+    }
+}
+
+thread_local! {
+    /// The exception being thrown, which the panic only has the message of.
+    static THROWN: RefCell<Option<CljVal>> = const { RefCell::new(None) };
+}
+
+/// Throw an exception, which is a panic with its message unless something
+/// catches it.
+pub fn throw(e: &CljVal) -> ! {
+    if !matches!(e, CljVal::CljException(_)) {
+        panic!(
+            "class {} cannot be cast to class java.lang.Throwable",
+            e.type_name()
+        )
+    }
+    THROWN.with(|t| *t.borrow_mut() = Some(e.clone()));
+    panic!("{}", e)
+}
+
+/// Run `body`, with what it throws as the error. The panic hook is quiet
+/// meanwhile, as what is caught is not reported.
+pub fn attempt<F: FnOnce() -> CljVal>(body: F) -> CljResult {
+    THROWN.with(|t| t.borrow_mut().take());
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = panic::catch_unwind(AssertUnwindSafe(body));
+    panic::set_hook(hook);
+    result.map_err(|payload| {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                // Not an error of the code run, like one of the compiler.
+                Err(payload) => panic::resume_unwind(payload),
+            },
+        };
+        match THROWN.with(|t| t.borrow_mut().take()) {
+            Some(e) => CljErr::ErrCljVal(e),
+            None => CljErr::ErrString(message),
+        }
+    })
+}
+
+/// A `try`: the value of `body`, or if it throws, the one of the `catch` it
+/// gives what was thrown to, unless that does not catch it. `finally` runs
+/// after either, and what was not caught is thrown again.
+pub fn try_catch<B, C, F>(body: B, catch: C, finally: F) -> CljVal
+where
+    B: FnOnce() -> CljVal,
+    C: FnOnce(&CljVal) -> Option<CljVal>,
+    F: FnOnce() -> CljVal,
+{
+    let outcome = match attempt(body) {
+        Err(err) => attempt(|| catch(&err.exception()).unwrap_or_else(|| err.raise())),
+        outcome => outcome,
+    };
+    finally();
+    outcome.unwrap_or_else(|err| err.raise())
+}
+
+/// Whether a `catch` of the class `class` catches `e`: one of
+/// `ExceptionInfo` only catches an `ex-info`, the others everything.
+pub fn catches(class: &str, e: &CljVal) -> bool {
+    match (class, e) {
+        ("ExceptionInfo", CljVal::CljException(e)) => e.is_info(),
+        _ => true,
+    }
+}
+
+/// Whether a `catch` of a map catches `e`: an `ex-info` with the entries of
+/// the map in its data.
+pub fn catches_data(pattern: &CljVal, e: &CljVal) -> bool {
+    match (pattern, e) {
+        (CljVal::CljMap(entries), CljVal::CljException(e)) if e.is_info() => {
+            entries.iter().all(|(k, v)| get__2(&e.data, k) == *v)
+        }
+        _ => false,
+    }
+}
+
+#[allow(non_snake_case)]
+pub fn ex_info__2(message: &CljVal, data: &CljVal) -> CljVal {
+    ex_info__3(message, data, &CljVal::CljNil)
+}
+
+/// An exception with a message, a map of data, and the exception that
+/// caused it.
+#[allow(non_snake_case)]
+pub fn ex_info__3(message: &CljVal, data: &CljVal, cause: &CljVal) -> CljVal {
+    if !matches!(data, CljVal::CljMap(_) | CljVal::CljRecord(_)) {
+        panic!("ex-info takes a map of data, not {}", data)
+    }
+    CljVal::CljException(Rc::new(Exception {
+        message: message.to_string(),
+        data: data.clone(),
+        cause: cause.clone(),
+    }))
+}
+
+pub fn ex_info(args: &[CljVal]) -> CljVal {
+    match args {
+        [message, data] => ex_info__2(message, data),
+        [message, data, cause] => ex_info__3(message, data, cause),
+        _ => arity_error("ex-info", args.len()),
+    }
+}
+
+/// The data of an `ex-info`, or nil.
+pub fn ex_data(e: &CljVal) -> CljVal {
+    match e {
+        CljVal::CljException(e) => e.data.clone(),
+        _ => CljVal::CljNil,
+    }
+}
+
+/// The message of an exception, or nil.
+pub fn ex_message(e: &CljVal) -> CljVal {
+    match e {
+        CljVal::CljException(e) => CljVal::CljString(e.message.clone()),
+        _ => CljVal::CljNil,
+    }
+}
+
+/// The exception that caused an exception, or nil.
+pub fn ex_cause(e: &CljVal) -> CljVal {
+    match e {
+        CljVal::CljException(e) => e.cause.clone(),
+        _ => CljVal::CljNil,
+    }
+}
+
+#[macro_export]
+macro_rules! cljlist {
+    ($($x:expr),*) => {
+        {
+            let mut l: Vec<CljVal> = Vec::new();
+            $( l.push($x); )*
+                CljVal::CljList(l)
+        }}
+    ;
+}
+
+pub fn inc(x: &CljVal) -> CljVal {
+    match x {
+        CljVal::CljInt(i) => CljVal::CljInt(i + 1),
+        CljVal::CljFloat(f) => CljVal::CljFloat(f + 1.0),
+        _ => panic!("{} cannot be cast to Number", x),
+    }
+}
+
+/// `<`
+pub fn lt(a: &CljVal, b: &CljVal) -> CljVal {
+    CljVal::CljBool(match (a, b) {
+        (CljVal::CljInt(a), CljVal::CljInt(b)) => a < b,
+        (CljVal::CljInt(a), CljVal::CljFloat(b)) => (*a as f64) < *b,
+        (CljVal::CljFloat(a), CljVal::CljInt(b)) => *a < *b as f64,
+        (CljVal::CljFloat(a), CljVal::CljFloat(b)) => a < b,
+        _ => panic!("{} cannot be cast to Number", a),
+    })
+}
+
+/// The end of a `case` or `condp` that none of its clauses matched.
+pub fn no_matching_clause(x: &CljVal) -> CljVal {
+    panic!("No matching clause: {}", x)
+}
+
+/// A protocol method called on a value of a type the protocol is not
+/// extended to.
+pub fn no_implementation(method: &str, protocol: &str, x: &CljVal) -> ! {
+    panic!(
+        "No implementation of method: :{} of protocol: #'{} found for class: {}",
+        method,
+        protocol,
+        x.type_name()
+    )
+}
+
+/// The version of the runtime, which is that of the compiler it comes with.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The number at `i` in `s`, and where the next part starts: after the `.`
+/// that ends it, or at the `-` of a pre-release.
+const fn version_part(s: &[u8], mut i: usize) -> (u32, usize) {
+    let mut n = 0;
+    while i < s.len() && s[i].is_ascii_digit() {
+        n = n * 10 + (s[i] - b'0') as u32;
+        i += 1;
+    }
+    if i < s.len() && s[i] == b'.' {
+        i += 1;
+    }
+    (n, i)
+}
+
+/// Whether `a` from `i` is the same as `b` from `j`.
+const fn same_rest(a: &[u8], mut i: usize, b: &[u8], mut j: usize) -> bool {
+    if a.len() - i != b.len() - j {
+        return false;
+    }
+    while i < a.len() {
+        if a[i] != b[j] {
+            return false;
+        }
+        i += 1;
+        j += 1;
+    }
+    true
+}
+
+/// Whether the runtime of version `runtime` runs code compiled by the
+/// compiler of version `compiler`: the same major version, or minor one
+/// before 1.0, or patch one before 0.1, and not older. A pre-release is
+/// older than its release, and runs only the code of the same pre-release.
+pub const fn compatible(compiler: &str, runtime: &str) -> bool {
+    let (c, r) = (compiler.as_bytes(), runtime.as_bytes());
+    let (c_major, i) = version_part(c, 0);
+    let (c_minor, i) = version_part(c, i);
+    let (c_patch, i) = version_part(c, i);
+    let (r_major, j) = version_part(r, 0);
+    let (r_minor, j) = version_part(r, j);
+    let (r_patch, j) = version_part(r, j);
+    if c_major != r_major
+        || (c_major == 0 && c_minor != r_minor)
+        || (c_major == 0 && c_minor == 0 && c_patch != r_patch)
+    {
+        return false;
+    }
+    if r_minor != c_minor || r_patch != c_patch {
+        return r_minor > c_minor || (r_minor == c_minor && r_patch > c_patch);
+    }
+    j == r.len() || same_rest(c, i, r, j)
+}
+
+/// Where `main` puts the value it returns: in the file the environment
+/// variable `CLOJURE_RUST_MAIN_VALUE` names, if it is set, for `clojure-rust
+/// test` to compare it with what the interpreter returns.
+pub fn main_returned(value: &CljVal) {
+    if let Some(path) = std::env::var_os("CLOJURE_RUST_MAIN_VALUE") {
+        let _ = std::fs::write(path, value.to_string());
+    }
+}
+
+/// Called by every crate compiled, with the version of the compiler, so
+/// that building it against a runtime that does not run its code fails.
+pub const fn check_version(compiler: &str) {
+    if !compatible(compiler, VERSION) {
+        panic!("this code was compiled by a version of clojure-rust its clojure-rust-core runtime is not compatible with, recompile it or change the version of the runtime in Cargo.toml");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_before_1_0_are_compatible_only_with_themselves() {
+        assert!(compatible("0.0.1", "0.0.1"));
+        assert!(!compatible("0.0.1", "0.0.2"));
+        assert!(!compatible("0.0.2", "0.0.1"));
+        assert!(compatible("0.1.0", "0.1.3"));
+        assert!(!compatible("0.1.0", "0.2.0"));
+        assert!(!compatible("0.2.0", "0.1.0"));
+    }
+
+    #[test]
+    fn a_runtime_runs_code_of_its_major_version_up_to_its_own() {
+        assert!(compatible("1.2.0", "1.3.0"));
+        assert!(compatible("1.2.5", "1.2.5"));
+        assert!(compatible("1.2.5", "1.3.0"));
+        assert!(!compatible("1.3.0", "1.2.9"));
+        assert!(!compatible("1.2.5", "1.2.4"));
+        assert!(!compatible("1.2.0", "2.2.0"));
+    }
+
+    #[test]
+    fn pre_releases_are_older_than_their_release() {
+        assert!(compatible("1.2.0-alpha", "1.2.0"));
+        assert!(compatible("1.2.0-rc.1", "1.2.0-rc.1"));
+        assert!(!compatible("1.2.0", "1.2.0-alpha"));
+        assert!(!compatible("1.2.0-alpha", "1.2.0-beta"));
+        assert!(compatible("1.2.0-alpha", "1.3.0-beta"));
+        assert!(!compatible("0.0.1-alpha", "0.0.2"));
+    }
+
+    #[test]
+    fn the_runtime_runs_code_of_its_own_compiler() {
+        assert!(compatible(VERSION, VERSION));
+        check_version(VERSION);
+    }
+}
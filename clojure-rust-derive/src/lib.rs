@@ -0,0 +1,159 @@
+//! `#[derive(CljMap)]`, which converts a struct with named fields to and
+//! from a Clojure map of the `clojure-rust-core` runtime, each field under
+//! the keyword of its name, with `-` for `_`:
+//!
+//! ```ignore
+//! #[derive(CljMap)]
+//! struct Invoice {
+//!     customer_id: i64,
+//!     lines: Vec<String>,
+//!     note: Option<String>,
+//! }
+//! ```
+//!
+//! is `{:customer-id 1, :lines ["a"], :note nil}`, with
+//! `From<Invoice> for CljVal` and `TryFrom<CljVal> for Invoice`. A field
+//! that is an `Option` may be missing from the map. The crate using it
+//! depends on `clojure-rust-core`.
+extern crate proc_macro;
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+
+/// A field of the struct, with its type.
+struct Field {
+    name: String,
+    ty: String,
+}
+
+#[proc_macro_derive(CljMap)]
+pub fn derive_clj_map(input: TokenStream) -> TokenStream {
+    let code = match parse_struct(input) {
+        Ok((name, fields)) => conversions(&name, &fields),
+        Err(message) => format!("compile_error!({:?});", message),
+    };
+    code.parse().unwrap()
+}
+
+/// The name and fields of a struct, or why it can not be derived for.
+fn parse_struct(input: TokenStream) -> Result<(String, Vec<Field>), String> {
+    let mut tokens = input.into_iter().peekable();
+    let mut name = None;
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Ident(ident) if ident.to_string() == "struct" => {
+                name = tokens.next().map(|t| t.to_string());
+                break;
+            }
+            TokenTree::Ident(ident)
+                if ident.to_string() == "enum" || ident.to_string() == "union" =>
+            {
+                return Err("CljMap can only be derived for a struct".to_string())
+            }
+            _ => {}
+        }
+    }
+    let name = name.ok_or("CljMap can only be derived for a struct")?;
+    match tokens.next() {
+        Some(TokenTree::Group(body)) if body.delimiter() == Delimiter::Brace => {
+            Ok((name, parse_fields(body.stream())?))
+        }
+        Some(TokenTree::Punct(p)) if p.as_char() == '<' => Err(format!(
+            "CljMap can not be derived for {}, which is generic",
+            name
+        )),
+        _ => Err(format!(
+            "CljMap can only be derived for a struct with named fields, not {}",
+            name
+        )),
+    }
+}
+
+/// The fields in the body of a struct: their attributes and visibility are
+/// skipped, and a type goes on to the comma that is not in its brackets.
+fn parse_fields(body: TokenStream) -> Result<Vec<Field>, String> {
+    let mut fields = Vec::new();
+    let mut tokens = body.into_iter().peekable();
+    while tokens.peek().is_some() {
+        let name = loop {
+            match tokens.next() {
+                // An attribute is `#` and a bracketed group.
+                Some(TokenTree::Punct(p)) if p.as_char() == '#' => {
+                    tokens.next();
+                }
+                Some(TokenTree::Ident(ident)) if ident.to_string() == "pub" => {
+                    if let Some(TokenTree::Group(g)) = tokens.peek() {
+                        if g.delimiter() == Delimiter::Parenthesis {
+                            tokens.next();
+                        }
+                    }
+                }
+                Some(TokenTree::Ident(ident)) => break ident.to_string(),
+                _ => {
+                    return Err(
+                        "CljMap can only be derived for a struct with named fields".to_string()
+                    )
+                }
+            }
+        };
+        match tokens.next() {
+            Some(TokenTree::Punct(p)) if p.as_char() == ':' => {}
+            _ => return Err(format!("expected the type of the field {}", name)),
+        }
+        let mut ty = String::new();
+        let mut depth = 0;
+        for token in tokens.by_ref() {
+            match &token {
+                TokenTree::Punct(p) if p.as_char() == ',' && depth == 0 => break,
+                TokenTree::Punct(p) if p.as_char() == '<' => depth += 1,
+                TokenTree::Punct(p) if p.as_char() == '>' => depth -= 1,
+                _ => {}
+            }
+            ty.push_str(&token.to_string());
+            ty.push(' ');
+        }
+        let name = name.trim_start_matches("r#").to_string();
+        fields.push(Field { name, ty });
+    }
+    Ok(fields)
+}
+
+/// The conversions of the struct `name` to and from a map.
+fn conversions(name: &str, fields: &[Field]) -> String {
+    let mut entries = String::new();
+    let mut reads = String::new();
+    for field in fields {
+        let key = field.name.replace('_', "-");
+        entries.push_str(&format!(
+            "(::clojure_rust_core::CljVal::CljKeyword({:?}.to_string()), ::clojure_rust_core::CljVal::from(x.{})),",
+            key, field.name
+        ));
+        // An optional field is read as its type, unless it is nil.
+        let read = match field.ty.trim().strip_prefix("Option <") {
+            Some(_) => "map_field_opt",
+            None => "map_field",
+        };
+        reads.push_str(&format!(
+            "{}: ::clojure_rust_core::{}(&entries, {:?})?,",
+            field.name, read, key
+        ));
+    }
+    format!(
+        "impl ::std::convert::From<{name}> for ::clojure_rust_core::CljVal {{
+            fn from(x: {name}) -> ::clojure_rust_core::CljVal {{
+                ::clojure_rust_core::CljVal::CljMap(vec![{entries}])
+            }}
+        }}
+        impl ::std::convert::TryFrom<::clojure_rust_core::CljVal> for {name} {{
+            type Error = ::clojure_rust_core::ConversionError;
+            fn try_from(v: ::clojure_rust_core::CljVal) -> ::std::result::Result<{name}, ::clojure_rust_core::ConversionError> {{
+                let entries = match v {{
+                    ::clojure_rust_core::CljVal::CljMap(entries) => entries,
+                    _ => return ::std::result::Result::Err(::clojure_rust_core::ConversionError::new(\"a map\", &v)),
+                }};
+                ::std::result::Result::Ok({name} {{{reads}}})
+            }}
+        }}",
+        name = name,
+        entries = entries,
+        reads = reads
+    )
+}
@@ -0,0 +1,123 @@
+extern crate clojure_rust_core;
+#[macro_use]
+extern crate clojure_rust_derive;
+
+use clojure_rust_core::{CljVal, FromClj, IntoClj};
+use std::convert::TryFrom;
+
+#[derive(CljMap, Debug, PartialEq)]
+struct Address {
+    city: String,
+}
+
+#[derive(CljMap, Debug, PartialEq)]
+struct Invoice {
+    customer_id: i64,
+    pub lines: Vec<String>,
+    note: Option<String>,
+    address: Address,
+}
+
+fn keyword(k: &str) -> CljVal {
+    CljVal::CljKeyword(k.to_string())
+}
+
+/// A struct is a map of its fields, nested structs being maps too.
+#[test]
+fn struct_round_trips_through_a_map() {
+    let invoice = Invoice {
+        customer_id: 7,
+        lines: vec!["tea".to_string()],
+        note: None,
+        address: Address {
+            city: "Lyon".to_string(),
+        },
+    };
+    let value = CljVal::from(invoice);
+    assert_eq!(
+        value.to_string(),
+        "{:customer-id 7, :lines [tea], :note nil, :address {:city Lyon}}"
+    );
+    let back = Invoice::try_from(value).unwrap();
+    assert_eq!(back.customer_id, 7);
+    assert_eq!(back.address.city, "Lyon");
+}
+
+/// An `Option` field can be left out of the map.
+#[test]
+fn missing_optional_field_is_none() {
+    let map = CljVal::CljMap(vec![
+        (keyword("customer-id"), CljVal::CljInt(1)),
+        (keyword("lines"), CljVal::CljVec(vec![])),
+        (
+            keyword("address"),
+            CljVal::CljMap(vec![(
+                keyword("city"),
+                CljVal::CljString("Oslo".to_string()),
+            )]),
+        ),
+    ]);
+    let invoice = Invoice::try_from(map).unwrap();
+    assert_eq!(invoice.note, None);
+    assert!(invoice.lines.is_empty());
+}
+
+/// An error tells the keys of the maps the bad value is in.
+#[test]
+fn bad_field_says_where_it_is() {
+    let map = CljVal::CljMap(vec![
+        (keyword("customer-id"), CljVal::CljInt(1)),
+        (
+            keyword("address"),
+            CljVal::CljMap(vec![(keyword("city"), CljVal::CljInt(3))]),
+        ),
+    ]);
+    let error = Invoice::try_from(map).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "in :address: in :city: expected a string, found 3 (an integer)"
+    );
+    let error = Invoice::try_from(CljVal::CljVec(vec![])).unwrap_err();
+    assert_eq!(error.to_string(), "expected a map, found [] (a vector)");
+}
+
+/// Integers and floats keep the 64 bits of `i64` and `f64`, and do not fit
+/// a narrower type silently.
+#[test]
+fn numbers_convert_without_loss() {
+    let big = CljVal::from(5_000_000_000i64);
+    assert_eq!(i64::try_from(big.clone()), Ok(5_000_000_000));
+    assert_eq!(
+        i32::try_from(big).unwrap_err().to_string(),
+        "expected an integer in the i32 range, found 5000000000 (an integer)"
+    );
+    assert_eq!(f64::try_from(CljVal::from(0.1f64)), Ok(0.1));
+    assert_eq!(u8::try_from(CljVal::from(3.0f64)), Ok(3));
+    assert_eq!(
+        u8::try_from(CljVal::from(-1i64)).unwrap_err().to_string(),
+        "expected an integer in the u8 range, found -1 (an integer)"
+    );
+    assert_eq!(
+        u8::try_from(CljVal::from(2.5f64)).unwrap_err().to_string(),
+        "expected an integer, found 2.5 (a float)"
+    );
+}
+
+/// Rust code called from Clojure takes its arguments by the same rules.
+#[test]
+fn arguments_convert_as_try_from_does() {
+    assert_eq!(i32::from_clj(&CljVal::from(2.0f64)), 2);
+    assert_eq!(
+        Vec::<String>::from_clj(&CljVal::CljNil),
+        Vec::<String>::new()
+    );
+    assert!(String::try_from(keyword("a")).is_err());
+    assert_eq!(3u8.into_clj(), CljVal::from(3i64));
+}
+
+/// `nil` is not `false` to Rust, as it is not to `TryFrom`.
+#[test]
+#[should_panic(expected = "expected a boolean, found nil")]
+fn arguments_that_do_not_convert_panic() {
+    bool::from_clj(&CljVal::CljNil);
+}
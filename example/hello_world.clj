@@ -1,8 +1,8 @@
 (defn f [x]
-  (let [y "world"]
+  (let [y "world!"]
     (if (= y "e")
       (println "error")
-      (println x " " y "!"))))
+      (println x y))))
 
 (defn main []
   (f "hello"))
@@ -1,26 +1,8 @@
-mod cljtype;
-use cljtype::CljVal;
-fn f(x: &CljVal) -> CljVal {
-    {
-        let y = CljVal::CljString("world".to_string());;        if ({ (y == CljVal::CljString("e".to_string())) }) {
-            {
-                println!("{}", CljVal::CljString("error".to_string()),);
-                CljVal::CljNil
-            }
-        } else {
-            {
-                println!(
-                    "{}{}{}{}",
-                    x,
-                    CljVal::CljString(" ".to_string()),
-                    y,
-                    CljVal::CljString("!".to_string()),
-                );
-                CljVal::CljNil
-            }
-        }
-    }
-}
-fn main() {
-    f(&CljVal::CljString("hello".to_string()));
-}
+#![allow(warnings)]
+extern crate clojure_rust_core;
+const _: () = clojure_rust_core::check_version("0.0.1");
+use clojure_rust_core::*;
+pub fn f(x:&CljVal,)->CljVal{{let y = CljVal::CljString("world!".to_string());;if(CljVal::CljBool( y.clone() == CljVal::CljString("e".to_string()) )).truthy(){println!("{}",CljVal::CljString("error".to_string()),);CljVal::CljNil}else{println!("{} {}",x.clone(),y.clone(),);CljVal::CljNil}}}
+pub fn main(){__init();main_returned(&{f(&CljVal::CljString("hello".to_string()),)});}
+/// Runs the top-level forms of this module, once, after those of the modules it requires.
+pub fn __init() {thread_local!(static DONE__: std::cell::Cell<bool> = std::cell::Cell::new(false));if DONE__.with(|done__| done__.replace(true)) {return;}}
@@ -1,12 +1,16 @@
+use diagnostic::Span;
+use interpreter::Function;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt;
-use std::iter::FromIterator;
-use std::slice::Iter;
-#[derive(Debug, Clone)]
+use std::rc::Rc;
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug)]
 pub enum AstVal {
     AstNil,
     AstBool(bool),
-    AstInt(i32),
-    AstFloat(f32),
+    AstInt(i64),
+    AstFloat(f64),
     AstString(String),
     AstSymbol(String),
     AstKeyword(String),
@@ -15,6 +19,44 @@ pub enum AstVal {
     AstMap(Vec<(AstVal, AstVal)>),
     AstMeta(Vec<(AstVal, AstVal)>),
     AstCommentLine(String),
+    /// A function of the interpreter, only there at compile time.
+    AstFn(Rc<Function>),
+}
+
+thread_local! {
+    /// Where the forms read are in their source, by their `address`. A
+    /// clone is where what it is cloned from is, so that what macros and
+    /// the analyzer copy of a form still points at it.
+    static SPANS: RefCell<HashMap<usize, Span>> = RefCell::new(HashMap::new());
+}
+
+/// Where the form at `address` was read, if one was.
+pub fn span_at(address: usize) -> Option<Span> {
+    SPANS.with(|spans| spans.borrow().get(&address).copied())
+}
+
+impl Clone for AstVal {
+    fn clone(&self) -> AstVal {
+        let copy = match self {
+            AstVal::AstNil => AstVal::AstNil,
+            AstVal::AstBool(b) => AstVal::AstBool(*b),
+            AstVal::AstInt(i) => AstVal::AstInt(*i),
+            AstVal::AstFloat(f) => AstVal::AstFloat(*f),
+            AstVal::AstString(s) => AstVal::AstString(s.clone()),
+            AstVal::AstSymbol(s) => AstVal::AstSymbol(s.clone()),
+            AstVal::AstKeyword(s) => AstVal::AstKeyword(s.clone()),
+            AstVal::AstVec(v) => AstVal::AstVec(v.clone()),
+            AstVal::AstList(v) => AstVal::AstList(v.clone()),
+            AstVal::AstMap(v) => AstVal::AstMap(v.clone()),
+            AstVal::AstMeta(v) => AstVal::AstMeta(v.clone()),
+            AstVal::AstCommentLine(s) => AstVal::AstCommentLine(s.clone()),
+            AstVal::AstFn(f) => AstVal::AstFn(f.clone()),
+        };
+        if let Some(span) = self.address().and_then(span_at) {
+            copy.set_span(span);
+        }
+        copy
+    }
 }
 
 impl AstVal {
@@ -30,19 +72,34 @@ impl AstVal {
     pub fn new_meta() -> AstVal {
         AstVal::AstMeta(vec![])
     }
-    pub fn list_type(&self) -> Option<String> {
+    /// The address of the text or items of a form, which is the same for as
+    /// long as it is not cloned, and tells it apart from equal ones.
+    pub fn address(&self) -> Option<usize> {
         match self {
-            AstVal::AstList(v) => match &v[0] {
-                AstVal::AstSymbol(s) => Some(s.clone()),
-                _ => panic!("not callable list"),
-            },
+            AstVal::AstString(s) | AstVal::AstSymbol(s) | AstVal::AstKeyword(s)
+                if !s.is_empty() =>
+            {
+                Some(s.as_ptr() as usize)
+            }
+            AstVal::AstVec(v) | AstVal::AstList(v) if !v.is_empty() => Some(v.as_ptr() as usize),
+            AstVal::AstMap(v) | AstVal::AstMeta(v) if !v.is_empty() => Some(v.as_ptr() as usize),
             _ => None,
         }
     }
-    pub fn map_to_meta(self) -> AstVal {
+    /// Record where the form was read.
+    pub fn set_span(&self, span: Span) {
+        if let Some(address) = self.address() {
+            SPANS.with(|spans| spans.borrow_mut().insert(address, span));
+        }
+    }
+    /// The symbol a list starts with, naming what it calls, if it does.
+    pub fn list_type(&self) -> Option<String> {
         match self {
-            AstVal::AstMap(v) => AstVal::AstMeta(v),
-            _ => panic!("not map"),
+            AstVal::AstList(v) => match v.first() {
+                Some(AstVal::AstSymbol(s)) => Some(s.clone()),
+                _ => None,
+            },
+            _ => None,
         }
     }
     pub fn insert(&mut self, k: AstVal, v: AstVal) {
@@ -61,38 +118,12 @@ impl AstVal {
             _ => panic!("can't push"),
         }
     }
-    pub fn pop(&mut self) -> AstVal {
-        match self {
-            AstVal::AstList(v) => match v.pop() {
-                Some(x) => x,
-                None => AstVal::AstNil,
-            },
-            AstVal::AstVec(v) => match v.pop() {
-                Some(x) => x,
-                None => AstVal::AstNil,
-            },
-
-            _ => panic!("can't pop"),
-        }
-    }
-    fn is_atom(&self) -> bool {
-        match self {
-            AstVal::AstList(_) => false,
-            _ => true,
-        }
-    }
     fn is_nil(&self) -> bool {
         match self {
             AstVal::AstVec(v) => v.is_empty(),
             AstVal::AstNil => true,
             AstVal::AstList(v) => v.is_empty(),
-            AstVal::AstMap(v) | AstVal::AstMeta(v) => {
-                if v.len() == 0 {
-                    true
-                } else {
-                    false
-                }
-            }
+            AstVal::AstMap(v) | AstVal::AstMeta(v) => v.is_empty(),
             AstVal::AstBool(false) => true,
             _ => false,
         }
@@ -104,130 +135,6 @@ impl AstVal {
             _ => panic!("no len method"),
         }
     }
-
-    pub fn is_leaf_list(&self) -> bool {
-        match self {
-            AstVal::AstList(v) => {
-                for i in v {
-                    match i {
-                        AstVal::AstList(v1) => {
-                            if (v1.len() != 0) & (AstVal::AstSymbol(String::from("\'")) == v1[0]) {
-                                continue;
-                            } else {
-                                return false;
-                            }
-                        }
-                        _ => continue,
-                    }
-                }
-                return true;
-            }
-            _ => return false,
-        }
-    }
-
-    pub fn is_leaf(&self) -> bool {
-        match self {
-            AstVal::AstList(_) => {
-                if self.is_leaf_list() {
-                    return true;
-                } else {
-                    return false;
-                }
-            }
-            _ => return true,
-        }
-    }
-    pub fn is_list(&self) -> bool {
-        match self {
-            AstVal::AstList(_) => true,
-            _ => false,
-        }
-    }
-
-    pub fn text(&self) -> String {
-        return format!("{}", self);
-    }
-    pub fn first(&self) -> AstVal {
-        match self {
-            AstVal::AstList(v) => {
-                if v.len() == 0 {
-                    return AstVal::AstNil;
-                } else {
-                    return v[0].clone();
-                }
-            }
-            _ => panic!("no method first"),
-        }
-    }
-    pub fn rest(&self) -> AstVal {
-        match self {
-            AstVal::AstList(v) => {
-                if v.len() > 1 {
-                    return AstVal::AstList(v[1..].to_vec());
-                } else {
-                    return AstVal::AstNil;
-                }
-            }
-            _ => panic!("no method rest"),
-        }
-    }
-    pub fn cons(&self, a: AstVal) -> AstVal {
-        match self {
-            AstVal::AstList(v) => {
-                let mut v = v.clone();
-                let mut va = vec![a];
-                va.append(&mut v);
-                return AstVal::AstList(va);
-            }
-            _ => panic!("no method cons"),
-        }
-    }
-    pub fn cons_mut(&mut self, a: AstVal) {
-        let s = self.clone();
-        match s {
-            AstVal::AstList(mut v) => {
-                let mut va = vec![a];
-                va.append(&mut v);
-                *self = AstVal::AstList(va);
-            }
-            _ => panic!("no method cons_mut"),
-        }
-    }
-    pub fn iter(&self) -> Iter<AstVal> {
-        match self {
-            AstVal::AstList(v) => v.iter(),
-            _ => panic!("no method iter"),
-        }
-    }
-}
-
-impl Iterator for AstVal {
-    type Item = AstVal;
-    fn next(&mut self) -> Option<AstVal> {
-        match self {
-            AstVal::AstList(_) => {
-                let first = self.first();
-                if self.is_nil() {
-                    return None;
-                }
-                let rest = self.rest();
-                *self = rest;
-                return Some(first);
-            }
-            AstVal::AstNil => None,
-            _ => panic!("no method next"),
-        }
-    }
-}
-impl FromIterator<AstVal> for AstVal {
-    fn from_iter<I: IntoIterator<Item = AstVal>>(iter: I) -> Self {
-        let mut l = AstVal::new_list();
-        for i in iter {
-            l.cons_mut(i);
-        }
-        l
-    }
 }
 
 impl PartialEq for AstVal {
@@ -238,9 +145,16 @@ impl PartialEq for AstVal {
             (AstVal::AstList(v), AstVal::AstNil) => v.is_empty(),
             (AstVal::AstString(s1), AstVal::AstString(s2)) => s1 == s2,
             (AstVal::AstSymbol(s1), AstVal::AstSymbol(s2)) => s1 == s2,
+            (AstVal::AstKeyword(s1), AstVal::AstKeyword(s2)) => s1 == s2,
+            (AstVal::AstVec(v1), AstVal::AstVec(v2)) => v1 == v2,
+            (AstVal::AstList(v1), AstVal::AstList(v2)) => v1 == v2,
             (AstVal::AstBool(b1), AstVal::AstBool(b2)) => b1 == b2,
             (AstVal::AstInt(i1), AstVal::AstInt(i2)) => i1 == i2,
             (AstVal::AstFloat(f1), AstVal::AstFloat(f2)) => f1 == f2,
+            (AstVal::AstMap(m1), AstVal::AstMap(m2)) => {
+                m1.len() == m2.len() && m1.iter().all(|item| m2.contains(item))
+            }
+            (AstVal::AstFn(f1), AstVal::AstFn(f2)) => Rc::ptr_eq(f1, f2),
             (AstVal::AstNil, AstVal::AstVec(_)) => other.is_nil(),
             (AstVal::AstVec(_), AstVal::AstNil) => self.is_nil(),
             (AstVal::AstMap(_), AstVal::AstNil) => self.is_nil(),
@@ -260,27 +174,26 @@ impl fmt::Display for AstVal {
             AstVal::AstFloat(s) => write!(f, "{}", s),
             AstVal::AstString(s) => write!(f, "{}", s),
             AstVal::AstSymbol(s) => write!(f, "{}", s),
-            AstVal::AstKeyword(s) => write!(f, "{}", s),
+            AstVal::AstKeyword(s) => write!(f, ":{}", s),
             AstVal::AstCommentLine(s) => write!(f, "{}", s),
-            _ => panic!("can't do that"),
+            AstVal::AstNil => write!(f, "nil"),
+            AstVal::AstList(v) => write!(f, "({})", join(v)),
+            AstVal::AstVec(v) => write!(f, "[{}]", join(v)),
+            AstVal::AstMap(v) | AstVal::AstMeta(v) => {
+                let pairs: Vec<String> = v
+                    .iter()
+                    .map(|(k, item)| format!("{} {}", k, item))
+                    .collect();
+                write!(f, "{{{}}}", pairs.join(", "))
+            }
+            AstVal::AstFn(func) => write!(f, "{:?}", func),
         }
     }
 }
 
-#[derive(Debug)]
-enum AstErr {
-    ErrString(&'static str),
-    ErrAstVal(AstVal),
-}
-
-type AstResult = Result<AstVal, AstErr>;
-
-macro_rules! cljlist {
-    ($($x:expr),*) => {
-        {
-            let mut l: Vec<AstVal> = Vec::new();
-            $( l.push($x); )*
-                AstVal::AstList(l)
-        }}
-    ;
+fn join(v: &[AstVal]) -> String {
+    v.iter()
+        .map(|item| format!("{}", item))
+        .collect::<Vec<_>>()
+        .join(" ")
 }
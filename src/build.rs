@@ -0,0 +1,308 @@
+use ast::AstVal;
+use cli::Emit;
+use diagnostic::{self, Diagnostic};
+use namespace::{compile_tree, find_ns, read_forms, read_source, source_root, SourceFile};
+use project::Project;
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use syntax::{syntax, Globals};
+use translate::{
+    crate_prelude, translate, translate_dispatchers, translate_init, translate_signatures,
+};
+
+/// The source of the runtime crate, which `build_with_rustc` compiles
+/// programs with.
+const RUNTIME: &str = include_str!("../clojure-rust-core/src/lib.rs");
+
+/// The line of a `Cargo.toml` that depends on the runtime crate the compiler
+/// comes with.
+pub fn runtime_dependency() -> String {
+    format!(
+        "clojure-rust-core = {{ version = \"{}\", path = \"{}\" }}\n",
+        env!("CARGO_PKG_VERSION"),
+        concat!(env!("CARGO_MANIFEST_DIR"), "/clojure-rust-core")
+    )
+}
+
+pub fn read_input(input: &str) -> SourceFile {
+    if input == "-" {
+        let mut text = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut text) {
+            Diagnostic::error("E0017", format!("can not read stdin: {}", e)).raise()
+        }
+        read_source("<stdin>", text)
+    } else {
+        read_forms(Path::new(input))
+    }
+}
+
+pub fn write_file(path: &Path, contents: &str) {
+    let written = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => {
+            fs::create_dir_all(dir).and_then(|_| fs::write(path, contents))
+        }
+        _ => fs::write(path, contents),
+    };
+    if let Err(e) = written {
+        Diagnostic::error("E0018", format!("can not write {}: {}", path.display(), e)).raise()
+    }
+}
+
+/// What an input compiles to.
+pub struct Compiled {
+    /// The directory the files go in by default: the root of the source tree
+    /// of a namespace, or the directory of a file without one.
+    pub dir: PathBuf,
+    /// The Rust files, relative to `dir`.
+    pub files: Vec<(PathBuf, String)>,
+    /// Whether it defines a `main` to run, rather than being a library.
+    pub main: bool,
+}
+
+/// The Rust files `input` compiles to, the namespaces it requires being
+/// looked for in the source paths of the project too, and a library if the
+/// project is one. With `--emit=ast` or `--emit=syntax`,
+/// what is emitted is printed instead and there are no files.
+pub fn compile_input(input: &str, emit: Emit, project: &Project) -> Compiled {
+    let source = read_input(input);
+    let nothing = Compiled {
+        dir: PathBuf::new(),
+        files: Vec::new(),
+        main: false,
+    };
+    if emit == Emit::Ast {
+        for form in &source.forms {
+            println!("{:#?}", form);
+        }
+        return nothing;
+    }
+    let dump = |x: &AstVal, globals: &mut Globals| format!("{:#?}", syntax(x, globals));
+    let rust = |x: &AstVal, globals: &mut Globals| translate(&syntax(x, globals));
+    let compile: &dyn Fn(&AstVal, &mut Globals) -> String =
+        if emit == Emit::Syntax { &dump } else { &rust };
+    if let Some(ns) = find_ns(&source.forms) {
+        if input == "-" {
+            Diagnostic::error(
+                "E0014",
+                format!("namespace {} must be compiled from its file", ns.name),
+            )
+            .help("the files of the namespaces it requires are found from where its own file is")
+            .raise()
+        }
+        let path = Path::new(input);
+        let files = compile_tree(
+            path,
+            &ns,
+            &source,
+            &project.source_paths,
+            project.lib,
+            |_, x, globals| {
+                let code = compile(x, globals);
+                if emit == Emit::Syntax {
+                    println!("{}", code);
+                }
+                code
+            },
+        );
+        if emit != Emit::Rust {
+            return nothing;
+        }
+        return Compiled {
+            dir: source_root(path, &ns.name),
+            main: files.iter().any(|(name, _)| name == Path::new("main.rs")),
+            files,
+        };
+    }
+
+    let mut globals = Globals::new();
+    diagnostic::in_source(&source.source, None, || globals.prescan(&source.forms));
+    let code = source.compile_each(|x| compile(x, &mut globals));
+    if emit == Emit::Syntax {
+        for node in code {
+            println!("{}", node);
+        }
+        return nothing;
+    }
+    let main = globals.exports("").defines("main") && !project.lib;
+    let mut rust = crate_prelude(!main);
+    rust.push_str("use clojure_rust_core::*;\n");
+    for t in code {
+        rust.push_str(&t);
+        rust.push('\n');
+    }
+    rust.push_str(&translate_signatures(globals.signatures()));
+    rust.push_str(&translate_dispatchers(
+        globals.protocols(),
+        globals.extensions(),
+    ));
+    if globals.has_init() {
+        rust.push_str(&translate_init(globals.inits(), &[]));
+    }
+    let path = Path::new(input);
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let name = match path.file_stem() {
+        Some(stem) if input != "-" => PathBuf::from(stem).with_extension("rs"),
+        _ => PathBuf::from("main.rs"),
+    };
+    Compiled {
+        dir,
+        files: vec![(name, rust)],
+        main,
+    }
+}
+
+/// A directory as an absolute path, which names it even when it is `.`, or
+/// empty for the current one, or does not exist yet.
+fn absolute(dir: &Path) -> PathBuf {
+    fs::canonicalize(dir)
+        .or_else(|_| env::current_dir().map(|cwd| cwd.join(dir)))
+        .unwrap_or_else(|_| dir.to_path_buf())
+}
+
+/// The Cargo package of the program in `input`: the directory given, or the
+/// one of the project, or the project its source tree is the `src` of, or
+/// else a directory named after it next to it. Writes a `Cargo.toml`
+/// depending on the runtime, generated from the project if it is configured
+/// and otherwise unless there is one, and the crate under `src`, with a
+/// module for each namespace. The directory of the package, and whether it
+/// is a program to run.
+pub fn package(input: &str, out_dir: Option<&str>, project: &Project) -> (PathBuf, bool) {
+    let compiled = compile_input(input, Emit::Rust, project);
+    let source_dir = absolute(&compiled.dir);
+    let dir = match out_dir
+        .map(PathBuf::from)
+        .or_else(|| project.out_dir.clone())
+    {
+        Some(out) => out,
+        None if input == "-" => Diagnostic::error(
+            "E0014",
+            "the package of a program read from stdin needs a directory",
+        )
+        .help("give it one with --out-dir")
+        .raise(),
+        None if source_dir.file_name().is_some_and(|name| name == "src") => source_dir
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default(),
+        None => compiled
+            .dir
+            .join(Path::new(input).file_stem().unwrap_or_default()),
+    };
+    let manifest = dir.join("Cargo.toml");
+    if project.path.is_some() || !manifest.exists() {
+        let name = absolute(&dir)
+            .file_name()
+            .map(|n| {
+                n.to_string_lossy()
+                    .replace(|c: char| !c.is_alphanumeric() && c != '-', "_")
+            })
+            .unwrap_or_else(|| "app".to_string());
+        write_file(&manifest, &project.cargo_toml(&name, &runtime_dependency()));
+    }
+    for (name, code) in &compiled.files {
+        // A file without a namespace is the whole crate.
+        let name = match compiled.files.len() {
+            1 if compiled.main => Path::new("main.rs"),
+            1 => Path::new("lib.rs"),
+            _ => name.as_path(),
+        };
+        write_file(&dir.join("src").join(name), code);
+    }
+    (dir, compiled.main)
+}
+
+/// A cargo `subcommand` on the package in `dir`, offline as everything it
+/// needs is local.
+pub fn cargo(dir: &Path, subcommand: &str) -> process::Command {
+    let mut command = process::Command::new("cargo");
+    command
+        .args([subcommand, "--offline", "--manifest-path"])
+        .arg(dir.join("Cargo.toml"));
+    command
+}
+
+/// Build the package in `dir` with cargo.
+pub fn cargo_build(dir: &Path, quiet: bool) {
+    let mut command = cargo(dir, "build");
+    if quiet {
+        command.arg("--quiet");
+    }
+    match command.status() {
+        Ok(status) if status.success() => {}
+        Ok(_) => Diagnostic::error(
+            "E0020",
+            format!("could not build the package in {}", dir.display()),
+        )
+        .raise(),
+        Err(e) => Diagnostic::error("E0020", format!("could not run cargo: {}", e))
+            .help("install Rust, and make sure cargo is on the PATH")
+            .raise(),
+    }
+}
+
+/// Compile the program in `input` into the directory `dir`, with the runtime,
+/// and build it with rustc alone, which is quicker than a package for a
+/// program run once. The path of the executable.
+pub fn build_with_rustc(input: &str, dir: &Path) -> PathBuf {
+    let Compiled { files, main, .. } = compile_input(input, Emit::Rust, &Project::default());
+    if !main {
+        no_main(input)
+    }
+    for (name, code) in &files {
+        // A file without a namespace is the whole program.
+        let name = if files.len() == 1 {
+            Path::new("main.rs")
+        } else {
+            name.as_path()
+        };
+        write_file(&dir.join(name), code);
+    }
+    write_file(&dir.join("clojure_rust_core.rs"), RUNTIME);
+    let runtime = dir.join("libclojure_rust_core.rlib");
+    rustc(
+        process::Command::new("rustc")
+            .args([
+                "--crate-type",
+                "rlib",
+                "--crate-name",
+                "clojure_rust_core",
+                "-o",
+            ])
+            .arg(&runtime)
+            .arg(dir.join("clojure_rust_core.rs"))
+            // What Cargo would set, for the version of the runtime.
+            .env("CARGO_PKG_VERSION", env!("CARGO_PKG_VERSION")),
+        "the clojure-rust-core runtime",
+    );
+    let exe = dir.join("main");
+    rustc(
+        process::Command::new("rustc")
+            .arg("--extern")
+            .arg(format!("clojure_rust_core={}", runtime.display()))
+            .arg("-o")
+            .arg(&exe)
+            .arg(dir.join("main.rs")),
+        "the Rust compiled from the program",
+    );
+    exe
+}
+
+pub fn no_main(input: &str) -> ! {
+    Diagnostic::error("E0019", format!("{} has no main function to run", input))
+        .help("define (defn main [] ...) in the namespace to run")
+        .raise()
+}
+
+/// Run rustc quietly to build `what`.
+fn rustc(command: &mut process::Command, what: &str) {
+    match command.args(["-A", "warnings"]).status() {
+        Ok(status) if status.success() => {}
+        Ok(_) => Diagnostic::error("E0020", format!("could not build {}", what)).raise(),
+        Err(e) => Diagnostic::error("E0020", format!("could not run rustc: {}", e))
+            .help("install Rust, and make sure rustc is on the PATH")
+            .raise(),
+    }
+}
@@ -0,0 +1,238 @@
+/// What the compiler does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Compile,
+    Build,
+    Run,
+    Check,
+    Repl,
+    Fmt,
+    New,
+    Test,
+}
+
+/// What `compile` makes of its inputs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    /// The forms read, printed.
+    Ast,
+    /// The syntax tree of each form, printed.
+    Syntax,
+    /// Rust source files.
+    Rust,
+}
+
+/// The arguments of a command.
+#[derive(Debug)]
+pub struct Options {
+    pub command: Command,
+    /// Source files, `-` being stdin, or for `new`, the project directory.
+    pub inputs: Vec<String>,
+    /// Where to write what is compiled, `-` being stdout; next to the
+    /// sources if not given.
+    pub out_dir: Option<String>,
+    pub emit: Emit,
+    pub json: bool,
+    /// For `fmt`, only report the files that are not formatted.
+    pub check: bool,
+    /// Compile to a library crate, even a program with a `main`.
+    pub lib: bool,
+    /// The arguments after `--`, for the program `run` runs.
+    pub program_args: Vec<String>,
+}
+
+pub enum Parsed {
+    Options(Options),
+    Help(Option<Command>),
+    Version,
+}
+
+pub const USAGE: &str = "Usage: clojure-rust [COMMAND] [OPTIONS] [FILES]...
+
+Commands:
+  compile  Compile Clojure files to Rust (the default)
+  build    Generate the Cargo package of a program and build it
+  run      Generate the Cargo package of a program, build it and run it
+  check    Report the errors of files without writing anything
+  repl     Evaluate forms interactively
+  fmt      Indent Clojure files the usual way
+  new      Create a new project
+  test     Check that programs do the same interpreted and compiled
+
+Options:
+  -o, --out-dir <DIR>        Write the Rust files into DIR, `-` for stdout
+      --emit <ast|syntax|rust>
+                             What to compile to, ast and syntax are printed
+      --error-format <human|json>
+                             How to report errors and warnings
+      --lib                  Make a library crate, even of a program with main
+  -h, --help                 Print help, of a command if given one
+  -V, --version              Print version
+
+A FILE of `-` is read from stdin and compiled to stdout. Without FILES,
+compile, check, build and run take the :main namespace of the project.edn
+in the current directory, which also gives the source paths, the package
+and its dependencies, and compiler options.
+Exits with 0 on success, 1 when there are errors, and 2 on bad usage.";
+
+/// The help of a command.
+pub fn help(command: Option<Command>) -> &'static str {
+    match command {
+        None | Some(Command::Compile) => USAGE,
+        Some(Command::Build) => {
+            "Usage: clojure-rust build [OPTIONS] [FILE]
+
+Compile FILE, or the namespace in it with everything it requires, into a
+Cargo package and build it with cargo, offline. The package is the one
+given with --out-dir or the :out-dir of the project.edn, the project whose
+src FILE is under, or else a directory named after FILE next to it. It
+gets a Cargo.toml depending on the runtime, generated from the project.edn
+or written unless there is one, and src/main.rs if FILE defines main, or
+else src/lib.rs, with a module for each namespace. FILE is the :main of
+the project.edn if not given.
+
+With --lib, or :crate-type \"lib\" in the project.edn, the package is a
+library even if FILE defines main. Public functions are pub fns taking and
+returning values, documented with their docstrings, and those with a
+^{:rust/sig \"fn(i64, &str) -> String\"} also get a wrapper of that type in
+the typed module next to them."
+        }
+        Some(Command::Run) => {
+            "Usage: clojure-rust run [OPTIONS] [FILE] [-- ARGS...]
+
+Generate and build the Cargo package of FILE as build does, and run it
+with ARGS. Exits with the exit code of the program."
+        }
+        Some(Command::Check) => {
+            "Usage: clojure-rust check [OPTIONS] <FILES>...
+
+Compile FILES without writing anything, to report their errors."
+        }
+        Some(Command::Repl) => {
+            "Usage: clojure-rust repl [OPTIONS]
+
+Read forms from stdin and evaluate them, keeping what they define. *1, *2
+and *3 are the last values, *e the last error; (doc name) shows what name
+is, and (require '[my.util :as u]) loads namespaces from src."
+        }
+        Some(Command::Fmt) => {
+            "Usage: clojure-rust fmt [--check] <FILES>...
+
+Indent FILES in place, or with --check, list the files that are not
+indented and exit with 1 if there are any. `-` formats stdin to stdout."
+        }
+        Some(Command::New) => {
+            "Usage: clojure-rust new <DIR>
+
+Create a project in DIR with a hello world in src/main.clj and a
+project.edn, and its Cargo package, to cargo run as it is."
+        }
+        Some(Command::Test) => {
+            "Usage: clojure-rust test [OPTIONS] [PATHS]...
+
+Run the `main` of every .clj file in PATHS, example and tests by default,
+both with the interpreter and compiled with rustc, and fail on the programs
+whose output or error differs between the two."
+        }
+    }
+}
+
+fn command(name: &str) -> Option<Command> {
+    match name {
+        "compile" => Some(Command::Compile),
+        "build" => Some(Command::Build),
+        "run" => Some(Command::Run),
+        "check" => Some(Command::Check),
+        "repl" => Some(Command::Repl),
+        "fmt" => Some(Command::Fmt),
+        "new" => Some(Command::New),
+        "test" => Some(Command::Test),
+        _ => None,
+    }
+}
+
+/// The command line, without the name of the program. An error is what to
+/// tell about a bad usage.
+pub fn parse(args: &[String]) -> Result<Parsed, String> {
+    let mut args = args.iter();
+    let mut options = Options {
+        command: Command::Compile,
+        inputs: Vec::new(),
+        out_dir: None,
+        emit: Emit::Rust,
+        json: false,
+        check: false,
+        lib: false,
+        program_args: Vec::new(),
+    };
+    let mut named = None;
+    let mut help_wanted = false;
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (&arg[..i], Some(arg[i + 1..].to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = |name: &str| match inline.clone().or_else(|| args.next().cloned()) {
+            Some(v) => Ok(v),
+            None => Err(format!("{} needs a value", name)),
+        };
+        match flag {
+            "-h" | "--help" => help_wanted = true,
+            "-V" | "--version" => return Ok(Parsed::Version),
+            "-o" | "--out-dir" => options.out_dir = Some(value(flag)?),
+            "--emit" => {
+                options.emit = match value(flag)?.as_str() {
+                    "ast" => Emit::Ast,
+                    "syntax" => Emit::Syntax,
+                    "rust" => Emit::Rust,
+                    other => {
+                        return Err(format!(
+                            "unknown --emit kind: {}, expected ast, syntax or rust",
+                            other
+                        ))
+                    }
+                }
+            }
+            "--error-format" => {
+                options.json = match value(flag)?.as_str() {
+                    "json" => true,
+                    "human" => false,
+                    other => {
+                        return Err(format!(
+                            "unknown --error-format: {}, expected human or json",
+                            other
+                        ))
+                    }
+                }
+            }
+            "--check" => options.check = true,
+            "--lib" => options.lib = true,
+            "--" => {
+                options.program_args = args.cloned().collect();
+                break;
+            }
+            "-" => options.inputs.push(arg.clone()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+            _ => match command(arg) {
+                Some(c) if named.is_none() && options.inputs.is_empty() => named = Some(c),
+                _ => options.inputs.push(arg.clone()),
+            },
+        }
+    }
+    if let Some(c) = named {
+        options.command = c;
+    }
+    if help_wanted {
+        return Ok(Parsed::Help(named));
+    }
+    match options.command {
+        Command::Repl if !options.inputs.is_empty() => Err("repl takes no files".to_string()),
+        Command::Run if options.inputs.len() > 1 => Err("run takes one file".to_string()),
+        Command::Build if options.inputs.len() > 1 => Err("build takes one file".to_string()),
+        Command::New if options.inputs.len() != 1 => {
+            Err("new takes exactly one directory".to_string())
+        }
+        Command::Fmt if options.inputs.is_empty() => Err("no input files".to_string()),
+        _ => Ok(Parsed::Options(options)),
+    }
+}
@@ -0,0 +1,511 @@
+use ast::{self, AstVal};
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+/// How bad a diagnostic is: errors stop the compilation, warnings do not.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Where something is in a source, in chars from its start.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A source file the compiler reads, kept for diagnostics to show lines of.
+#[derive(Debug)]
+pub struct Source {
+    pub path: String,
+    pub text: String,
+}
+
+impl Source {
+    /// The line and column, both from 1, of a char offset.
+    fn line_col(&self, offset: usize) -> (usize, usize) {
+        let mut line = 1;
+        let mut col = 1;
+        for c in self.text.chars().take(offset) {
+            if c == '\n' {
+                line += 1;
+                col = 1;
+            } else {
+                col += 1;
+            }
+        }
+        (line, col)
+    }
+    fn line(&self, line: usize) -> &str {
+        self.text.lines().nth(line - 1).unwrap_or("")
+    }
+}
+
+/// A span of the source a diagnostic points at, with what to say there. The
+/// primary label is where the problem is, secondary ones give context.
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    pub primary: bool,
+}
+
+/// An error or warning of the compiler. Errors are raised by panicking with
+/// one, which `run` catches and reports.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Stable across versions, so that tools can match on it.
+    pub code: &'static str,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub help: Option<String>,
+    /// The text of what the diagnostic is about, with its label and the
+    /// address it had, to be looked up in the form being compiled when it
+    /// gets a location.
+    culprit: Option<(String, String, usize)>,
+    /// Where the innermost form being analyzed when it was made was read.
+    subform: Option<Span>,
+    source: Option<Arc<Source>>,
+}
+
+/// The code of the panics that are not diagnostics.
+const MALFORMED: &str = "E0000";
+
+thread_local! {
+    /// The warnings of the form being compiled, until it gets a location.
+    static WARNINGS: RefCell<Vec<Diagnostic>> = const { RefCell::new(Vec::new()) };
+    static JSON: Cell<bool> = const { Cell::new(false) };
+    static WARNINGS_AS_ERRORS: Cell<bool> = const { Cell::new(false) };
+    /// The forms being analyzed, innermost last, by their `AstVal::address`.
+    static FORMS: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Takes the forms `within` pushed off again, even when unwinding.
+struct Within(usize);
+
+impl Drop for Within {
+    fn drop(&mut self) {
+        FORMS.with(|forms| forms.borrow_mut().truncate(self.0));
+    }
+}
+
+/// Analyze `form` with `f`, so that the diagnostics made meanwhile without
+/// a culprit point at it, or at what in it is being analyzed.
+pub fn within<T, F: FnOnce() -> T>(form: &AstVal, f: F) -> T {
+    let _within = FORMS.with(|forms| {
+        let mut forms = forms.borrow_mut();
+        let depth = forms.len();
+        forms.extend(form.address());
+        Within(depth)
+    });
+    f()
+}
+
+impl Diagnostic {
+    pub fn error<S: Into<String>>(code: &'static str, message: S) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            labels: Vec::new(),
+            help: None,
+            culprit: None,
+            subform: FORMS.with(|forms| {
+                forms
+                    .borrow()
+                    .iter()
+                    .rev()
+                    .find_map(|address| ast::span_at(*address))
+            }),
+            source: None,
+        }
+    }
+    pub fn warning<S: Into<String>>(code: &'static str, message: S) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(code, message)
+        }
+    }
+    /// Point at `text` in the form being compiled: where it was read, when
+    /// it is the text of a symbol read, or else where it first is.
+    pub fn culprit(mut self, text: &str, label: &str) -> Diagnostic {
+        self.culprit = Some((text.to_string(), label.to_string(), text.as_ptr() as usize));
+        self
+    }
+    /// Point at `span` of the source, as the reader does.
+    pub fn at(mut self, span: Span, label: &str) -> Diagnostic {
+        self.labels.push(Label {
+            span,
+            message: label.to_string(),
+            primary: true,
+        });
+        self
+    }
+    pub fn help<S: Into<String>>(mut self, help: S) -> Diagnostic {
+        self.help = Some(help.into());
+        self
+    }
+    pub fn raise(self) -> ! {
+        panic::panic_any(self)
+    }
+    /// Report a warning once the form being compiled is done, or raise it
+    /// as an error when warnings are errors.
+    pub fn emit(mut self) {
+        if WARNINGS_AS_ERRORS.with(Cell::get) {
+            self.severity = Severity::Error;
+            if self.help.is_none() {
+                self.help = Some("warnings are errors in this project".to_string());
+            }
+            self.raise()
+        }
+        WARNINGS.with(|w| w.borrow_mut().push(self));
+    }
+
+    /// Give the diagnostic the source it is in, and labels in the form at
+    /// `form`, unless it already has them.
+    fn locate(&mut self, source: &Arc<Source>, form: Option<Span>) {
+        if self.source.is_some() {
+            return;
+        }
+        self.source = Some(source.clone());
+        let form = match form {
+            Some(form) if self.labels.is_empty() => form,
+            _ => return,
+        };
+        let inside = |span: &Span| form.start <= span.start && span.end <= form.end;
+        let found = match &self.culprit {
+            Some((text, label, address)) => read_at(source, form, text, *address)
+                .or_else(|| find_token(source, form, text))
+                .map(|span| (span, label.clone())),
+            None => self
+                .subform
+                .filter(|span| inside(span) && *span != form)
+                .map(|span| (span, String::new())),
+        };
+        match found {
+            Some((span, label)) => {
+                self.labels.push(Label {
+                    span,
+                    message: label,
+                    primary: true,
+                });
+                if span.start != form.start {
+                    self.labels.push(Label {
+                        span: Span {
+                            start: form.start,
+                            end: form.start + 1,
+                        },
+                        message: "in this form".to_string(),
+                        primary: false,
+                    });
+                }
+            }
+            None => self.labels.push(Label {
+                span: form,
+                message: String::new(),
+                primary: true,
+            }),
+        }
+    }
+
+    fn severity_name(&self) -> &'static str {
+        match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    /// The diagnostic the way rustc shows them, with the lines it points at
+    /// underlined.
+    pub fn render(&self) -> String {
+        let mut s = format!(
+            "{}[{}]: {}\n",
+            self.severity_name(),
+            self.code,
+            self.message
+        );
+        let source = match &self.source {
+            Some(source) => source,
+            None => {
+                if let Some(help) = &self.help {
+                    s.push_str(&format!("  = help: {}\n", help));
+                }
+                return s;
+            }
+        };
+        let mut labels = self.labels.clone();
+        labels.sort_by_key(|l| l.span.start);
+        let lines: Vec<(usize, usize)> = labels
+            .iter()
+            .map(|l| source.line_col(l.span.start))
+            .collect();
+        let width = lines
+            .iter()
+            .map(|(line, _)| line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let pad = " ".repeat(width);
+        match labels.iter().position(|l| l.primary) {
+            Some(i) => s.push_str(&format!(
+                "{}--> {}:{}:{}\n",
+                pad, source.path, lines[i].0, lines[i].1
+            )),
+            None => s.push_str(&format!("{}--> {}\n", pad, source.path)),
+        }
+        s.push_str(&format!("{} |\n", pad));
+        let mut shown = 0;
+        for (label, (line, col)) in labels.iter().zip(lines) {
+            let text = source.line(line);
+            if line != shown {
+                s.push_str(&format!("{:>w$} | {}\n", line, text, w = width));
+                shown = line;
+            }
+            let len = (label.span.end - label.span.start)
+                .clamp(1, text.chars().count().saturating_sub(col - 1).max(1));
+            let marker = if label.primary { "^" } else { "-" };
+            let underline = format!(
+                "{} | {}{} {}",
+                pad,
+                " ".repeat(col - 1),
+                marker.repeat(len),
+                label.message
+            );
+            s.push_str(underline.trim_end());
+            s.push('\n');
+        }
+        if let Some(help) = &self.help {
+            s.push_str(&format!("{} |\n{} = help: {}\n", pad, pad, help));
+        }
+        s
+    }
+
+    /// One line of JSON, for editors and CI.
+    pub fn to_json(&self) -> String {
+        let mut spans = Vec::new();
+        if let Some(source) = &self.source {
+            for label in &self.labels {
+                let (line_start, column_start) = source.line_col(label.span.start);
+                let (line_end, column_end) = source.line_col(label.span.end);
+                spans.push(format!(
+                    "{{\"file\":{},\"start\":{},\"end\":{},\"line_start\":{},\"column_start\":{},\"line_end\":{},\"column_end\":{},\"primary\":{},\"label\":{}}}",
+                    json_string(&source.path),
+                    label.span.start,
+                    label.span.end,
+                    line_start,
+                    column_start,
+                    line_end,
+                    column_end,
+                    label.primary,
+                    json_string(&label.message)
+                ));
+            }
+        }
+        format!(
+            "{{\"severity\":{},\"code\":{},\"message\":{},\"file\":{},\"spans\":[{}],\"help\":{},\"rendered\":{}}}",
+            json_string(self.severity_name()),
+            json_string(self.code),
+            json_string(&self.message),
+            self.source.as_ref().map_or("null".to_string(), |s| json_string(&s.path)),
+            spans.join(","),
+            self.help.as_ref().map_or("null".to_string(), |h| json_string(h)),
+            json_string(&self.render())
+        )
+    }
+
+    pub fn report(&self) {
+        if JSON.with(|j| j.get()) {
+            eprintln!("{}", self.to_json());
+        } else {
+            eprintln!("{}", self.render());
+        }
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Where the reader read `token`, at `address`, in the form at `form`. The
+/// text there is checked, as the address may be that of a form gone since.
+fn read_at(source: &Source, form: Span, token: &str, address: usize) -> Option<Span> {
+    let span = ast::span_at(address)?;
+    let inside = form.start <= span.start && span.end <= form.end;
+    let text = source
+        .text
+        .chars()
+        .skip(span.start)
+        .take(span.end - span.start);
+    Some(span).filter(|_| inside && text.eq(token.chars()))
+}
+
+/// The first occurrence of `token` in the form at `form` that is a whole
+/// token, not a part of a longer symbol.
+fn find_token(source: &Source, form: Span, token: &str) -> Option<Span> {
+    let chars: Vec<char> = source
+        .text
+        .chars()
+        .skip(form.start)
+        .take(form.end - form.start)
+        .collect();
+    let token: Vec<char> = token.chars().collect();
+    let boundary = |c: Option<&char>| match c {
+        None => true,
+        Some(c) => c.is_whitespace() || "()[]{}\"';@^`~,".contains(*c),
+    };
+    (0..chars.len()).find_map(|i| {
+        let end = i + token.len();
+        let fits = end <= chars.len() && chars[i..end] == token[..];
+        let alone =
+            boundary(i.checked_sub(1).and_then(|j| chars.get(j))) && boundary(chars.get(end));
+        if fits && alone {
+            Some(Span {
+                start: form.start + i,
+                end: form.start + end,
+            })
+        } else {
+            None
+        }
+    })
+}
+
+/// Turn what a panic was raised with into a diagnostic.
+fn from_panic(payload: Box<dyn std::any::Any + Send>) -> Diagnostic {
+    match payload.downcast::<Diagnostic>() {
+        Ok(d) => *d,
+        Err(payload) => {
+            let message = match payload.downcast::<String>() {
+                Ok(s) => *s,
+                Err(payload) => match payload.downcast::<&str>() {
+                    Ok(s) => s.to_string(),
+                    Err(_) => "compiler error".to_string(),
+                },
+            };
+            Diagnostic::error(MALFORMED, message)
+        }
+    }
+}
+
+/// Compile something of `source`, the form at `form` if given, so that the
+/// diagnostics raised or emitted meanwhile point into it.
+pub fn in_source<T, F: FnOnce() -> T>(source: &Arc<Source>, form: Option<Span>, f: F) -> T {
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    let warnings = WARNINGS.with(|w| w.replace(Vec::new()));
+    for mut w in warnings {
+        w.locate(source, form);
+        w.report();
+    }
+    match result {
+        Ok(value) => value,
+        Err(payload) => {
+            let mut d = from_panic(payload);
+            d.locate(source, form);
+            panic::resume_unwind(Box::new(d))
+        }
+    }
+}
+
+/// Report diagnostics as JSON lines from now on, or as text.
+pub fn set_json(json: bool) {
+    JSON.with(|j| j.set(json));
+}
+
+/// Raise the warnings emitted from now on as errors, or not.
+pub fn set_warnings_as_errors(deny: bool) {
+    WARNINGS_AS_ERRORS.with(|w| w.set(deny));
+}
+
+/// Run `f`, giving back the diagnostic it raised instead of reporting it.
+pub fn catch<T, F: FnOnce() -> T>(f: F) -> Result<T, Box<Diagnostic>> {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    panic::set_hook(hook);
+    result.map_err(|payload| Box::new(from_panic(payload)))
+}
+
+/// Run the compiler, reporting its diagnostics as text or, with `json`, as
+/// JSON lines on stderr. Whether it succeeded.
+pub fn run<F: FnOnce()>(json: bool, f: F) -> bool {
+    set_json(json);
+    let result = catch(f);
+    for w in WARNINGS.with(|w| w.replace(Vec::new())) {
+        w.report();
+    }
+    match result {
+        Ok(()) => true,
+        Err(d) => {
+            d.report();
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `d` raised while compiling the form at `form` of `text`.
+    fn located(d: Diagnostic, text: &str, form: Span) -> Diagnostic {
+        let source = Arc::new(Source {
+            path: "a.clj".to_string(),
+            text: text.to_string(),
+        });
+        *catch(|| in_source(&source, Some(form), || d.raise())).unwrap_err()
+    }
+
+    /// The culprit is the whole token, not the start of `bar-baz`.
+    #[test]
+    fn renders_like_rustc() {
+        let text = "(def a 1)\n(foo bar-baz bar)\n";
+        let d = Diagnostic::error("E0001", "Unable to resolve symbol: bar")
+            .culprit("bar", "not found")
+            .help("def it first");
+        assert_eq!(
+            located(d, text, Span { start: 10, end: 27 }).render(),
+            "error[E0001]: Unable to resolve symbol: bar\n \
+             --> a.clj:2:14\n  \
+             |\n\
+             2 | (foo bar-baz bar)\n  \
+             | - in this form\n  \
+             |              ^^^ not found\n  \
+             |\n  \
+             = help: def it first\n"
+        );
+        let d = Diagnostic::warning("E0024", "unused").help("a \"quoted\" help");
+        assert_eq!(
+            d.render(),
+            "warning[E0024]: unused\n  = help: a \"quoted\" help\n"
+        );
+    }
+
+    #[test]
+    fn json_has_spans_and_escapes_strings() {
+        let d = Diagnostic::error("E0002", "bad \"form\"\n");
+        let json = located(d, "x\n(quote)", Span { start: 2, end: 9 }).to_json();
+        let expected = concat!(
+            r#"{"severity":"error","code":"E0002","message":"bad \"form\"\n","file":"a.clj","#,
+            r#""spans":[{"file":"a.clj","start":2,"end":9,"line_start":2,"column_start":1,"#,
+            r#""line_end":2,"column_end":8,"primary":true,"label":""}],"#,
+            r#""help":null,"rendered":"error[E0002]"#
+        );
+        assert!(json.starts_with(expected), "{}", json);
+        assert!(!json.contains('\n'));
+    }
+}
@@ -0,0 +1,609 @@
+use ast::AstVal;
+use clojure_rust_core::*;
+use diagnostic::Diagnostic;
+use mangle::{demangle, demangle_path, mangle};
+use namespace::module_path;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use syntax::{Arity, Catch, Catches, Extension, Field, Protocol, RefKind, RustCallee, SyntaxNode};
+
+/// The functions and vars defined so far, by their path in Rust, shared by
+/// the functions defined, which look their globals up in there when called.
+type Defs = Rc<RefCell<HashMap<String, CljVal>>>;
+
+/// The methods of protocols implemented for types, by the qualified name of
+/// the protocol and the name the runtime gives the type.
+type Impls = Rc<RefCell<HashMap<(String, String), HashMap<String, CljVal>>>>;
+
+/// Locals by their name in Rust, innermost last.
+type Env = Vec<(String, CljVal)>;
+
+/// What evaluating a node in tail position comes to: a value, or the values
+/// a `recur` goes back to its loop or function with.
+enum Step {
+    Value(CljVal),
+    Recur(Vec<CljVal>),
+}
+
+/// Runs analyzed forms the way the Rust `translate` makes of them would,
+/// on the values of the `clojure-rust-core` runtime.
+#[derive(Clone)]
+pub struct Evaluator {
+    defs: Defs,
+    impls: Impls,
+    /// The path of the module of the code evaluated, empty for the REPL,
+    /// which the paths of its own definitions are relative to.
+    module: String,
+    /// Where `println` writes to instead of stdout, if anywhere.
+    out: Option<Rc<RefCell<String>>>,
+}
+
+impl Evaluator {
+    pub fn new() -> Evaluator {
+        Evaluator {
+            defs: Rc::new(RefCell::new(HashMap::new())),
+            impls: Rc::new(RefCell::new(HashMap::new())),
+            module: String::new(),
+            out: None,
+        }
+    }
+    /// This evaluator, with what the code prints appended to `out`.
+    pub fn capturing(mut self, out: Rc<RefCell<String>>) -> Evaluator {
+        self.out = Some(out);
+        self
+    }
+    /// The evaluator of the code of the namespace `ns`, sharing definitions
+    /// with this one.
+    pub fn in_ns(&self, ns: &str) -> Evaluator {
+        Evaluator {
+            defs: self.defs.clone(),
+            impls: self.impls.clone(),
+            module: format!("crate::{}", module_path(ns)),
+            out: self.out.clone(),
+        }
+    }
+    /// Set a var from outside the code, by its name in Rust.
+    pub fn set(&self, name: &str, value: CljVal) {
+        self.defs.borrow_mut().insert(self.path(name), value);
+    }
+    pub fn get(&self, name: &str) -> CljVal {
+        self.global(name).unwrap_or(CljVal::CljNil)
+    }
+    /// Evaluate a top-level form.
+    pub fn eval(&self, n: &SyntaxNode) -> CljVal {
+        self.value(n, &mut Vec::new())
+    }
+
+    /// The full path of a definition, as the code of the module names it.
+    fn path(&self, name: &str) -> String {
+        if name.starts_with("crate::") || self.module.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}::{}", self.module, name)
+        }
+    }
+    fn global(&self, name: &str) -> Option<CljVal> {
+        self.defs.borrow().get(&self.path(name)).cloned()
+    }
+
+    fn value(&self, n: &SyntaxNode, env: &mut Env) -> CljVal {
+        match self.step(n, env) {
+            Step::Value(v) => v,
+            Step::Recur(_) => unreachable!(),
+        }
+    }
+    fn values(&self, items: &[SyntaxNode], env: &mut Env) -> Vec<CljVal> {
+        items.iter().map(|item| self.value(item, env)).collect()
+    }
+    /// A `try` the way `try_catch` runs the closures it compiles to. The
+    /// locals bound when something is thrown are dropped.
+    fn try_catch(
+        &self,
+        body: &[SyntaxNode],
+        catches: &[Catch],
+        finally: &[SyntaxNode],
+        env: &mut Env,
+    ) -> CljVal {
+        let depth = env.len();
+        let mut outcome = attempt(|| self.do_value(body, env));
+        env.truncate(depth);
+        if let Err(err) = outcome {
+            outcome = attempt(|| {
+                let e = err.exception();
+                for catch in catches {
+                    let caught = match &catch.catches {
+                        Catches::Class(class) => clojure_rust_core::catches(class, &e),
+                        Catches::Data(data) => catches_data(&self.value(data, env), &e),
+                    };
+                    if caught {
+                        env.push((catch.binding.clone(), e));
+                        return self.do_value(&catch.body, env);
+                    }
+                }
+                err.raise()
+            });
+            env.truncate(depth);
+        }
+        self.do_value(finally, env);
+        outcome.unwrap_or_else(|err| err.raise())
+    }
+    fn do_value(&self, body: &[SyntaxNode], env: &mut Env) -> CljVal {
+        match self.body(body, env) {
+            Step::Value(v) => v,
+            Step::Recur(_) => unreachable!(),
+        }
+    }
+    /// The forms of a body in turn, the last one in tail position.
+    fn body(&self, body: &[SyntaxNode], env: &mut Env) -> Step {
+        match body.split_last() {
+            Some((last, init)) => {
+                for item in init {
+                    self.value(item, env);
+                }
+                self.step(last, env)
+            }
+            None => Step::Value(CljVal::CljNil),
+        }
+    }
+
+    fn step(&self, n: &SyntaxNode, env: &mut Env) -> Step {
+        let value = match n {
+            SyntaxNode::Fn { name, arities, .. } => {
+                self.defn(name, arities);
+                CljVal::CljNil
+            }
+            SyntaxNode::Closure {
+                name,
+                captures,
+                arities,
+            } => {
+                let captured = captures
+                    .iter()
+                    .map(|c| (c.clone(), local(env, c)))
+                    .collect();
+                let display = name.as_deref().map_or("fn".to_string(), demangle);
+                self.function(display, name.clone(), arities.clone(), captured)
+            }
+            SyntaxNode::Def { name, value, .. } => {
+                let value = self.value(value, env);
+                self.defs.borrow_mut().insert(self.path(name), value);
+                CljVal::CljNil
+            }
+            SyntaxNode::Nothing => CljVal::CljNil,
+            SyntaxNode::Init { body, .. } => return self.step(body, env),
+            SyntaxNode::Try {
+                body,
+                catches,
+                finally,
+            } => self.try_catch(body, catches, finally, env),
+            SyntaxNode::Do(body) => return self.body(body, env),
+            SyntaxNode::Let { bindings, body } => {
+                let depth = env.len();
+                for (name, value) in bindings {
+                    let value = self.value(value, env);
+                    env.push((name.clone(), value));
+                }
+                let step = self.body(body, env);
+                env.truncate(depth);
+                return step;
+            }
+            SyntaxNode::If {
+                test,
+                then,
+                otherwise,
+            } => {
+                return if self.value(test, env).truthy() {
+                    self.step(then, env)
+                } else {
+                    self.step(otherwise, env)
+                };
+            }
+            SyntaxNode::Loop { bindings, body } => {
+                let depth = env.len();
+                for (name, value) in bindings {
+                    let value = self.value(value, env);
+                    env.push((name.clone(), value));
+                }
+                let value = self.recur_loop(body, env, depth, bindings.len());
+                env.truncate(depth);
+                value
+            }
+            SyntaxNode::Recur { args, .. } => return Step::Recur(self.values(args, env)),
+            SyntaxNode::Case {
+                expr,
+                clauses,
+                default,
+            } => {
+                let value = self.value(expr, env);
+                for (keys, then) in clauses {
+                    if keys.iter().any(|k| case_matches(k, &value)) {
+                        return self.step(then, env);
+                    }
+                }
+                return match default {
+                    Some(d) => self.step(d, env),
+                    None => Step::Value(no_matching_clause(&value)),
+                };
+            }
+            SyntaxNode::Call { path, args } => {
+                let args = self.values(args, env);
+                match self.global(path) {
+                    Some(f) => f.call(&args),
+                    None => call_core(path, &args),
+                }
+            }
+            SyntaxNode::Invoke { target, args } => {
+                let target = self.value(target, env);
+                target.call(&self.values(args, env))
+            }
+            SyntaxNode::Vector(items) => CljVal::CljVec(self.values(items, env)),
+            SyntaxNode::List(items) => CljVal::CljList(self.values(items, env)),
+            SyntaxNode::HashMap(pairs) => CljVal::CljMap(
+                pairs
+                    .iter()
+                    .map(|(k, v)| (self.value(k, env), self.value(v, env)))
+                    .collect(),
+            ),
+            SyntaxNode::Println(items) => {
+                let items: Vec<String> = self
+                    .values(items, env)
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect();
+                match &self.out {
+                    Some(out) => {
+                        let mut out = out.borrow_mut();
+                        out.push_str(&items.join(" "));
+                        out.push('\n');
+                    }
+                    None => println!("{}", items.join(" ")),
+                }
+                CljVal::CljNil
+            }
+            SyntaxNode::Equal(a, b) => CljVal::CljBool(self.value(a, env) == self.value(b, env)),
+            SyntaxNode::Const(a) => constant(a),
+            SyntaxNode::LocalRef { name, .. } => local(env, name),
+            SyntaxNode::GlobalRef { path, kind } => self.global_ref(path, *kind),
+            SyntaxNode::Record {
+                name,
+                type_name,
+                fields,
+                extensions,
+            } => {
+                self.defrecord(name, type_name, fields);
+                self.extend(extensions);
+                CljVal::CljNil
+            }
+            SyntaxNode::Protocol(protocol) => {
+                self.defprotocol(protocol);
+                CljVal::CljNil
+            }
+            SyntaxNode::Extend(extensions) => {
+                self.extend(extensions);
+                CljVal::CljNil
+            }
+            SyntaxNode::GetField { field, target, .. } => {
+                get__2(&self.value(target, env), &CljVal::CljKeyword(field.clone()))
+            }
+            SyntaxNode::RustCall { callee, .. } => {
+                let name = match callee {
+                    RustCallee::Fn(path) => path.clone(),
+                    RustCallee::Method(method) => format!(".{}", method),
+                    RustCallee::Macro(name) => format!("{}!", name),
+                };
+                Diagnostic::error(
+                    "E0022",
+                    format!(
+                        "{} is Rust code, which can only be compiled, not interpreted",
+                        name
+                    ),
+                )
+                .help("build the program to run it")
+                .raise()
+            }
+        };
+        Step::Value(value)
+    }
+
+    /// Run a body until it does not `recur`, rebinding the `n` locals from
+    /// `depth` each time it does.
+    fn recur_loop(&self, body: &[SyntaxNode], env: &mut Env, depth: usize, n: usize) -> CljVal {
+        loop {
+            match self.body(body, env) {
+                Step::Value(v) => return v,
+                Step::Recur(values) => {
+                    env.truncate(depth + n);
+                    for (binding, value) in env[depth..].iter_mut().zip(values) {
+                        binding.1 = value;
+                    }
+                }
+            }
+        }
+    }
+
+    /// A `defn` defines the dispatcher taking the arguments as a slice and,
+    /// when it has several arities, a function for each of them taking its
+    /// parameters in order, as `translate` does.
+    fn defn(&self, name: &str, arities: &[Arity]) {
+        let f = self.function(demangle_path(name), None, arities.to_vec(), Vec::new());
+        self.defs.borrow_mut().insert(self.path(name), f);
+        if arities.len() == 1 && arities[0].rest.is_none() {
+            return;
+        }
+        for arity in arities {
+            let arity_name = match arity.rest {
+                Some(_) => format!("{}__rest", name),
+                None => format!("{}__{}", name, arity.fixed.len()),
+            };
+            let (ev, arity) = (self.clone(), arity.clone());
+            let f = CljVal::new_fn(move |args| ev.apply(&arity, Vec::new(), args.to_vec()));
+            self.defs.borrow_mut().insert(self.path(&arity_name), f);
+        }
+    }
+    /// A `defrecord` defines the functions making records, of a type the
+    /// struct of which is not compiled, but that are the same values.
+    fn defrecord(&self, name: &str, type_name: &str, fields: &[Field]) {
+        let keys: Vec<CljVal> = fields
+            .iter()
+            .map(|f| CljVal::CljKeyword(f.name.clone()))
+            .collect();
+        let (type_name, fields) = (type_name.to_string(), fields.to_vec());
+        let ctor_name = format!("->{}", name);
+        let make = CljVal::new_fn(move |args| {
+            if args.len() != fields.len() {
+                arity_error(&ctor_name, args.len())
+            }
+            let fields = fields
+                .iter()
+                .zip(args)
+                .map(|(f, v)| (CljVal::CljKeyword(f.name.clone()), hinted(&f.hint, v)))
+                .collect();
+            CljVal::record(DynRecord {
+                type_name: type_name.clone(),
+                fields,
+            })
+        });
+        let ctor = make.clone();
+        let map_ctor_name = format!("map->{}", name);
+        let from_map = CljVal::new_fn(move |args| {
+            if args.len() != 1 {
+                arity_error(&map_ctor_name, args.len())
+            }
+            let values: Vec<CljVal> = keys.iter().map(|k| get__2(&args[0], k)).collect();
+            assoc_all(&ctor.call(&values), &args[0])
+        });
+        let mut defs = self.defs.borrow_mut();
+        defs.insert(self.path(&mangle(&format!("->{}", name))), make);
+        defs.insert(self.path(&mangle(&format!("map->{}", name))), from_map);
+    }
+    /// A `defprotocol` defines the functions of its methods, which call the
+    /// one implementing it for the type of their first argument the way
+    /// the compiled ones do, and the one telling whether a value satisfies
+    /// it.
+    fn defprotocol(&self, protocol: &Protocol) {
+        let mut defs = self.defs.borrow_mut();
+        for method in &protocol.methods {
+            let (impls, qualified) = (self.impls.clone(), protocol.qualified.clone());
+            let (name, n) = (method.name.clone(), method.params.len());
+            let f = CljVal::new_fn(move |args| {
+                if args.len() != n {
+                    arity_error(&name, args.len())
+                }
+                let type_name = args[0].type_name();
+                let find = |t: &str| {
+                    let impls = impls.borrow();
+                    impls
+                        .get(&(qualified.clone(), t.to_string()))?
+                        .get(&name)
+                        .cloned()
+                };
+                let f = match type_name {
+                    "nil" => find(type_name),
+                    _ => find(type_name).or_else(|| find("Object")),
+                };
+                match f {
+                    Some(f) => f.call(args),
+                    None => no_implementation(&name, &qualified, &args[0]),
+                }
+            });
+            defs.insert(self.path(&mangle(&method.name)), f);
+        }
+        let (impls, qualified) = (self.impls.clone(), protocol.qualified.clone());
+        let satisfies = CljVal::new_fn(move |args| {
+            let impls = impls.borrow();
+            let extends = |t: &str| impls.contains_key(&(qualified.clone(), t.to_string()));
+            let type_name = args[0].type_name();
+            CljVal::CljBool(extends(type_name) || (type_name != "nil" && extends("Object")))
+        });
+        defs.insert(self.path(&protocol.satisfies_name()), satisfies);
+    }
+    /// Protocols extended to types have their methods implemented for them.
+    fn extend(&self, extensions: &[Extension]) {
+        let mut impls = self.impls.borrow_mut();
+        for e in extensions {
+            let methods = impls
+                .entry((e.protocol.qualified.clone(), e.type_name.clone()))
+                .or_default();
+            for (method, arity) in &e.methods {
+                let f = self.function(method.clone(), None, vec![arity.clone()], Vec::new());
+                methods.insert(method.clone(), f);
+            }
+        }
+    }
+    /// A function value, dispatching on the number of arguments, and bound
+    /// to the local `me` in its body when it is named.
+    fn function(
+        &self,
+        name: String,
+        me: Option<String>,
+        arities: Vec<Arity>,
+        captured: Env,
+    ) -> CljVal {
+        let ev = self.clone();
+        CljVal::new_named_fn(move |f, args| {
+            let mut env = captured.clone();
+            if let Some(me) = &me {
+                env.push((me.clone(), f.clone()));
+            }
+            let n = args.len();
+            for arity in &arities {
+                let k = arity.fixed.len();
+                match arity.rest {
+                    None if n == k => return ev.apply(arity, env, args.to_vec()),
+                    Some(_) if n >= k => {
+                        let mut params = args[..k].to_vec();
+                        params.push(CljVal::rest_args(&args[k..]));
+                        return ev.apply(arity, env, params);
+                    }
+                    _ => {}
+                }
+            }
+            arity_error(&name, n)
+        })
+    }
+    /// Run an arity with its parameters, the one after `&` being last.
+    fn apply(&self, arity: &Arity, mut env: Env, params: Vec<CljVal>) -> CljVal {
+        let depth = env.len();
+        let names = arity.fixed.iter().chain(arity.rest.iter()).cloned();
+        env.extend(names.zip(params));
+        let n = env.len() - depth;
+        self.recur_loop(&arity.body, &mut env, depth, n)
+    }
+
+    fn global_ref(&self, path: &str, kind: RefKind) -> CljVal {
+        let f = match kind {
+            RefKind::Var(_) => {
+                return self
+                    .global(path)
+                    .unwrap_or_else(|| panic!("Var {} is unbound.", demangle_path(path)))
+            }
+            _ => match self.global(path) {
+                Some(f) => f,
+                None => {
+                    let path = path.to_string();
+                    CljVal::new_fn(move |args| call_core(&path, args))
+                }
+            },
+        };
+        match kind {
+            RefKind::Fn(arity) => {
+                let name = demangle_path(path);
+                CljVal::new_fn(move |args| match args.len() {
+                    n if n == arity => f.call(args),
+                    n => arity_error(&name, n),
+                })
+            }
+            _ => f,
+        }
+    }
+}
+
+fn local(env: &Env, name: &str) -> CljVal {
+    match env.iter().rev().find(|(n, _)| n == name) {
+        Some((_, value)) => value.clone(),
+        None => unreachable!(),
+    }
+}
+
+/// A literal or quoted data, as a value.
+fn constant(a: &AstVal) -> CljVal {
+    // Metadata in quoted data is left out, as values do not keep it.
+    let seq = |v: &[AstVal]| {
+        v.iter()
+            .filter(|x| !matches!(x, AstVal::AstMeta(_)))
+            .map(constant)
+            .collect()
+    };
+    match a {
+        AstVal::AstSymbol(s) => CljVal::CljSymbol(s.clone()),
+        AstVal::AstList(v) => CljVal::CljList(seq(v)),
+        AstVal::AstVec(v) => CljVal::CljVec(seq(v)),
+        AstVal::AstMap(v) => CljVal::CljMap(
+            v.iter()
+                .map(|(k, item)| (constant(k), constant(item)))
+                .collect(),
+        ),
+        AstVal::AstNil => CljVal::CljNil,
+        AstVal::AstBool(b) => CljVal::CljBool(*b),
+        AstVal::AstInt(i) => CljVal::CljInt(*i),
+        AstVal::AstFloat(f) => CljVal::CljFloat(*f),
+        AstVal::AstString(s) => CljVal::CljString(s.clone()),
+        AstVal::AstKeyword(s) => CljVal::CljKeyword(s.clone()),
+        _ => Diagnostic::error("E0029", format!("Can't embed object in code: {}", a)).raise(),
+    }
+}
+
+/// Whether a key of a `case` clause matches. `nil` is a pattern in Rust,
+/// and matches nothing but `nil`, where `=` has it equal to `()` and `[]`.
+fn case_matches(key: &AstVal, value: &CljVal) -> bool {
+    match key {
+        AstVal::AstNil => matches!(value, CljVal::CljNil),
+        _ => constant(key) == *value,
+    }
+}
+
+/// A value of a record field as the struct of the compiled record has it:
+/// converted to the type the field is hinted with, and back.
+fn hinted(hint: &Option<String>, v: &CljVal) -> CljVal {
+    match hint.as_deref() {
+        Some("i8") => i8::from_clj(v).into_clj(),
+        Some("i16") => i16::from_clj(v).into_clj(),
+        Some("i32") => i32::from_clj(v).into_clj(),
+        Some("i64") => i64::from_clj(v).into_clj(),
+        Some("isize") => isize::from_clj(v).into_clj(),
+        Some("u8") => u8::from_clj(v).into_clj(),
+        Some("u16") => u16::from_clj(v).into_clj(),
+        Some("u32") => u32::from_clj(v).into_clj(),
+        Some("u64") => u64::from_clj(v).into_clj(),
+        Some("usize") => usize::from_clj(v).into_clj(),
+        Some("f32") => f32::from_clj(v).into_clj(),
+        Some("f64") => f64::from_clj(v).into_clj(),
+        Some("bool") => bool::from_clj(v).into_clj(),
+        Some("String") => String::from_clj(v).into_clj(),
+        _ => v.clone(),
+    }
+}
+
+/// Call a function of the runtime by its name in Rust.
+fn call_core(path: &str, args: &[CljVal]) -> CljVal {
+    match path {
+        "map" => map(&args[0], &args[1]),
+        "reduce" => reduce(args),
+        "reduce__2" => reduce__2(&args[0], &args[1]),
+        "reduce__3" => reduce__3(&args[0], &args[1], &args[2]),
+        "get" => get(args),
+        "get__2" => get__2(&args[0], &args[1]),
+        "get__3" => get__3(&args[0], &args[1], &args[2]),
+        "nth" => nth(args),
+        "nth__2" => nth__2(&args[0], &args[1]),
+        "nth__3" => nth__3(&args[0], &args[1], &args[2]),
+        "nthnext" => nthnext(&args[0], &args[1]),
+        "seq_to_map_for_destructuring" => seq_to_map_for_destructuring(&args[0]),
+        "inc" => inc(&args[0]),
+        "assoc" => assoc(&args[0], &args[1], &args[2]),
+        "keys" => keys(&args[0]),
+        "vals" => vals(&args[0]),
+        "lt" => lt(&args[0], &args[1]),
+        "no_matching_clause" => no_matching_clause(&args[0]),
+        "multi_fn" => multi_fn(&args[0], &args[1], &args[2]),
+        "add_method" => add_method(&args[0], &args[1], &args[2]),
+        "prefer_method" => prefer_method(&args[0], &args[1], &args[2]),
+        "remove_method" => remove_method(&args[0], &args[1]),
+        "get_method" => get_method(&args[0], &args[1]),
+        "derive" => derive(&args[0], &args[1]),
+        "underive" => underive(&args[0], &args[1]),
+        "isa" => isa(&args[0], &args[1]),
+        "parents" => parents(&args[0]),
+        "ancestors" => ancestors(&args[0]),
+        "descendants" => descendants(&args[0]),
+        "throw" => throw(&args[0]),
+        "ex_info" => ex_info(args),
+        "ex_info__2" => ex_info__2(&args[0], &args[1]),
+        "ex_info__3" => ex_info__3(&args[0], &args[1], &args[2]),
+        "ex_data" => ex_data(&args[0]),
+        "ex_message" => ex_message(&args[0]),
+        "ex_cause" => ex_cause(&args[0]),
+        _ => panic!("Unable to resolve function: {}", demangle_path(path)),
+    }
+}
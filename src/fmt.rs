@@ -0,0 +1,169 @@
+/// Heads of forms whose body is indented by two spaces instead of being
+/// aligned with their first argument, besides those of `BODY_PREFIXES`.
+const BODY_FORMS: &[&str] = &[
+    "if",
+    "ns",
+    "fn",
+    "do",
+    "case",
+    "cond",
+    "condp",
+    "cond->",
+    "cond->>",
+    "as->",
+    "try",
+    "catch",
+    "finally",
+    "for",
+    "doseq",
+    "dotimes",
+    "doto",
+    "loop",
+    "binding",
+    "letfn",
+    "comment",
+    "reify",
+    "proxy",
+    "extend-type",
+    "extend-protocol",
+];
+const BODY_PREFIXES: &[&str] = &["def", "with-", "when", "if-", "let"];
+
+fn is_body_form(head: &str) -> bool {
+    let name = head.rsplit('/').next().unwrap_or(head);
+    BODY_FORMS.contains(&name) || BODY_PREFIXES.iter().any(|p| name.starts_with(p))
+}
+
+/// An open bracket on the lines formatted so far.
+struct Frame {
+    /// The column of the bracket.
+    col: usize,
+    /// The column of the lines in it that start with one of its items.
+    indent: usize,
+    /// For a list with a symbol first, whether the indent is not known yet,
+    /// as it is the column of its second item if that is on the same line.
+    aligning: bool,
+    /// The number of items in it so far.
+    items: usize,
+}
+
+/// Source formatted the way Clojure is usually written: each line indented
+/// by the brackets it is in, without trailing whitespace, and with at most
+/// one blank line in a row. The text of strings and comments is kept.
+pub fn format_source(text: &str) -> String {
+    let mut out = String::new();
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut in_string = false;
+    let mut blank = false;
+    for raw in text.lines() {
+        let line = if in_string { raw } else { raw.trim() };
+        if line.is_empty() && !in_string {
+            if !out.is_empty() && !blank {
+                out.push('\n');
+            }
+            blank = true;
+            continue;
+        }
+        blank = false;
+        let indent = if in_string {
+            0
+        } else {
+            match stack.last() {
+                Some(frame) if line.starts_with(|c| ")]}".contains(c)) => frame.col + 1,
+                Some(frame) => frame.indent,
+                None => 0,
+            }
+        };
+        out.push_str(&" ".repeat(indent));
+        out.push_str(line);
+        out.push('\n');
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let col = if in_string { i } else { indent + i };
+            if in_string {
+                match c {
+                    '\\' => i += 1,
+                    '"' => in_string = false,
+                    _ => {}
+                }
+                i += 1;
+                continue;
+            }
+            match c {
+                ';' => break,
+                '"' => {
+                    item(&mut stack, col, false);
+                    in_string = true;
+                }
+                '(' | '[' | '{' => {
+                    item(&mut stack, col, false);
+                    stack.push(Frame {
+                        col,
+                        indent: col + 1,
+                        aligning: c == '(',
+                        items: 0,
+                    });
+                }
+                ')' | ']' | '}' => {
+                    stack.pop();
+                }
+                '\\' => {
+                    // A character literal, which may be a bracket.
+                    item(&mut stack, col, false);
+                    i += 2;
+                    while i < chars.len() && !is_delimiter(chars[i]) {
+                        i += 1;
+                    }
+                    continue;
+                }
+                c if c.is_whitespace() || c == ',' => {}
+                '\'' | '`' | '~' | '@' | '^' | '#' => {}
+                _ => {
+                    let start = i;
+                    while i < chars.len() && !is_delimiter(chars[i]) {
+                        i += 1;
+                    }
+                    let token: String = chars[start..i].iter().collect();
+                    if let Some(frame) = stack.last_mut() {
+                        if frame.items == 0 && frame.aligning && is_body_form(&token) {
+                            frame.indent = frame.col + 2;
+                            frame.aligning = false;
+                        }
+                    }
+                    item(&mut stack, col, true);
+                    continue;
+                }
+            }
+            i += 1;
+        }
+        // What is still aligning at the end of a line has no second item
+        // on the line of its head.
+        for frame in stack.iter_mut() {
+            frame.aligning = false;
+        }
+    }
+    while out.ends_with("\n\n") {
+        out.pop();
+    }
+    out
+}
+
+/// Count an item starting at `col` in the innermost bracket. Only lists
+/// with a symbol first align their items.
+fn item(stack: &mut [Frame], col: usize, symbol: bool) {
+    if let Some(frame) = stack.last_mut() {
+        frame.items += 1;
+        if frame.items == 1 && !symbol {
+            frame.aligning = false;
+        } else if frame.items == 2 && frame.aligning {
+            frame.indent = col;
+            frame.aligning = false;
+        }
+    }
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()[]{}\",;".contains(c)
+}
@@ -0,0 +1,322 @@
+use build::build_with_rustc;
+use clojure_rust_core::CljVal;
+use diagnostic::{self, in_source};
+use eval::Evaluator;
+use namespace::{compile_tree, find_ns, read_forms};
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use syntax::{syntax, Globals};
+
+/// Where `test` looks for programs when it is given none.
+pub const DEFAULT_PATHS: &[&str] = &["example", "tests"];
+
+/// How a program ended: what it printed, and what its `main` returned, or
+/// the message of the error it stopped with.
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    stdout: String,
+    value: Option<String>,
+    error: Option<String>,
+}
+
+impl Outcome {
+    /// How it ended, after a line of its own.
+    fn ending(&self) -> String {
+        match (&self.error, &self.value) {
+            (Some(message), _) => format!("error: {}", message),
+            (None, Some(value)) => format!("=> {}", value),
+            (None, None) => "=> ?".to_string(),
+        }
+    }
+    /// The outcome as its `.out` file has it: what the program printed, and
+    /// how it ended on the last line, after an empty one.
+    fn render(&self) -> String {
+        format!("{}\n{}\n", self.stdout, self.ending())
+    }
+    fn parse(text: &str) -> Outcome {
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        let (stdout, ending) = text.rsplit_once('\n').unwrap_or(("", text));
+        let (value, error) = match ending.strip_prefix("error: ") {
+            Some(message) => (None, Some(message.to_string())),
+            None => (ending.strip_prefix("=> ").map(str::to_string), None),
+        };
+        Outcome {
+            stdout: stdout.to_string(),
+            value,
+            error,
+        }
+    }
+}
+
+/// Run a program with the interpreter, or why it can not be: it has no
+/// `main`, or calls Rust code. The errors of running it are its outcome,
+/// those of compiling it are raised.
+fn interpret(path: &Path) -> Result<Outcome, &'static str> {
+    let out = Rc::new(RefCell::new(String::new()));
+    let ev = Evaluator::new().capturing(out.clone());
+    let source = read_forms(path);
+    let ran = diagnostic::catch(|| {
+        let ev = match find_ns(&source.forms) {
+            Some(ns) => {
+                compile_tree(path, &ns, &source, &[], false, |ns, x, globals| {
+                    ev.in_ns(ns).eval(&syntax(x, globals));
+                    String::new()
+                });
+                ev.in_ns(&ns.name)
+            }
+            None => {
+                let mut globals = Globals::new();
+                in_source(&source.source, None, || globals.prescan(&source.forms));
+                source.compile_each(|x| {
+                    ev.eval(&syntax(x, &mut globals));
+                    String::new()
+                });
+                ev.clone()
+            }
+        };
+        match ev.get("main") {
+            CljVal::CljNil => None,
+            main => Some(main.call(&[]).to_string()),
+        }
+    });
+    let (value, error) = match ran {
+        Ok(None) => return Err("no main"),
+        Ok(value) => (value, None),
+        // What is not a diagnostic of the compiler is an error of the program.
+        Err(d) if d.code == "E0000" => (None, Some(d.message)),
+        Err(d) if d.code == "E0022" => return Err("uses Rust interop"),
+        Err(d) => d.raise(),
+    };
+    let stdout = out.borrow().clone();
+    Ok(Outcome {
+        stdout,
+        value,
+        error,
+    })
+}
+
+/// Build a program with rustc in `dir`, and run it.
+fn run_compiled(path: &Path, dir: &Path) -> Outcome {
+    let exe = build_with_rustc(&path.display().to_string(), dir);
+    let value_file = dir.join("value");
+    let output = match process::Command::new(&exe)
+        .env("CLOJURE_RUST_MAIN_VALUE", &value_file)
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) => diagnostic::Diagnostic::error(
+            "E0020",
+            format!("could not run {}: {}", exe.display(), e),
+        )
+        .raise(),
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Outcome {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        value: fs::read_to_string(&value_file).ok(),
+        error: if output.status.success() {
+            None
+        } else {
+            Some(panic_message(&stderr))
+        },
+    }
+}
+
+/// The message of the panic a Rust program reported on stderr.
+fn panic_message(stderr: &str) -> String {
+    let mut lines = stderr.lines();
+    while let Some(line) = lines.next() {
+        if line.contains("panicked at") {
+            return lines.next().unwrap_or("").to_string();
+        }
+    }
+    stderr.trim().to_string()
+}
+
+/// How two outcomes differ, each named, for the report of a test that
+/// failed.
+fn difference((a_name, a): (&str, &Outcome), (b_name, b): (&str, &Outcome)) -> String {
+    let mut s = String::new();
+    let width = a_name.len().max(b_name.len()) + 1;
+    if a.stdout != b.stdout {
+        let (a_lines, b_lines): (Vec<&str>, Vec<&str>) =
+            (a.stdout.lines().collect(), b.stdout.lines().collect());
+        let line = (0..a_lines.len().max(b_lines.len()))
+            .find(|&i| a_lines.get(i) != b_lines.get(i))
+            .unwrap_or(0);
+        s.push_str(&format!(
+            "stdout differs at line {}:\n  {:w$} {}\n  {:w$} {}\n",
+            line + 1,
+            format!("{}:", a_name),
+            a_lines.get(line).unwrap_or(&"<end of output>"),
+            format!("{}:", b_name),
+            b_lines.get(line).unwrap_or(&"<end of output>"),
+            w = width
+        ));
+    }
+    if a.ending() != b.ending() {
+        s.push_str(&format!(
+            "the programs ended differently:\n  {:w$} {}\n  {:w$} {}\n",
+            format!("{}:", a_name),
+            a.ending(),
+            format!("{}:", b_name),
+            b.ending(),
+            w = width
+        ));
+    }
+    s
+}
+
+/// Run a program with the interpreter, unless it can only run compiled, and
+/// compiled in `dir`, and tell how the outcomes differ from each other and
+/// from the one in the `.out` file next to it, if there is one. A program
+/// with Rust interop and no `.out` file, or with no `main`, is ignored.
+fn check(path: &Path, dir: &Path) -> Result<String, &'static str> {
+    let expected = fs::read_to_string(path.with_extension("out"))
+        .ok()
+        .map(|text| Outcome::parse(&text));
+    let interpreted = match (interpret(path), &expected) {
+        (Ok(interpreted), _) => Some(interpreted),
+        (Err("uses Rust interop"), Some(_)) => None,
+        (Err(why), _) => return Err(why),
+    };
+    let compiled = run_compiled(path, dir);
+    let mut s = String::new();
+    if let Some(interpreted) = &interpreted {
+        s.push_str(&difference(
+            ("interpreted", interpreted),
+            ("compiled", &compiled),
+        ));
+    }
+    if let Some(expected) = &expected {
+        if *expected != compiled {
+            s.push_str(&difference(("expected", expected), ("compiled", &compiled)));
+            s.push_str(&format!(
+                "what the compiled program gives, as a .out file has it:\n{}",
+                compiled.render()
+            ));
+        }
+        if let Some(interpreted) = &interpreted {
+            s.push_str(&difference(
+                ("expected", expected),
+                ("interpreted", interpreted),
+            ));
+        }
+    }
+    Ok(s)
+}
+
+/// The `.clj` files at `path`, and under it if it is a directory.
+fn collect(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.flatten() {
+            collect(&entry.path(), files);
+        }
+    } else if path.extension().is_some_and(|e| e == "clj") {
+        files.push(path.to_path_buf());
+    }
+}
+
+/// Run every program in `paths` with the interpreter and compiled with
+/// rustc, and fail on those where what they print or how they end differs,
+/// between the two or from what their `.out` file says. Programs without a
+/// `main`, like the namespaces others require, are ignored, and so are
+/// those calling Rust code, which only runs compiled, unless they have a
+/// `.out` file. Whether all passed.
+pub fn test(paths: &[String], json: bool) -> bool {
+    let mut files = Vec::new();
+    for path in paths {
+        collect(Path::new(path), &mut files);
+    }
+    files.sort();
+    println!("running {} programs", files.len());
+    let (mut passed, mut ignored) = (0, 0);
+    let mut failures = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        let dir = env::temp_dir().join(format!("clojure-rust-test-{}-{}", process::id(), i));
+        let mut result = Err("");
+        let compiled = diagnostic::run(json, || result = check(file, &dir));
+        let _ = fs::remove_dir_all(&dir);
+        let status = match result {
+            _ if !compiled => {
+                failures.push((file, "it does not compile\n".to_string()));
+                "FAILED".to_string()
+            }
+            Err(why) => {
+                ignored += 1;
+                format!("ignored, {}", why)
+            }
+            Ok(ref diff) if diff.is_empty() => {
+                passed += 1;
+                "ok".to_string()
+            }
+            Ok(diff) => {
+                failures.push((file, diff));
+                "FAILED".to_string()
+            }
+        };
+        println!("test {} ... {}", file.display(), status);
+    }
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (file, diff) in &failures {
+            print!("\n---- {} ----\n{}", file.display(), diff);
+        }
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed; {} ignored",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        passed,
+        failures.len(),
+        ignored
+    );
+    failures.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcome_round_trips_through_its_out_file() {
+        let outcomes = [
+            Outcome {
+                stdout: "a\nb\n".to_string(),
+                value: Some("[1 2]".to_string()),
+                error: None,
+            },
+            Outcome {
+                stdout: String::new(),
+                value: None,
+                error: Some("Index out of bounds".to_string()),
+            },
+            Outcome {
+                stdout: "no newline".to_string(),
+                value: Some("nil".to_string()),
+                error: None,
+            },
+        ];
+        for outcome in outcomes {
+            assert_eq!(Outcome::parse(&outcome.render()), outcome);
+        }
+    }
+
+    #[test]
+    fn difference_names_what_differs() {
+        let expected = Outcome::parse("1\n2\n\n=> nil\n");
+        let compiled = Outcome::parse("1\n3\n\nerror: boom\n");
+        assert_eq!(
+            difference(("expected", &expected), ("compiled", &compiled)),
+            "stdout differs at line 2:\n  expected: 2\n  compiled: 3\n\
+             the programs ended differently:\n  expected: => nil\n  compiled: error: boom\n"
+        );
+    }
+}
@@ -0,0 +1,917 @@
+use ast::AstVal;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use syntax::expand_core_macro;
+
+/// The definitions of a namespace, as the interpreter sees them. Shared by
+/// the functions of the namespace, which look their globals up in there.
+pub type Defs = Rc<RefCell<HashMap<String, AstVal>>>;
+
+/// Locals, innermost last.
+type Env = Vec<(String, AstVal)>;
+
+pub enum Function {
+    Builtin(&'static str),
+    Lambda(Lambda),
+}
+
+pub struct Lambda {
+    name: Option<String>,
+    arities: Vec<Arity>,
+    /// A macro takes `&form` and `&env` first, then its arguments unevaluated.
+    is_macro: bool,
+    /// The locals it closes over.
+    env: Env,
+    /// The namespace it was defined in, with its definitions.
+    ns: Option<String>,
+    defs: Defs,
+}
+
+struct Arity {
+    parameters: Vec<AstVal>,
+    rest: Option<AstVal>,
+    body: Vec<AstVal>,
+}
+
+impl Function {
+    pub fn name(&self) -> &str {
+        match self {
+            Function::Builtin(name) => name,
+            Function::Lambda(l) => l.name.as_deref().unwrap_or("fn"),
+        }
+    }
+    pub fn is_macro(&self) -> bool {
+        matches!(self, Function::Lambda(l) if l.is_macro)
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#function[{}]", self.name())
+    }
+}
+
+const BUILTINS: &[&str] = &[
+    "list",
+    "list*",
+    "cons",
+    "concat",
+    "seq",
+    "first",
+    "second",
+    "rest",
+    "next",
+    "last",
+    "butlast",
+    "nth",
+    "count",
+    "empty?",
+    "conj",
+    "vector",
+    "vec",
+    "hash-map",
+    "get",
+    "assoc",
+    "keys",
+    "vals",
+    "contains?",
+    "symbol",
+    "keyword",
+    "name",
+    "str",
+    "pr-str",
+    "println",
+    "gensym",
+    "=",
+    "not=",
+    "not",
+    "nil?",
+    "some?",
+    "symbol?",
+    "keyword?",
+    "string?",
+    "number?",
+    "list?",
+    "vector?",
+    "map?",
+    "seq?",
+    "coll?",
+    "fn?",
+    "+",
+    "-",
+    "*",
+    "/",
+    "inc",
+    "dec",
+    "<",
+    ">",
+    "<=",
+    ">=",
+    "mod",
+    "even?",
+    "odd?",
+    "zero?",
+    "map",
+    "filter",
+    "reduce",
+    "apply",
+    "interleave",
+    "partition",
+    "reverse",
+    "into",
+    "identity",
+    "range",
+    "macroexpand",
+    "macroexpand-1",
+    "no_matching_clause",
+];
+
+pub fn truthy(a: &AstVal) -> bool {
+    !matches!(a, AstVal::AstNil | AstVal::AstBool(false))
+}
+
+/// The items of a collection, for the functions that take a seq.
+pub fn items(a: &AstVal) -> Vec<AstVal> {
+    match a {
+        AstVal::AstNil => Vec::new(),
+        AstVal::AstList(v) | AstVal::AstVec(v) => v.clone(),
+        AstVal::AstMap(v) => v
+            .iter()
+            .map(|(k, item)| AstVal::AstVec(vec![k.clone(), item.clone()]))
+            .collect(),
+        AstVal::AstString(s) => s
+            .chars()
+            .map(|c| AstVal::AstString(c.to_string()))
+            .collect(),
+        _ => panic!("Don't know how to create ISeq from: {}", pr_str(a)),
+    }
+}
+
+fn seq(v: Vec<AstVal>) -> AstVal {
+    if v.is_empty() {
+        AstVal::AstNil
+    } else {
+        AstVal::AstList(v)
+    }
+}
+
+/// A value printed the way the reader reads it back.
+pub fn pr_str(a: &AstVal) -> String {
+    match a {
+        AstVal::AstString(s) => format!("{:?}", s),
+        AstVal::AstList(v) => format!("({})", v.iter().map(pr_str).collect::<Vec<_>>().join(" ")),
+        AstVal::AstVec(v) => format!("[{}]", v.iter().map(pr_str).collect::<Vec<_>>().join(" ")),
+        AstVal::AstMap(v) | AstVal::AstMeta(v) => format!(
+            "{{{}}}",
+            v.iter()
+                .map(|(k, item)| format!("{} {}", pr_str(k), pr_str(item)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        _ => format!("{}", a),
+    }
+}
+
+/// What `str` makes of a value.
+fn to_str(a: &AstVal) -> String {
+    match a {
+        AstVal::AstNil => String::new(),
+        AstVal::AstString(s) => s.clone(),
+        _ => pr_str(a),
+    }
+}
+
+fn get(m: &AstVal, k: &AstVal, default: AstVal) -> AstVal {
+    match (m, k) {
+        (AstVal::AstMap(v), _) => v
+            .iter()
+            .find(|(k1, _)| k1 == k)
+            .map(|(_, item)| item.clone()),
+        (AstVal::AstVec(v), AstVal::AstInt(i)) => v.get(*i as usize).cloned(),
+        _ => None,
+    }
+    .unwrap_or(default)
+}
+
+fn number(a: &AstVal) -> f64 {
+    match a {
+        AstVal::AstInt(i) => *i as f64,
+        AstVal::AstFloat(f) => *f,
+        _ => panic!("{} cannot be cast to Number", pr_str(a)),
+    }
+}
+
+fn arithmetic(name: &str, args: &[AstVal]) -> AstVal {
+    let ints: Option<Vec<i64>> = args
+        .iter()
+        .map(|a| match a {
+            AstVal::AstInt(i) => Some(*i),
+            _ => None,
+        })
+        .collect();
+    match (name, ints) {
+        ("+", Some(v)) => AstVal::AstInt(v.iter().sum()),
+        ("*", Some(v)) => AstVal::AstInt(v.iter().product()),
+        ("-", Some(v)) if v.len() == 1 => AstVal::AstInt(-v[0]),
+        ("-", Some(v)) => AstVal::AstInt(v[1..].iter().fold(v[0], |a, b| a - b)),
+        ("/", Some(ref v)) if v.len() == 2 && v[1] != 0 && v[0] % v[1] == 0 => {
+            AstVal::AstInt(v[0] / v[1])
+        }
+        _ => {
+            let v: Vec<f64> = args.iter().map(number).collect();
+            AstVal::AstFloat(match name {
+                "+" => v.iter().sum(),
+                "*" => v.iter().product(),
+                "-" if v.len() == 1 => -v[0],
+                "-" => v[1..].iter().fold(v[0], |a, b| a - b),
+                _ if v.len() == 1 => 1.0 / v[0],
+                _ => v[1..].iter().fold(v[0], |a, b| a / b),
+            })
+        }
+    }
+}
+
+fn compare(name: &str, args: &[AstVal]) -> AstVal {
+    let v: Vec<f64> = args.iter().map(number).collect();
+    AstVal::AstBool(v.windows(2).all(|w| match name {
+        "<" => w[0] < w[1],
+        ">" => w[0] > w[1],
+        "<=" => w[0] <= w[1],
+        _ => w[0] >= w[1],
+    }))
+}
+
+fn arity_error(name: &str, n: usize) -> ! {
+    panic!("Wrong number of args ({}) passed to: {}", n, name)
+}
+
+/// Evaluates forms at compile time: the bodies of macros, and the functions
+/// they call.
+#[derive(Debug)]
+pub struct Interpreter {
+    /// The name of the namespace being compiled, which `` ` `` qualifies its
+    /// definitions with.
+    pub ns: Option<String>,
+    pub defs: Defs,
+    gensym_counter: usize,
+    /// The arguments of a `recur` on its way back to its loop.
+    recur: Option<Vec<AstVal>>,
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter {
+            ns: None,
+            defs: Rc::new(RefCell::new(HashMap::new())),
+            gensym_counter: 0,
+            recur: None,
+        }
+    }
+    pub fn gensym(&mut self, prefix: &str) -> AstVal {
+        self.gensym_counter += 1;
+        AstVal::AstSymbol(format!("{}{}", prefix, self.gensym_counter))
+    }
+    /// The macro a symbol names in this namespace.
+    pub fn find_macro(&self, s: &str) -> Option<AstVal> {
+        let name = match s.find('/') {
+            Some(i) if s.len() > 1 && Some(&s[..i]) == self.ns.as_deref() => &s[i + 1..],
+            _ => s,
+        };
+        match self.defs.borrow().get(name) {
+            Some(AstVal::AstFn(f)) if f.is_macro() => Some(AstVal::AstFn(f.clone())),
+            _ => None,
+        }
+    }
+    /// `(defmacro name doc? attr-map? [params] body...)`, or with several
+    /// arities.
+    pub fn defmacro(&mut self, v: &[AstVal]) -> String {
+        let name = match v.get(1) {
+            Some(AstVal::AstSymbol(name)) => name.clone(),
+            _ => panic!("defmacro needs a name"),
+        };
+        let mut rest = &v[2..];
+        while let Some(AstVal::AstString(_)) | Some(AstVal::AstMap(_)) = rest.first() {
+            rest = &rest[1..];
+        }
+        let mut lambda = self.lambda(Some(name.clone()), rest, &Vec::new());
+        lambda.is_macro = true;
+        for arity in &mut lambda.arities {
+            arity
+                .parameters
+                .insert(0, AstVal::AstSymbol("&env".to_string()));
+            arity
+                .parameters
+                .insert(0, AstVal::AstSymbol("&form".to_string()));
+        }
+        self.defs.borrow_mut().insert(
+            name.clone(),
+            AstVal::AstFn(Rc::new(Function::Lambda(lambda))),
+        );
+        name
+    }
+    /// Make a function defined with `defn` callable from macros.
+    pub fn defn(&mut self, name: &str, arities: &[AstVal]) {
+        let lambda = self.lambda(Some(name.to_string()), arities, &Vec::new());
+        self.defs.borrow_mut().insert(
+            name.to_string(),
+            AstVal::AstFn(Rc::new(Function::Lambda(lambda))),
+        );
+    }
+    /// Call a macro on a form, with the locals in scope as `&env`.
+    pub fn expand(&mut self, mac: &AstVal, form: &AstVal, locals: &[AstVal]) -> AstVal {
+        let env = AstVal::AstMap(locals.iter().map(|l| (l.clone(), AstVal::AstNil)).collect());
+        let mut args = vec![form.clone(), env];
+        if let AstVal::AstList(v) = form {
+            args.extend(v[1..].iter().cloned());
+        }
+        self.apply(mac, args)
+    }
+    /// Expand a form once if it is a call to a macro of this namespace.
+    fn macroexpand_1(&mut self, form: &AstVal) -> AstVal {
+        if let AstVal::AstList(v) = form {
+            if let Some(AstVal::AstSymbol(s)) = v.first() {
+                if let Some(mac) = self.find_macro(s) {
+                    return self.expand(&mac, form, &[]);
+                }
+            }
+        }
+        form.clone()
+    }
+    fn lambda(&mut self, name: Option<String>, v: &[AstVal], env: &Env) -> Lambda {
+        let arities: Vec<(&AstVal, &[AstVal])> = match v.first() {
+            Some(AstVal::AstVec(_)) | Some(AstVal::AstNil) => vec![(&v[0], &v[1..])],
+            Some(AstVal::AstList(_)) => v
+                .iter()
+                .map(|item| match item {
+                    AstVal::AstList(l) => (&l[0], &l[1..]),
+                    _ => panic!("not an arity"),
+                })
+                .collect(),
+            _ => panic!("no function parameter"),
+        };
+        let arities = arities
+            .into_iter()
+            .map(|(parameters, body)| {
+                let mut fixed = Vec::new();
+                let mut rest = None;
+                let parameters = match parameters {
+                    AstVal::AstVec(p) => p.clone(),
+                    _ => Vec::new(),
+                };
+                let mut items = parameters.into_iter();
+                while let Some(p) = items.next() {
+                    if p == AstVal::AstSymbol("&".to_string()) {
+                        rest = items.next();
+                    } else {
+                        fixed.push(p);
+                    }
+                }
+                Arity {
+                    parameters: fixed,
+                    rest,
+                    body: body.to_vec(),
+                }
+            })
+            .collect();
+        Lambda {
+            name,
+            arities,
+            is_macro: false,
+            env: env.clone(),
+            ns: self.ns.clone(),
+            defs: self.defs.clone(),
+        }
+    }
+    fn lookup(&self, s: &str, env: &Env) -> AstVal {
+        if let Some((_, a)) = env.iter().rev().find(|(name, _)| name == s) {
+            return a.clone();
+        }
+        let (ns, name) = match s.find('/') {
+            Some(i) if s.len() > 1 => (Some(&s[..i]), &s[i + 1..]),
+            _ => (None, s),
+        };
+        if ns != Some("clojure.core") {
+            if let Some(a) = self.defs.borrow().get(name) {
+                return a.clone();
+            }
+        }
+        match BUILTINS.iter().find(|b| **b == name) {
+            Some(b) => AstVal::AstFn(Rc::new(Function::Builtin(b))),
+            None => panic!("Unable to resolve symbol: {} in this context", s),
+        }
+    }
+    /// Bind a binding form to a value, destructuring it.
+    fn bind(&mut self, target: &AstVal, value: AstVal, env: &mut Env) {
+        match target {
+            AstVal::AstSymbol(s) => env.push((s.clone(), value)),
+            AstVal::AstVec(v) => {
+                let all = items(&value);
+                let mut n = 0;
+                let mut targets = v.iter();
+                while let Some(t) = targets.next() {
+                    match t {
+                        AstVal::AstSymbol(s) if s == "&" => {
+                            let rest = all.get(n..).map(|r| r.to_vec()).unwrap_or_default();
+                            self.bind(targets.next().unwrap(), seq(rest), env);
+                        }
+                        AstVal::AstKeyword(k) if k == "as" => {
+                            self.bind(targets.next().unwrap(), value.clone(), env);
+                        }
+                        _ => {
+                            self.bind(t, all.get(n).cloned().unwrap_or(AstVal::AstNil), env);
+                            n += 1;
+                        }
+                    }
+                }
+            }
+            AstVal::AstMap(v) => {
+                let m = match value {
+                    AstVal::AstList(l) => {
+                        AstVal::AstMap(l.chunks(2).map(|p| (p[0].clone(), p[1].clone())).collect())
+                    }
+                    _ => value,
+                };
+                let defaults = v
+                    .iter()
+                    .find(|(k, _)| *k == AstVal::AstKeyword("or".to_string()))
+                    .map(|(_, d)| d.clone())
+                    .unwrap_or(AstVal::AstNil);
+                for (k, t) in v {
+                    let keys: Vec<(AstVal, AstVal)> = match k {
+                        AstVal::AstKeyword(s) if s == "or" => continue,
+                        AstVal::AstKeyword(s) if s == "as" => {
+                            self.bind(t, m.clone(), env);
+                            continue;
+                        }
+                        AstVal::AstKeyword(s)
+                            if s.ends_with("keys") || s == "strs" || s == "syms" =>
+                        {
+                            items(t)
+                                .into_iter()
+                                .map(|name| {
+                                    let name = match name {
+                                        AstVal::AstSymbol(n) | AstVal::AstKeyword(n) => n,
+                                        _ => panic!("{} is not a symbol", pr_str(&name)),
+                                    };
+                                    let local = name.rsplit('/').next().unwrap().to_string();
+                                    let full = match s.find('/') {
+                                        Some(i) => format!("{}/{}", &s[..i], local),
+                                        None => name.clone(),
+                                    };
+                                    let key = match s.as_str() {
+                                        "strs" => AstVal::AstString(full),
+                                        "syms" => AstVal::AstSymbol(full),
+                                        _ => AstVal::AstKeyword(full),
+                                    };
+                                    (AstVal::AstSymbol(local), key)
+                                })
+                                .collect()
+                        }
+                        _ => vec![(t.clone(), k.clone())],
+                    };
+                    for (local, key) in keys {
+                        let default = get(&defaults, &local, AstVal::AstNil);
+                        let value = get(&m, &key, default);
+                        self.bind(&local, value, env);
+                    }
+                }
+            }
+            _ => panic!("unsupported binding form: {}", pr_str(target)),
+        }
+    }
+    fn eval_body(&mut self, body: &[AstVal], env: &mut Env) -> AstVal {
+        let mut result = AstVal::AstNil;
+        for form in body {
+            result = self.eval(form, env);
+        }
+        result
+    }
+    /// `` `form ``: symbols defined in this namespace get qualified with it,
+    /// `x#` becomes the same fresh symbol throughout, and `~x` and `~@xs`
+    /// are evaluated.
+    fn syntax_quote(
+        &mut self,
+        form: &AstVal,
+        env: &mut Env,
+        gensyms: &mut HashMap<String, AstVal>,
+    ) -> AstVal {
+        match form {
+            AstVal::AstSymbol(s) if s.ends_with('#') && s.len() > 1 => {
+                let prefix = format!("{}__", &s[..s.len() - 1]);
+                if !gensyms.contains_key(s) {
+                    let g = self.gensym(&prefix);
+                    gensyms.insert(s.clone(), AstVal::AstSymbol(format!("{}__auto__", g)));
+                }
+                gensyms[s].clone()
+            }
+            AstVal::AstSymbol(s) => match &self.ns {
+                Some(ns) if !s.contains('/') && self.defs.borrow().contains_key(s) => {
+                    AstVal::AstSymbol(format!("{}/{}", ns, s))
+                }
+                _ => form.clone(),
+            },
+            AstVal::AstList(v) if v.len() == 2 && v[0] == AstVal::AstSymbol("~".to_string()) => {
+                self.eval(&v[1], env)
+            }
+            AstVal::AstList(v) | AstVal::AstVec(v) => {
+                let mut result = Vec::new();
+                for item in v {
+                    match item {
+                        AstVal::AstList(u)
+                            if u.len() == 2 && u[0] == AstVal::AstSymbol("~@".to_string()) =>
+                        {
+                            let spliced = self.eval(&u[1], env);
+                            result.extend(items(&spliced));
+                        }
+                        _ => result.push(self.syntax_quote(item, env, gensyms)),
+                    }
+                }
+                match form {
+                    AstVal::AstList(_) => AstVal::AstList(result),
+                    _ => AstVal::AstVec(result),
+                }
+            }
+            AstVal::AstMap(v) => AstVal::AstMap(
+                v.iter()
+                    .map(|(k, item)| {
+                        (
+                            self.syntax_quote(k, env, gensyms),
+                            self.syntax_quote(item, env, gensyms),
+                        )
+                    })
+                    .collect(),
+            ),
+            _ => form.clone(),
+        }
+    }
+    pub fn eval(&mut self, form: &AstVal, env: &mut Env) -> AstVal {
+        let v = match form {
+            AstVal::AstSymbol(s) => return self.lookup(s, env),
+            AstVal::AstVec(v) => {
+                return AstVal::AstVec(v.iter().map(|item| self.eval(item, env)).collect())
+            }
+            AstVal::AstMap(v) => {
+                return AstVal::AstMap(
+                    v.iter()
+                        .map(|(k, item)| (self.eval(k, env), self.eval(item, env)))
+                        .collect(),
+                )
+            }
+            AstVal::AstList(v) if !v.is_empty() => v,
+            _ => return form.clone(),
+        };
+        if let AstVal::AstSymbol(s) = &v[0] {
+            match s.as_str() {
+                "quote" | "'" => return v[1].clone(),
+                "`" => return self.syntax_quote(&v[1], env, &mut HashMap::new()),
+                "do" => return self.eval_body(&v[1..], env),
+                "if" => {
+                    let test = self.eval(&v[1], env);
+                    return match (truthy(&test), v.get(3)) {
+                        (true, _) => self.eval(&v[2], env),
+                        (false, Some(e)) => self.eval(e, env),
+                        (false, None) => AstVal::AstNil,
+                    };
+                }
+                "let" | "let*" | "loop" => {
+                    let bindings = items(&v[1]);
+                    let depth = env.len();
+                    for pair in bindings.chunks(2) {
+                        let value = self.eval(&pair[1], env);
+                        self.bind(&pair[0], value, env);
+                    }
+                    let mut result = self.eval_body(&v[2..], env);
+                    if s == "loop" {
+                        while let Some(args) = self.recur.take() {
+                            env.truncate(depth);
+                            for (pair, value) in bindings.chunks(2).zip(args) {
+                                self.bind(&pair[0], value, env);
+                            }
+                            result = self.eval_body(&v[2..], env);
+                        }
+                    }
+                    env.truncate(depth);
+                    return result;
+                }
+                "recur" => {
+                    let args = v[1..].iter().map(|item| self.eval(item, env)).collect();
+                    self.recur = Some(args);
+                    return AstVal::AstNil;
+                }
+                "fn" | "fn*" => {
+                    let (name, rest) = match &v[1] {
+                        AstVal::AstSymbol(name) => (Some(name.clone()), &v[2..]),
+                        _ => (None, &v[1..]),
+                    };
+                    let lambda = self.lambda(name, rest, env);
+                    return AstVal::AstFn(Rc::new(Function::Lambda(lambda)));
+                }
+                "def" => {
+                    let value = match v.last() {
+                        Some(value) if v.len() > 2 => self.eval(value, env),
+                        _ => AstVal::AstNil,
+                    };
+                    self.defs.borrow_mut().insert(v[1].to_string(), value);
+                    return v[1].clone();
+                }
+                _ if !env.iter().any(|(name, _)| name == s) => {
+                    if let Some(mac) = self.find_macro(s) {
+                        let expanded = self.expand(&mac, form, &[]);
+                        return self.eval(&expanded, env);
+                    }
+                    if let Some(expanded) = expand_core_macro(form, false, &mut |p| self.gensym(p))
+                    {
+                        return self.eval(&expanded, env);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let f = self.eval(&v[0], env);
+        let args = v[1..].iter().map(|item| self.eval(item, env)).collect();
+        self.apply(&f, args)
+    }
+    pub fn apply(&mut self, f: &AstVal, args: Vec<AstVal>) -> AstVal {
+        match f {
+            AstVal::AstFn(function) => match &**function {
+                Function::Builtin(name) => self.builtin(name, args),
+                Function::Lambda(l) => self.call_lambda(f, l, args),
+            },
+            AstVal::AstKeyword(_) | AstVal::AstSymbol(_) => {
+                let default = args.get(1).cloned().unwrap_or(AstVal::AstNil);
+                get(&args[0], f, default)
+            }
+            AstVal::AstMap(_) | AstVal::AstVec(_) => {
+                let default = args.get(1).cloned().unwrap_or(AstVal::AstNil);
+                get(f, &args[0], default)
+            }
+            _ => panic!("{} cannot be cast to IFn", pr_str(f)),
+        }
+    }
+    fn call_lambda(&mut self, f: &AstVal, l: &Lambda, mut args: Vec<AstVal>) -> AstVal {
+        let arity = l
+            .arities
+            .iter()
+            .find(|a| a.rest.is_none() && a.parameters.len() == args.len())
+            .or_else(|| {
+                l.arities
+                    .iter()
+                    .find(|a| a.rest.is_some() && a.parameters.len() <= args.len())
+            });
+        let arity = match arity {
+            Some(arity) => arity,
+            None => {
+                let n = if l.is_macro {
+                    args.len() - 2
+                } else {
+                    args.len()
+                };
+                arity_error(l.name.as_deref().unwrap_or("fn"), n)
+            }
+        };
+        let outer = std::mem::replace(&mut self.defs, l.defs.clone());
+        let outer_ns = std::mem::replace(&mut self.ns, l.ns.clone());
+        let mut env = l.env.clone();
+        if let Some(name) = &l.name {
+            env.push((name.clone(), f.clone()));
+        }
+        let depth = env.len();
+        let mut rest = seq(args.split_off(arity.parameters.len()));
+        loop {
+            for (p, a) in arity.parameters.iter().zip(args) {
+                self.bind(p, a, &mut env);
+            }
+            if let Some(r) = &arity.rest {
+                self.bind(r, rest.clone(), &mut env);
+            }
+            let result = self.eval_body(&arity.body, &mut env);
+            match self.recur.take() {
+                Some(mut recur_args) => {
+                    env.truncate(depth);
+                    if arity.rest.is_some() {
+                        rest = recur_args.pop().unwrap_or(AstVal::AstNil);
+                    }
+                    args = recur_args;
+                }
+                None => {
+                    self.defs = outer;
+                    self.ns = outer_ns;
+                    return result;
+                }
+            }
+        }
+    }
+    fn builtin(&mut self, name: &str, args: Vec<AstVal>) -> AstVal {
+        let arg = |i: usize| args.get(i).cloned().unwrap_or(AstVal::AstNil);
+        match name {
+            "list" => AstVal::AstList(args),
+            "list*" => {
+                let mut v = args[..args.len() - 1].to_vec();
+                v.extend(items(&args[args.len() - 1]));
+                AstVal::AstList(v)
+            }
+            "cons" => {
+                let mut v = vec![arg(0)];
+                v.extend(items(&arg(1)));
+                AstVal::AstList(v)
+            }
+            "concat" => AstVal::AstList(args.iter().flat_map(items).collect()),
+            "seq" => seq(items(&arg(0))),
+            "first" => items(&arg(0)).first().cloned().unwrap_or(AstVal::AstNil),
+            "second" => items(&arg(0)).get(1).cloned().unwrap_or(AstVal::AstNil),
+            "rest" => AstVal::AstList(items(&arg(0)).into_iter().skip(1).collect()),
+            "next" => seq(items(&arg(0)).into_iter().skip(1).collect()),
+            "last" => items(&arg(0)).pop().unwrap_or(AstVal::AstNil),
+            "butlast" => {
+                let mut v = items(&arg(0));
+                v.pop();
+                seq(v)
+            }
+            "nth" => match (items(&arg(0)).get(number(&arg(1)) as usize), args.get(2)) {
+                (Some(item), _) => item.clone(),
+                (None, Some(default)) => default.clone(),
+                (None, None) => panic!("Index out of bounds"),
+            },
+            "count" => AstVal::AstInt(items(&arg(0)).len() as i64),
+            "empty?" => AstVal::AstBool(items(&arg(0)).is_empty()),
+            "conj" => match arg(0) {
+                AstVal::AstVec(mut v) => {
+                    v.extend(args[1..].iter().cloned());
+                    AstVal::AstVec(v)
+                }
+                AstVal::AstMap(mut v) => {
+                    for pair in &args[1..] {
+                        let pair = items(pair);
+                        v.retain(|(k, _)| *k != pair[0]);
+                        v.push((pair[0].clone(), pair[1].clone()));
+                    }
+                    AstVal::AstMap(v)
+                }
+                l => {
+                    let mut v: Vec<AstVal> = args[1..].iter().rev().cloned().collect();
+                    v.extend(items(&l));
+                    AstVal::AstList(v)
+                }
+            },
+            "vector" => AstVal::AstVec(args),
+            "vec" => AstVal::AstVec(items(&arg(0))),
+            "hash-map" => AstVal::AstMap(
+                args.chunks(2)
+                    .map(|p| (p[0].clone(), p[1].clone()))
+                    .collect(),
+            ),
+            "get" => get(&arg(0), &arg(1), arg(2)),
+            "assoc" => {
+                let mut m = match arg(0) {
+                    AstVal::AstMap(v) => v,
+                    _ => Vec::new(),
+                };
+                for pair in args[1..].chunks(2) {
+                    m.retain(|(k, _)| *k != pair[0]);
+                    m.push((pair[0].clone(), pair[1].clone()));
+                }
+                AstVal::AstMap(m)
+            }
+            "keys" => seq(items(&arg(0)).iter().map(|p| items(p)[0].clone()).collect()),
+            "vals" => seq(items(&arg(0)).iter().map(|p| items(p)[1].clone()).collect()),
+            "contains?" => AstVal::AstBool(match arg(0) {
+                AstVal::AstMap(v) => v.iter().any(|(k, _)| *k == arg(1)),
+                AstVal::AstVec(v) => (number(&arg(1)) as usize) < v.len(),
+                _ => false,
+            }),
+            "symbol" => AstVal::AstSymbol(args.iter().map(to_str).collect::<Vec<_>>().join("/")),
+            "keyword" => AstVal::AstKeyword(args.iter().map(to_str).collect::<Vec<_>>().join("/")),
+            "name" => match arg(0) {
+                AstVal::AstSymbol(s) | AstVal::AstKeyword(s) => {
+                    AstVal::AstString(s.rsplit('/').next().unwrap().to_string())
+                }
+                a => AstVal::AstString(to_str(&a)),
+            },
+            "str" => AstVal::AstString(args.iter().map(to_str).collect()),
+            "pr-str" => AstVal::AstString(args.iter().map(pr_str).collect::<Vec<_>>().join(" ")),
+            "println" => {
+                println!("{}", args.iter().map(to_str).collect::<Vec<_>>().join(" "));
+                AstVal::AstNil
+            }
+            "gensym" => {
+                let prefix = match args.first() {
+                    Some(p) => to_str(p),
+                    None => "G__".to_string(),
+                };
+                self.gensym(&prefix)
+            }
+            "=" => AstVal::AstBool(args.windows(2).all(|w| w[0] == w[1])),
+            "not=" => AstVal::AstBool(!args.windows(2).all(|w| w[0] == w[1])),
+            "not" => AstVal::AstBool(!truthy(&arg(0))),
+            "nil?" => AstVal::AstBool(matches!(arg(0), AstVal::AstNil)),
+            "some?" => AstVal::AstBool(!matches!(arg(0), AstVal::AstNil)),
+            "symbol?" => AstVal::AstBool(matches!(arg(0), AstVal::AstSymbol(_))),
+            "keyword?" => AstVal::AstBool(matches!(arg(0), AstVal::AstKeyword(_))),
+            "string?" => AstVal::AstBool(matches!(arg(0), AstVal::AstString(_))),
+            "number?" => AstVal::AstBool(matches!(arg(0), AstVal::AstInt(_) | AstVal::AstFloat(_))),
+            "list?" | "seq?" => AstVal::AstBool(matches!(arg(0), AstVal::AstList(_))),
+            "vector?" => AstVal::AstBool(matches!(arg(0), AstVal::AstVec(_))),
+            "map?" => AstVal::AstBool(matches!(arg(0), AstVal::AstMap(_))),
+            "coll?" => AstVal::AstBool(matches!(
+                arg(0),
+                AstVal::AstList(_) | AstVal::AstVec(_) | AstVal::AstMap(_)
+            )),
+            "fn?" => AstVal::AstBool(matches!(arg(0), AstVal::AstFn(_))),
+            "+" | "-" | "*" | "/" => arithmetic(name, &args),
+            "inc" => arithmetic("+", &[arg(0), AstVal::AstInt(1)]),
+            "dec" => arithmetic("-", &[arg(0), AstVal::AstInt(1)]),
+            "<" | ">" | "<=" | ">=" => compare(name, &args),
+            "mod" => match (arg(0), arg(1)) {
+                (AstVal::AstInt(a), AstVal::AstInt(b)) => AstVal::AstInt(a.rem_euclid(b)),
+                (a, b) => AstVal::AstFloat(number(&a).rem_euclid(number(&b))),
+            },
+            "even?" => AstVal::AstBool(number(&arg(0)) as i64 % 2 == 0),
+            "odd?" => AstVal::AstBool(number(&arg(0)) as i64 % 2 != 0),
+            "zero?" => AstVal::AstBool(number(&arg(0)) == 0.0),
+            "map" => {
+                let colls: Vec<Vec<AstVal>> = args[1..].iter().map(items).collect();
+                let n = colls.iter().map(|c| c.len()).min().unwrap_or(0);
+                AstVal::AstList(
+                    (0..n)
+                        .map(|i| self.apply(&args[0], colls.iter().map(|c| c[i].clone()).collect()))
+                        .collect(),
+                )
+            }
+            "filter" => AstVal::AstList(
+                items(&arg(1))
+                    .into_iter()
+                    .filter(|item| {
+                        let keep = self.apply(&args[0], vec![item.clone()]);
+                        truthy(&keep)
+                    })
+                    .collect(),
+            ),
+            "reduce" => {
+                let mut v = items(&args[args.len() - 1]);
+                let mut acc = if args.len() == 3 {
+                    args[1].clone()
+                } else if v.is_empty() {
+                    return self.apply(&args[0], Vec::new());
+                } else {
+                    v.remove(0)
+                };
+                for item in v {
+                    acc = self.apply(&args[0], vec![acc, item]);
+                }
+                acc
+            }
+            "apply" => {
+                let mut v = args[1..args.len() - 1].to_vec();
+                v.extend(items(&args[args.len() - 1]));
+                self.apply(&args[0], v)
+            }
+            "interleave" => {
+                let colls: Vec<Vec<AstVal>> = args.iter().map(items).collect();
+                let n = colls.iter().map(|c| c.len()).min().unwrap_or(0);
+                AstVal::AstList(
+                    (0..n)
+                        .flat_map(|i| colls.iter().map(move |c| c[i].clone()))
+                        .collect(),
+                )
+            }
+            "partition" => {
+                let n = number(&arg(0)) as usize;
+                AstVal::AstList(
+                    items(&arg(1))
+                        .chunks(n)
+                        .filter(|c| c.len() == n)
+                        .map(|c| AstVal::AstList(c.to_vec()))
+                        .collect(),
+                )
+            }
+            "reverse" => AstVal::AstList(items(&arg(0)).into_iter().rev().collect()),
+            "into" => self.builtin(
+                "conj",
+                std::iter::once(arg(0)).chain(items(&arg(1))).collect(),
+            ),
+            "identity" => arg(0),
+            "range" => {
+                let (start, end) = match args.len() {
+                    1 => (0, number(&arg(0)) as i64),
+                    _ => (number(&arg(0)) as i64, number(&arg(1)) as i64),
+                };
+                AstVal::AstList((start..end).map(AstVal::AstInt).collect())
+            }
+            "macroexpand-1" => self.macroexpand_1(&arg(0)),
+            "macroexpand" => {
+                let mut form = arg(0);
+                loop {
+                    let expanded = self.macroexpand_1(&form);
+                    if expanded == form {
+                        return form;
+                    }
+                    form = expanded;
+                }
+            }
+            "no_matching_clause" => panic!("No matching clause: {}", pr_str(&arg(0))),
+            _ => panic!("Unable to resolve symbol: {} in this context", name),
+        }
+    }
+}
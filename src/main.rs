@@ -1,54 +1,208 @@
-// use std::io;
-// use std::io::{BufRead, Write};
-mod syntax;
-use syntax::syntax;
-mod translate;
-use translate::translate;
+// The runtime of the programs compiled, which the REPL evaluates with too.
+extern crate clojure_rust_core;
 mod ast;
+mod build;
+mod cli;
+mod diagnostic;
+mod eval;
+mod fmt;
+mod harness;
+mod interpreter;
+mod mangle;
+mod namespace;
+mod project;
 mod reader;
-use reader::RawReader;
+mod repl;
+mod syntax;
+mod translate;
+use build::{cargo, cargo_build, compile_input, no_main, package, read_input, write_file};
+use cli::{Command, Emit, Options, Parsed};
+use diagnostic::Diagnostic;
+use project::Project;
 use std::env;
-use std::fs::File;
-use std::io::prelude::*;
-// TODO:REPL
-// fn repl_read_line() -> String {
-//     let stdout = io::stdout();
-//     let mut handle = stdout.lock();
-//     handle.write(b"user> ").unwrap();
-//     handle.flush().unwrap();
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
 
-//     let mut input_buffer = String::new();
-//     let stdin = io::stdin();
-//     let mut handle = stdin.lock();
-//     handle.read_line(&mut input_buffer).unwrap();
-//     format!("{}", input_buffer)
-// }
+fn compile(input: &str, options: &Options, project: &Project) {
+    let compiled = compile_input(input, options.emit, project);
+    let to_stdout = input == "-" || options.out_dir.as_deref() == Some("-");
+    for (name, code) in compiled.files {
+        if to_stdout {
+            print!("{}", code);
+            continue;
+        }
+        let path = match &options.out_dir {
+            Some(out) => Path::new(out).join(&name),
+            None => compiled.dir.join(&name),
+        };
+        if path == Path::new(input) {
+            Diagnostic::error(
+                "E0018",
+                format!(
+                    "{} would be overwritten by the Rust compiled from it",
+                    input
+                ),
+            )
+            .help("give Clojure files a .clj extension, or write the Rust elsewhere with --out-dir")
+            .raise()
+        }
+        write_file(&path, &code);
+    }
+}
 
-fn main() {
-    let mut file_name = String::new();
-    let mut index = 0;
-    for argument in env::args() {
-        if index == 1 {
-            file_name = argument;
-            break;
-        }
-        index = index + 1;
-    }
-    let mut file = File::open(&file_name).unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
+/// Generate the Cargo package of a program and build it.
+fn build(input: &str, options: &Options, project: &Project) {
+    let (dir, _) = package(input, options.out_dir.as_deref(), project);
+    cargo_build(&dir, false);
+}
 
-    let mut file = File::create(file_name.replace(".clj", ".rs")).unwrap();
-    file.write_fmt(format_args!("mod cljtype;\nuse cljtype::CljVal;\n")).unwrap();
-    let mut r = RawReader::new(contents);
-    loop {
-        match r.read() {
-            None => break,
-            Some(x) => {
-                let c = syntax(&x);
-                let t = translate(&c);
-                file.write_fmt(format_args!("{}\n", t)).unwrap();
+/// Generate the Cargo package of a program, build it and run it. Its exit
+/// code, or `None` if it could not be built.
+fn run(input: &str, options: &Options, project: &Project) -> Option<i32> {
+    let mut built = None;
+    let compiled = diagnostic::run(options.json, || {
+        let (dir, main) = package(input, options.out_dir.as_deref(), project);
+        if !main {
+            no_main(input)
+        }
+        cargo_build(&dir, true);
+        built = Some(dir)
+    });
+    match built {
+        Some(dir) if compiled => cargo(&dir, "run")
+            .args(["--quiet", "--"])
+            .args(&options.program_args)
+            .status()
+            .ok()
+            .map(|status| status.code().unwrap_or(1)),
+        _ => None,
+    }
+}
+
+fn check(input: &str, project: &Project) {
+    compile_input(input, Emit::Rust, project);
+}
+
+/// Format a file in place, or only tell whether it is formatted. Whether it
+/// was.
+fn format_file(input: &str, check: bool) -> bool {
+    let source = read_input(input);
+    let formatted = fmt::format_source(&source.source.text);
+    if input == "-" {
+        print!("{}", formatted);
+        return true;
+    }
+    if formatted == source.source.text {
+        return true;
+    }
+    if check {
+        println!("{}", input);
+    } else {
+        write_file(Path::new(input), &formatted);
+    }
+    !check
+}
+
+/// Create a project with a hello world, and its Cargo package.
+fn new_project(dir: &str) {
+    let root = Path::new(dir);
+    if root.exists() {
+        Diagnostic::error("E0018", format!("destination {} already exists", dir)).raise()
+    }
+    let main = root.join("src").join("main.clj");
+    write_file(&main, "(defn main []\n  (println \"Hello, world!\"))\n");
+    write_file(&root.join(".gitignore"), "/target\n");
+    let config = root.join(project::FILE);
+    write_file(&config, "{:main main\n :source-paths [\"src\"]}\n");
+    package(&main.display().to_string(), None, &project::read(&config));
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut options = match cli::parse(&args) {
+        Ok(Parsed::Options(options)) => options,
+        Ok(Parsed::Help(command)) => {
+            // Not println!, which panics when piped into `head`.
+            let _ = writeln!(io::stdout(), "{}", cli::help(command));
+            return;
+        }
+        Ok(Parsed::Version) => {
+            println!("clojure-rust {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(message) => {
+            eprintln!("error: {}\n\nFor more information, try '--help'.", message);
+            process::exit(2);
+        }
+    };
+    let json = options.json;
+    let mut project = Project::default();
+    let mut main = None;
+    if !diagnostic::run(json, || {
+        project = Project::find();
+        main = project.main_file();
+    }) {
+        process::exit(1);
+    }
+    diagnostic::set_warnings_as_errors(project.warnings_as_errors);
+    project.lib |= options.lib;
+    let compiles = matches!(
+        options.command,
+        Command::Compile | Command::Check | Command::Build | Command::Run
+    );
+    if compiles && options.inputs.is_empty() {
+        match main {
+            Some(main) => options.inputs.push(main.display().to_string()),
+            None => {
+                eprintln!(
+                    "error: no input files, and no :main in a {} here\n\nFor more information, try '--help'.",
+                    project::FILE
+                );
+                process::exit(2);
             }
         }
     }
+    let mut ok = true;
+    match options.command {
+        Command::Compile => {
+            for input in &options.inputs {
+                ok &= diagnostic::run(json, || compile(input, &options, &project));
+            }
+        }
+        Command::Check => {
+            for input in &options.inputs {
+                ok &= diagnostic::run(json, || check(input, &project));
+            }
+        }
+        Command::Build => {
+            ok = diagnostic::run(json, || build(&options.inputs[0], &options, &project))
+        }
+        Command::Run => match run(&options.inputs[0], &options, &project) {
+            Some(code) => process::exit(code),
+            None => ok = false,
+        },
+        Command::Repl => repl::repl(json, &project.source_paths),
+        Command::Fmt => {
+            for input in &options.inputs {
+                let mut formatted = true;
+                ok &= diagnostic::run(json, || formatted = format_file(input, options.check));
+                ok &= formatted;
+            }
+        }
+        Command::New => ok = diagnostic::run(json, || new_project(&options.inputs[0])),
+        Command::Test => {
+            let paths = match options.inputs.is_empty() {
+                true => harness::DEFAULT_PATHS
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect(),
+                false => options.inputs.clone(),
+            };
+            ok = harness::test(&paths, json);
+        }
+    }
+    if !ok {
+        process::exit(1);
+    }
 }
@@ -0,0 +1,193 @@
+/// How the characters of Clojure symbols that are not allowed in Rust
+/// identifiers are spelled there, the way Clojure munges them for the JVM.
+const CHAR_MAP: &[(char, &str)] = &[
+    (':', "_COLON_"),
+    ('+', "_PLUS_"),
+    ('>', "_GT_"),
+    ('<', "_LT_"),
+    ('=', "_EQ_"),
+    ('~', "_TILDE_"),
+    ('!', "_BANG_"),
+    ('@', "_CIRCA_"),
+    ('#', "_SHARP_"),
+    ('\'', "_SINGLEQUOTE_"),
+    ('"', "_DOUBLEQUOTE_"),
+    ('%', "_PERCENT_"),
+    ('^', "_CARET_"),
+    ('&', "_AMPERSAND_"),
+    ('*', "_STAR_"),
+    ('|', "_BAR_"),
+    ('{', "_LBRACE_"),
+    ('}', "_RBRACE_"),
+    ('[', "_LBRACK_"),
+    (']', "_RBRACK_"),
+    ('/', "_SLASH_"),
+    ('\\', "_BSLASH_"),
+    ('?', "_QMARK_"),
+    ('.', "_DOT_"),
+    ('$', "_DOLLAR_"),
+    ('_', "_USCORE_"),
+    ('-', "_DASH_"),
+];
+
+/// Rust keywords, strict and reserved, of every edition.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Marks a name that would otherwise be a Rust keyword.
+const KEYWORD_SUFFIX: &str = "_KW_";
+
+/// The Rust identifier for a Clojure name: `valid?` is `valid_QMARK_`,
+/// `new-user` is `new_user`, `new_user` is `new_USCORE_user` and `type` is
+/// `type_KW_`. Letters of any script are kept, other characters become `_U`
+/// and their code point in hex. No two names have the same identifier: a `-`
+/// is only a bare `_` where that can not be read as the start of an escape,
+/// nor next to another bare `_`, which leaves names like `args__` to the
+/// compiler.
+pub fn mangle(name: &str) -> String {
+    // Built from the end, as how a `-` is spelled depends on what follows it.
+    let mut s = String::new();
+    for c in name.chars().rev() {
+        let bare = c == '-'
+            && s != "KW_"
+            && unescape(&format!("_{}", s)).is_none()
+            && !(s.starts_with('_') && unescape(&s).is_none());
+        match CHAR_MAP.iter().find(|(k, _)| *k == c) {
+            _ if bare => s.insert(0, '_'),
+            Some((_, escape)) => s.insert_str(0, escape),
+            None if c.is_alphanumeric() => s.insert(0, c),
+            None => s.insert_str(0, &format!("_U{:X}_", c as u32)),
+        }
+    }
+    if s == "_" {
+        s = String::from("_DASH_");
+    }
+    if KEYWORDS.contains(&s.as_str()) {
+        s.push_str(KEYWORD_SUFFIX);
+    }
+    s
+}
+
+/// The character the escape `s` starts with stands for, and how long the
+/// escape is.
+fn unescape(s: &str) -> Option<(char, usize)> {
+    if let Some((c, escape)) = CHAR_MAP.iter().find(|(_, escape)| s.starts_with(escape)) {
+        return Some((*c, escape.len()));
+    }
+    let hex = s.strip_prefix("_U")?;
+    let end = hex.find('_').filter(|end| *end > 0)?;
+    u32::from_str_radix(&hex[..end], 16)
+        .ok()
+        .and_then(std::char::from_u32)
+        .map(|c| (c, end + 3))
+}
+
+/// The Clojure name a Rust identifier was mangled from. As with Clojure,
+/// every `_` that is not part of an escape is taken to be a `-`.
+pub fn demangle(name: &str) -> String {
+    let name = match name.strip_suffix(KEYWORD_SUFFIX) {
+        Some(keyword) if KEYWORDS.contains(&keyword) => keyword,
+        _ => name,
+    };
+    let mut s = String::new();
+    let mut rest = name;
+    while let Some(i) = rest.find('_') {
+        s.push_str(&rest[..i]);
+        rest = &rest[i..];
+        match unescape(rest) {
+            Some((c, len)) => {
+                s.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                s.push('-');
+                rest = &rest[1..];
+            }
+        }
+    }
+    s.push_str(rest);
+    s
+}
+
+/// How to name the function or var at a Rust path in a message, as Clojure
+/// would: `crate::my::util::valid_QMARK_` is `my.util/valid?`.
+pub fn demangle_path(path: &str) -> String {
+    let segments: Vec<&str> = path.split("::").filter(|s| *s != "crate").collect();
+    match segments.split_last() {
+        Some((name, [])) => demangle(name),
+        Some((name, ns)) => {
+            let ns: Vec<String> = ns.iter().map(|s| demangle(s)).collect();
+            format!("{}/{}", ns.join("."), demangle(name))
+        }
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: &[&str] = &[
+        "valid?",
+        "new-user",
+        "new_user",
+        "_",
+        "-",
+        "a-b_c",
+        "->User",
+        "map->User",
+        "type",
+        "type-KW-",
+        "f-GT-",
+        "-GT-",
+        "-U41-",
+        "-Uab-",
+        "x-DASH-",
+        "-USCORE-",
+        "*1",
+        "名前",
+        "a--b",
+    ];
+
+    #[test]
+    fn mangles_to_identifiers() {
+        assert_eq!(mangle("valid?"), "valid_QMARK_");
+        assert_eq!(mangle("new-user"), "new_user");
+        assert_eq!(mangle("new_user"), "new_USCORE_user");
+        assert_eq!(mangle("_"), "_USCORE_");
+        assert_eq!(mangle("type"), "type_KW_");
+        assert_eq!(mangle("a b"), "a_U20_b");
+        assert_eq!(mangle("a->b"), "a__GT_b");
+        assert_eq!(mangle("args--"), "args_DASH__");
+        for name in NAMES {
+            let s = mangle(name);
+            assert!(s != "_" && s.chars().all(|c| c == '_' || c.is_alphanumeric()));
+        }
+    }
+
+    #[test]
+    fn demangle_reverses_mangle() {
+        for name in NAMES {
+            assert_eq!(demangle(&mangle(name)), *name);
+        }
+        assert_eq!(
+            demangle_path("crate::my::util_lib::valid_QMARK_"),
+            "my.util-lib/valid?"
+        );
+    }
+
+    #[test]
+    fn names_do_not_collide() {
+        let mut seen = std::collections::HashMap::new();
+        for name in NAMES {
+            if let Some(other) = seen.insert(mangle(name), name) {
+                panic!("{} and {} are both {}", name, other, mangle(name));
+            }
+        }
+    }
+}
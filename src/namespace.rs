@@ -0,0 +1,347 @@
+use ast::AstVal;
+use diagnostic::{catch, in_source, Diagnostic, Source, Span};
+use mangle::mangle;
+use reader::RawReader;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use syntax::{parse_ns, Exports, Extension, Globals, NsForm, Protocol};
+use translate::{crate_prelude, translate_dispatchers, translate_init, translate_signatures};
+
+/// The Rust module path of a namespace, without the leading `crate::`:
+/// `my.util-lib` is `my::util_lib`, and `my.type` is `my::type_KW_`.
+pub fn module_path(ns: &str) -> String {
+    ns.split('.').map(mangle).collect::<Vec<_>>().join("::")
+}
+
+/// Where the source of a namespace is, under the root of the source tree:
+/// `my.util-lib` is in `my/util_lib.clj`.
+pub fn source_path(root: &Path, ns: &str) -> PathBuf {
+    let mut path = root.to_path_buf();
+    for segment in ns.split('.') {
+        path.push(segment.replace('-', "_"));
+    }
+    path.set_extension("clj");
+    path
+}
+
+/// The forms of a source file, with where each of them is.
+pub struct SourceFile {
+    pub source: Arc<Source>,
+    pub forms: Vec<AstVal>,
+    pub spans: Vec<Span>,
+}
+
+impl SourceFile {
+    /// Compile each form with `f`, so that the diagnostics it raises point at
+    /// the form.
+    pub fn compile_each<F: FnMut(&AstVal) -> String>(&self, mut f: F) -> Vec<String> {
+        self.forms
+            .iter()
+            .zip(&self.spans)
+            .map(|(form, span)| in_source(&self.source, Some(*span), || f(form)))
+            .collect()
+    }
+    /// Where the first form is, which for a namespace is its `ns` form.
+    pub fn first_span(&self) -> Option<Span> {
+        self.forms
+            .iter()
+            .zip(&self.spans)
+            .find(|(form, _)| !matches!(form, AstVal::AstCommentLine(_)))
+            .map(|(_, span)| *span)
+    }
+}
+
+pub fn read_forms(path: &Path) -> SourceFile {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            Diagnostic::error("E0017", format!("can not open {}: {}", path.display(), e)).raise()
+        }
+    };
+    let mut contents = String::new();
+    if let Err(e) = file.read_to_string(&mut contents) {
+        Diagnostic::error("E0017", format!("can not read {}: {}", path.display(), e)).raise()
+    }
+    read_source(&path.display().to_string(), contents)
+}
+
+/// The forms of source text that is not in a file, like stdin, or that is
+/// already read, named `name` in diagnostics.
+pub fn read_source(name: &str, contents: String) -> SourceFile {
+    let source = Arc::new(Source {
+        path: name.to_string(),
+        text: contents.clone(),
+    });
+    let mut r = RawReader::new(contents);
+    let mut forms = Vec::new();
+    let mut spans = Vec::new();
+    loop {
+        let start = r.position();
+        let end = start + 1;
+        match in_source(&source, Some(Span { start, end }), || r.read_spanned()) {
+            Some((form, span)) => {
+                forms.push(form);
+                spans.push(span);
+            }
+            None => break,
+        }
+    }
+    SourceFile {
+        source,
+        forms,
+        spans,
+    }
+}
+
+/// The `ns` form a file starts with, if any.
+pub fn find_ns(forms: &[AstVal]) -> Option<NsForm> {
+    forms
+        .iter()
+        .find(|form| !matches!(form, AstVal::AstCommentLine(_)))
+        .and_then(parse_ns)
+}
+
+/// Compiles a namespace and, first, everything it requires, each into the
+/// code of its own Rust module, compiling each form with `compile`, which
+/// is given the namespace of the form too.
+struct Loader<F> {
+    /// The roots of the source trees namespaces are looked for in, in turn.
+    roots: Vec<PathBuf>,
+    loaded: HashMap<String, Exports>,
+    /// The namespaces being loaded, each requiring the next one.
+    loading: Vec<String>,
+    /// The Rust code of each module, by module path.
+    modules: BTreeMap<String, String>,
+    /// The protocols each module defines, the functions of which are
+    /// generated once all the namespaces are loaded.
+    protocols: Vec<(String, Vec<Protocol>)>,
+    /// The protocols extended to types in all the namespaces.
+    extensions: Vec<Extension>,
+    compile: F,
+}
+
+impl<F: FnMut(&str, &AstVal, &mut Globals) -> String> Loader<F> {
+    fn load(&mut self, ns: &NsForm, file: &SourceFile) {
+        self.loading.push(ns.name.clone());
+        in_source(&file.source, file.first_span(), || self.load_requires(ns));
+        let mut globals = Globals::new();
+        for r in &ns.requires {
+            globals.require(&r.ns, self.loaded[&r.ns].clone());
+        }
+        in_source(&file.source, None, || globals.prescan(&file.forms));
+        let mut code = String::from("use clojure_rust_core::*;\n");
+        let compile = &mut self.compile;
+        for rust in file.compile_each(|x| compile(&ns.name, x, &mut globals)) {
+            code.push_str(&rust);
+            code.push('\n');
+        }
+        code.push_str(&translate_signatures(globals.signatures()));
+        if globals.has_init() {
+            code.push_str(&translate_init(globals.inits(), &globals.required_inits()));
+        }
+        let module = module_path(&ns.name);
+        self.protocols
+            .push((module.clone(), globals.protocols().to_vec()));
+        self.extensions
+            .extend(globals.extensions().iter().map(|e| Extension {
+                module: format!("crate::{}", module),
+                ..e.clone()
+            }));
+        self.loaded.insert(
+            ns.name.clone(),
+            globals.exports(&format!("crate::{}", module)),
+        );
+        self.modules.insert(module, code);
+        self.loading.pop();
+    }
+    /// Load what a namespace requires that is not loaded yet.
+    fn load_requires(&mut self, ns: &NsForm) {
+        for r in &ns.requires {
+            if self.loaded.contains_key(&r.ns) {
+                continue;
+            }
+            if self.loading.contains(&r.ns) {
+                let cycle: Vec<String> = self
+                    .loading
+                    .iter()
+                    .map(|name| {
+                        if *name == r.ns {
+                            format!("[ {} ]", name)
+                        } else {
+                            name.clone()
+                        }
+                    })
+                    .collect();
+                Diagnostic::error(
+                    "E0010",
+                    format!("Cyclic load dependency: {}->[ {} ]", cycle.join("->"), r.ns),
+                )
+                .culprit(&r.ns, "required here")
+                .raise();
+            }
+            let found = self
+                .roots
+                .iter()
+                .map(|root| source_path(root, &r.ns))
+                .find(|path| path.exists());
+            let path = match found {
+                Some(path) => path,
+                None => Diagnostic::error(
+                    "E0014",
+                    format!(
+                        "Could not locate {} on the source path",
+                        source_path(&self.roots[0], &r.ns).display()
+                    ),
+                )
+                .culprit(&r.ns, "required here")
+                .raise(),
+            };
+            let required_file = read_forms(&path);
+            match find_ns(&required_file.forms) {
+                Some(required) if required.name == r.ns => self.load(&required, &required_file),
+                _ => Diagnostic::error(
+                    "E0014",
+                    format!("{} does not define namespace {}", path.display(), r.ns),
+                )
+                .culprit(&r.ns, "required here")
+                .raise(),
+            }
+        }
+    }
+}
+
+/// Compile the namespace of the file at `path` with everything it requires,
+/// found from the root of the source tree its name implies, and then in
+/// `source_paths`, each form with `compile`. Each namespace becomes a module
+/// file, and the root of the tree a `main.rs` calling its `main` if it has
+/// one and is not made a `lib`, or else a `lib.rs`. The files are returned with their paths from the root.
+pub fn compile_tree<F>(
+    path: &Path,
+    ns: &NsForm,
+    file: &SourceFile,
+    source_paths: &[PathBuf],
+    lib: bool,
+    compile: F,
+) -> Vec<(PathBuf, String)>
+where
+    F: FnMut(&str, &AstVal, &mut Globals) -> String,
+{
+    let root = source_root(path, &ns.name);
+    if source_path(&root, &ns.name) != path {
+        let message = format!(
+            "namespace {} should be in {}",
+            ns.name,
+            source_path(Path::new(""), &ns.name).display()
+        );
+        in_source(&file.source, file.first_span(), || {
+            Diagnostic::error("E0014", message)
+                .culprit(&ns.name, "named here")
+                .raise()
+        });
+    }
+    let mut roots = vec![root];
+    roots.extend(source_paths.iter().cloned());
+    let mut loader = Loader {
+        roots,
+        loaded: HashMap::new(),
+        loading: Vec::new(),
+        modules: BTreeMap::new(),
+        protocols: Vec::new(),
+        extensions: Vec::new(),
+        compile,
+    };
+    loader.load(ns, file);
+    for (module, protocols) in &loader.protocols {
+        let dispatchers = translate_dispatchers(protocols, &loader.extensions);
+        loader
+            .modules
+            .get_mut(module)
+            .unwrap()
+            .push_str(&dispatchers);
+    }
+
+    // Parent modules of the namespaces only declare their children.
+    let mut children: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for module in loader.modules.keys() {
+        let mut parent = module.as_str();
+        while let Some(i) = parent.rfind("::") {
+            let child = &parent[i + 2..];
+            parent = &parent[..i];
+            children
+                .entry(parent.to_string())
+                .or_default()
+                .insert(child.to_string());
+        }
+        children.entry(module.clone()).or_default();
+    }
+    let main = loader.loaded[&ns.name].defines("main") && !lib;
+    let root_module = if main { "main" } else { "lib" };
+    let mut files = Vec::new();
+    let mut crate_root = crate_prelude(!main);
+    for (module, children) in &children {
+        let mut contents = loader.modules.get(module).cloned().unwrap_or_default();
+        for child in children {
+            contents.push_str(&format!("pub mod {};\n", child));
+        }
+        // A namespace named like a crate root has its file in its directory,
+        // as cargo would take `main.rs` or `lib.rs` for another target.
+        let file_path = if module == "main" || module == "lib" {
+            crate_root.push_str(&format!("#[path = \"{}/mod.rs\"]\n", module));
+            PathBuf::from(module).join("mod.rs")
+        } else {
+            PathBuf::from(format!("{}.rs", module.replace("::", "/")))
+        };
+        files.push((file_path, contents));
+        if !module.contains("::") {
+            crate_root.push_str(&format!("pub mod {};\n", module));
+        }
+    }
+    if main {
+        crate_root.push_str(&format!(
+            "fn main() {{\n    {}::main();\n}}\n",
+            module_path(&ns.name)
+        ));
+    }
+    files.push((PathBuf::from(format!("{}.rs", root_module)), crate_root));
+    files
+}
+
+/// Load what `ns` requires from the source trees at `roots`, unless it is in
+/// `loaded` already, compiling each form with `compile` as `compile_tree`
+/// does, but without making files of them. What is loaded is added to
+/// `loaded`.
+pub fn load_required<F>(
+    roots: &[PathBuf],
+    ns: &NsForm,
+    loaded: &mut HashMap<String, Exports>,
+    compile: F,
+) where
+    F: FnMut(&str, &AstVal, &mut Globals) -> String,
+{
+    let mut loader = Loader {
+        roots: roots.to_vec(),
+        loaded: std::mem::take(loaded),
+        loading: vec![ns.name.clone()],
+        modules: BTreeMap::new(),
+        protocols: Vec::new(),
+        extensions: Vec::new(),
+        compile,
+    };
+    let result = catch(|| loader.load_requires(ns));
+    *loaded = loader.loaded;
+    if let Err(d) = result {
+        d.raise()
+    }
+}
+
+/// The root of the source tree of the namespace `ns` in the file at `path`.
+pub fn source_root(path: &Path, ns: &str) -> PathBuf {
+    let mut root = path.to_path_buf();
+    for _ in ns.split('.') {
+        root.pop();
+    }
+    root
+}
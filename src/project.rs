@@ -0,0 +1,290 @@
+use ast::AstVal;
+use diagnostic::{in_source, Diagnostic};
+use namespace::{read_forms, source_path};
+use std::path::{Path, PathBuf};
+
+/// The file a project is configured in, at its root.
+pub const FILE: &str = "project.edn";
+
+/// The configuration of a project, a map in its `project.edn`:
+///
+/// ```clojure
+/// {:name "my-app"
+///  :main my.app
+///  :source-paths ["src" "lib"]
+///  :out-dir "target/rust"
+///  :edition "2021"
+///  :crate-type "lib"
+///  :deps {regex "1.10"
+///         serde {:version "1" :features ["derive"]}}
+///  :compiler {:opt-level 3 :warnings-as-errors true}}
+/// ```
+#[derive(Debug, Default)]
+pub struct Project {
+    /// The `project.edn` it was read from, if any. The `Cargo.toml` of a
+    /// configured project is generated from it, and that of one that is not
+    /// is only written when missing.
+    pub path: Option<PathBuf>,
+    /// The crate name, the name of the package directory by default.
+    pub name: Option<String>,
+    /// The namespace to compile when no file is given.
+    pub main: Option<String>,
+    /// Where namespaces are looked for, after the source tree of the file
+    /// compiled.
+    pub source_paths: Vec<PathBuf>,
+    /// Where the Cargo package goes.
+    pub out_dir: Option<PathBuf>,
+    pub edition: Option<String>,
+    /// The Rust dependencies of the package, with their TOML values.
+    pub deps: Vec<(String, String)>,
+    pub opt_level: Option<String>,
+    pub warnings_as_errors: bool,
+    /// Whether the package is a library, even with a `main`.
+    pub lib: bool,
+}
+
+impl Project {
+    /// The project configured in the current directory, if it is one.
+    pub fn find() -> Project {
+        let path = Path::new(FILE);
+        if path.exists() {
+            read(path)
+        } else {
+            Project::default()
+        }
+    }
+    /// The file of the main namespace, in the first source path it is in.
+    pub fn main_file(&self) -> Option<PathBuf> {
+        let main = self.main.as_ref()?;
+        let file = self
+            .source_paths
+            .iter()
+            .map(|root| source_path(root, main))
+            .find(|file| file.exists());
+        match file {
+            Some(file) => Some(file),
+            None => Diagnostic::error(
+                "E0021",
+                format!(
+                    "could not locate the main namespace {} on the source paths",
+                    main
+                ),
+            )
+            .help(format!(
+                "it should be in {} under one of them",
+                source_path(Path::new(""), main).display()
+            ))
+            .raise(),
+        }
+    }
+    /// The `Cargo.toml` of the package, named `name` unless the project
+    /// names it, depending on `runtime` and the dependencies of the project.
+    pub fn cargo_toml(&self, name: &str, runtime: &str) -> String {
+        let mut toml = format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"{}\"\n\n[dependencies]\n{}",
+            self.name.as_deref().unwrap_or(name),
+            self.edition.as_deref().unwrap_or("2021"),
+            runtime
+        );
+        for (name, value) in &self.deps {
+            toml.push_str(&format!("{} = {}\n", name, value));
+        }
+        if let Some(level) = &self.opt_level {
+            for profile in &["dev", "release"] {
+                toml.push_str(&format!("\n[profile.{}]\nopt-level = {}\n", profile, level));
+            }
+        }
+        toml.push_str("\n# Its own workspace, wherever it is.\n[workspace]\n");
+        toml
+    }
+}
+
+/// Read the configuration of a project from the `project.edn` at `path`.
+pub fn read(path: &Path) -> Project {
+    let file = read_forms(path);
+    let root = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let form = file
+        .forms
+        .iter()
+        .zip(&file.spans)
+        .find(|(form, _)| !matches!(form, AstVal::AstCommentLine(_)));
+    let (form, span) = match form {
+        Some((form, span)) => (form, Some(*span)),
+        None => (&AstVal::AstNil, None),
+    };
+    in_source(&file.source, span, || {
+        let entries = match form {
+            AstVal::AstMap(entries) => entries,
+            _ => Diagnostic::error("E0021", format!("{} should be a map", FILE)).raise(),
+        };
+        let mut project = Project {
+            path: Some(path.to_path_buf()),
+            source_paths: vec![root.join("src")],
+            ..Project::default()
+        };
+        for (key, value) in entries {
+            let key = match key {
+                AstVal::AstKeyword(k) => k.as_str(),
+                _ => bad(key, "a key of the project is a keyword"),
+            };
+            match key {
+                "name" => project.name = Some(string(value)),
+                "main" => project.main = Some(string(value)),
+                "source-paths" => {
+                    project.source_paths =
+                        items(value).iter().map(|p| root.join(string(p))).collect()
+                }
+                "out-dir" => project.out_dir = Some(root.join(string(value))),
+                "edition" => project.edition = Some(string(value)),
+                "crate-type" => {
+                    project.lib = match string(value).as_str() {
+                        "lib" => true,
+                        "bin" => false,
+                        _ => bad(value, "the crate types are lib and bin"),
+                    }
+                }
+                "deps" => {
+                    for (name, version) in map(value) {
+                        project.deps.push((string(name), toml(version)));
+                    }
+                }
+                "compiler" => {
+                    for (option, value) in map(value) {
+                        match option {
+                            AstVal::AstKeyword(k) if k == "opt-level" => {
+                                project.opt_level = Some(toml(value))
+                            }
+                            AstVal::AstKeyword(k) if k == "warnings-as-errors" => {
+                                project.warnings_as_errors = matches!(value, AstVal::AstBool(true))
+                            }
+                            _ => bad(
+                                option,
+                                "the compiler options are :opt-level and :warnings-as-errors",
+                            ),
+                        }
+                    }
+                }
+                _ => Diagnostic::error("E0021", format!("unknown key :{} in {}", key, FILE))
+                    .culprit(&format!(":{}", key), "not a key of the project")
+                    .help(
+                        ":name, :main, :source-paths, :out-dir, :edition, :crate-type, :deps and :compiler are",
+                    )
+                    .raise(),
+            }
+        }
+        project
+    })
+}
+
+fn bad(value: &AstVal, help: &str) -> ! {
+    Diagnostic::error("E0021", format!("unexpected {} in {}", value, FILE))
+        .help(help)
+        .raise()
+}
+
+/// A name or a path, as a string, symbol or keyword.
+fn string(value: &AstVal) -> String {
+    match value {
+        AstVal::AstString(s) | AstVal::AstSymbol(s) | AstVal::AstKeyword(s) => s.clone(),
+        _ => bad(value, "expected a string or a symbol"),
+    }
+}
+
+fn items(value: &AstVal) -> &[AstVal] {
+    match value {
+        AstVal::AstVec(v) | AstVal::AstList(v) => v,
+        // `[]` reads as nil.
+        AstVal::AstNil => &[],
+        _ => bad(value, "expected a vector"),
+    }
+}
+
+fn map(value: &AstVal) -> &[(AstVal, AstVal)] {
+    match value {
+        AstVal::AstMap(entries) => entries,
+        _ => bad(value, "expected a map"),
+    }
+}
+
+/// EDN data as a TOML value, for the `Cargo.toml`: maps are inline tables.
+fn toml(value: &AstVal) -> String {
+    match value {
+        AstVal::AstString(s) => format!("{:?}", s),
+        AstVal::AstInt(i) => i.to_string(),
+        AstVal::AstBool(b) => b.to_string(),
+        AstVal::AstVec(_) | AstVal::AstList(_) | AstVal::AstNil => {
+            let items: Vec<String> = items(value).iter().map(toml).collect();
+            format!("[{}]", items.join(", "))
+        }
+        AstVal::AstMap(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(k, v)| format!("{} = {}", string(k), toml(v)))
+                .collect();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        _ => bad(value, "expected a string, number, boolean, vector or map"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diagnostic::catch;
+    use std::{env, fs, process};
+
+    /// The project configured by `edn`, or the message of its error.
+    fn configure(edn: &str) -> Result<Project, String> {
+        let dir = env::temp_dir().join(format!("clojure-rust-project-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}-{}", edn.len(), FILE));
+        fs::write(&path, edn).unwrap();
+        let project = catch(|| read(&path)).map_err(|d| d.message.clone());
+        fs::remove_file(&path).unwrap();
+        project
+    }
+
+    #[test]
+    fn reads_every_key() {
+        let project = configure(
+            "; the app\n{:name \"my-app\" :main my.app :source-paths [\"src\" \"lib\"]\n \
+             :out-dir \"out\" :edition \"2018\" :crate-type \"lib\"\n \
+             :deps {regex \"1.10\" serde {:version \"1\" :features [\"derive\"]}}\n \
+             :compiler {:opt-level 3 :warnings-as-errors true}}",
+        )
+        .unwrap();
+        let root = project.path.as_ref().unwrap().parent().unwrap();
+        assert_eq!(project.main.as_deref(), Some("my.app"));
+        assert_eq!(project.source_paths, [root.join("src"), root.join("lib")]);
+        assert_eq!(project.out_dir, Some(root.join("out")));
+        assert!(project.lib && project.warnings_as_errors);
+        assert_eq!(
+            project.cargo_toml("ignored", "runtime = \"0\"\n"),
+            "[package]\nname = \"my-app\"\nversion = \"0.1.0\"\nedition = \"2018\"\n\n\
+             [dependencies]\nruntime = \"0\"\nregex = \"1.10\"\n\
+             serde = { version = \"1\", features = [\"derive\"] }\n\n\
+             [profile.dev]\nopt-level = 3\n\n[profile.release]\nopt-level = 3\n\n\
+             # Its own workspace, wherever it is.\n[workspace]\n"
+        );
+    }
+
+    #[test]
+    fn reports_what_is_not_a_project() {
+        assert_eq!(
+            configure("[:main app]").unwrap_err(),
+            "project.edn should be a map"
+        );
+        assert_eq!(
+            configure("{:mian app}").unwrap_err(),
+            "unknown key :mian in project.edn"
+        );
+        assert_eq!(
+            configure("{:crate-type \"dylib\"}").unwrap_err(),
+            "unexpected dylib in project.edn"
+        );
+        assert_eq!(
+            configure("{:compiler {:debug true}}").unwrap_err(),
+            "unexpected :debug in project.edn"
+        );
+    }
+}
@@ -1,13 +1,15 @@
 use ast::AstVal;
+use diagnostic::{Diagnostic, Span};
 use std::char::decode_utf16;
 #[derive(Debug)]
 enum ReadState {
-    EOF,
+    Eof,
     Continue(AstVal),
     Delimited(char),
 }
+/// The chars still to read, last first, and how many there were.
 #[derive(Debug, Clone)]
-pub struct RawReader(Vec<char>);
+pub struct RawReader(Vec<char>, usize);
 
 impl RawReader {
     pub fn new(s: String) -> RawReader {
@@ -17,20 +19,18 @@ impl RawReader {
         for c in v {
             match c {
                 Ok(c) => v1.push(c),
-                _ => panic!("not in utf-16"),
+                _ => Diagnostic::error("E0015", "Invalid UTF-16 in source").raise(),
             }
         }
         v1.reverse();
-        RawReader(v1)
+        let n = v1.len();
+        RawReader(v1, n)
     }
     fn len(&self) -> usize {
         self.0.len()
     }
     fn consume_char(&mut self) -> Option<char> {
-        match self.0.pop() {
-            Some(c) => Some(c),
-            None => None,
-        }
+        self.0.pop()
     }
     fn pre_read_next_char(&self) -> Option<char> {
         if self.len() == 0 {
@@ -41,63 +41,78 @@ impl RawReader {
     pub fn read(&mut self) -> Option<AstVal> {
         match read_internal(self) {
             ReadState::Continue(a) => Some(a),
-            ReadState::EOF => None,
-            _ => panic!("error"),
+            ReadState::Eof => None,
+            ReadState::Delimited(c) => {
+                let end = self.position();
+                error_at(
+                    end - 1,
+                    end,
+                    format!("Unmatched delimiter: {}", c),
+                    "unexpected",
+                )
+            }
         }
     }
+    /// How many chars have been read.
+    pub fn position(&self) -> usize {
+        self.1 - self.0.len()
+    }
+    /// Read a form, with where it is in the source.
+    pub fn read_spanned(&mut self) -> Option<(AstVal, Span)> {
+        while let Some(' ') | Some('\t') | Some('\n') = self.pre_read_next_char() {
+            self.consume_char();
+        }
+        let start = self.position();
+        let form = self.read()?;
+        Some((
+            form,
+            Span {
+                start,
+                end: self.position(),
+            },
+        ))
+    }
     fn unread_char(&mut self, ch: char) {
         self.0.push(ch);
     }
 }
 
+fn error_at(start: usize, end: usize, message: String, label: &str) -> ! {
+    Diagnostic::error("E0015", message)
+        .at(Span { start, end }, label)
+        .raise()
+}
+
 fn read_number(reader: &mut RawReader) -> AstVal {
+    let start = reader.position();
     let mut s = String::new();
-    loop {
-        match reader.pre_read_next_char() {
-            Some(next) => {
-                if (next.is_ascii_digit())
-                    | (next == '.')
-                    | (next == 'e')
-                    | (next == '-')
-                    | (next == '+')
-                {
-                    reader.consume_char();
-                    s.push(next)
-                } else {
-                    break;
-                }
-            }
-            None => {
-                break;
-            }
-        };
+    while let Some(next) = reader.pre_read_next_char() {
+        if (next.is_ascii_digit()) | (next == '.') | (next == 'e') | (next == '-') | (next == '+') {
+            reader.consume_char();
+            s.push(next)
+        } else {
+            break;
+        }
     }
-    match s.parse::<i32>() {
+    match s.parse::<i64>() {
         Ok(x) => AstVal::AstInt(x),
-        Err(_) => match s.parse::<f32>() {
+        Err(_) => match s.parse::<f64>() {
             Ok(x) => AstVal::AstFloat(x),
-            Err(_) => panic!("read number"),
+            Err(_) => error_at(
+                start,
+                reader.position(),
+                format!("Invalid number: {}", s),
+                "not a number",
+            ),
         },
     }
 }
 
 fn is_macro_terminating(ch: char) -> bool {
-    match ch {
-        '\"' => true,
-        ';' => true,
-        '@' => true,
-        '^' => true,
-        '`' => true,
-        '~' => true,
-        '(' => true,
-        ')' => true,
-        '[' => true,
-        ']' => true,
-        '{' => true,
-        '}' => true,
-        '\\' => true,
-        _ => false,
-    }
+    matches!(
+        ch,
+        '\"' | ';' | '@' | '^' | '`' | '~' | '(' | ')' | '[' | ']' | '{' | '}' | '\\'
+    )
 }
 fn read_token(reader: &mut RawReader) -> Option<String> {
     if reader.len() == 0 {
@@ -119,7 +134,8 @@ fn read_token(reader: &mut RawReader) -> Option<String> {
     Some(s)
 }
 
-fn escape_char(reader: &mut RawReader) -> char {
+fn escape_char(reader: &mut RawReader, start: usize) -> char {
+    let at = reader.position();
     reader.consume_char();
     let ch = reader.consume_char();
     match ch {
@@ -128,8 +144,13 @@ fn escape_char(reader: &mut RawReader) -> char {
         Some('r') => '\r',
         Some('\\') => '\\',
         Some('"') => '"',
-        Some(c) => panic!(format!("no define char:{}", c)),
-        None => panic!("nothing after \\!"),
+        Some(c) => error_at(
+            at,
+            at + 2,
+            format!("Unsupported escape character: \\{}", c),
+            "unknown escape",
+        ),
+        None => eof_in_string(start),
     }
 }
 fn read_string(reader: &mut RawReader) -> ReadState {
@@ -140,15 +161,16 @@ fn read_string(reader: &mut RawReader) -> ReadState {
 }
 
 fn read_string_inner(reader: &mut RawReader) -> String {
+    let start = reader.position();
     let mut s = String::new();
     reader.consume_char();
     loop {
         if reader.len() == 0 {
-            return s;
+            eof_in_string(start);
         };
         let ch = reader.pre_read_next_char().unwrap();
         match ch {
-            '\\' => s.push(escape_char(reader)),
+            '\\' => s.push(escape_char(reader, start)),
             '"' => {
                 reader.consume_char();
                 return s;
@@ -161,6 +183,15 @@ fn read_string_inner(reader: &mut RawReader) -> String {
     }
 }
 
+fn eof_in_string(start: usize) -> ! {
+    error_at(
+        start,
+        start + 1,
+        "EOF while reading string".to_string(),
+        "unclosed string",
+    )
+}
+
 fn read_symbol(reader: &mut RawReader) -> AstVal {
     let token = match read_token(reader) {
         Some(t) => t,
@@ -178,7 +209,7 @@ fn read_symbol(reader: &mut RawReader) -> AstVal {
 }
 
 fn read_delimited(reader: &mut RawReader, delim: char, into_l: &mut AstVal) -> AstVal {
-    // println!("into read delimited internal");
+    let start = reader.position();
     reader.consume_char();
     let mut l = into_l.clone();
     loop {
@@ -190,9 +221,18 @@ fn read_delimited(reader: &mut RawReader, delim: char, into_l: &mut AstVal) -> A
                     continue;
                 }
             }
-            ReadState::EOF => {
-                panic!("read delimited internal");
-            }
+            ReadState::Eof => Diagnostic::error("E0015", "EOF while reading")
+                .at(
+                    Span {
+                        start,
+                        end: start + 1,
+                    },
+                    "unclosed delimiter",
+                )
+                .help(format!("a `{}` is missing", delim))
+                .raise(),
+            // Comments are only kept at the top level.
+            ReadState::Continue(AstVal::AstCommentLine(_)) => {}
             ReadState::Continue(c) => {
                 l.push(c);
             }
@@ -214,12 +254,14 @@ fn read_unmatched_delimiter(reader: &mut RawReader) -> ReadState {
     ReadState::Delimited(ch)
 }
 fn read_keyword(reader: &mut RawReader) -> ReadState {
+    let start = reader.position();
     reader.consume_char();
+    let invalid = || error_at(start, start + 1, "Invalid token: :".to_string(), "no name");
     match reader.pre_read_next_char() {
-        Some(' ') => panic!("read keyword"),
+        Some(' ') => invalid(),
         _ => match read_token(reader) {
             Some(s) => ReadState::Continue(AstVal::AstKeyword(s)),
-            None => panic!("read keyword"),
+            None => invalid(),
         },
     }
 }
@@ -242,12 +284,41 @@ fn read_comment(reader: &mut RawReader) -> ReadState {
     ReadState::Continue(AstVal::AstCommentLine(s))
 }
 fn wrapping_reader(reader: &mut RawReader, ch: char) -> ReadState {
+    let start = reader.position();
     reader.consume_char();
     let mut l = AstVal::new_list();
     l.push(AstVal::AstSymbol(ch.to_string()));
     match read_internal(reader) {
         ReadState::Continue(x) => l.push(x),
-        _ => panic!("nothing after '"),
+        _ => error_at(
+            start,
+            start + 1,
+            format!("EOF while reading, after {}", ch),
+            "nothing after it",
+        ),
+    }
+    ReadState::Continue(l)
+}
+/// `~x` and `~@xs`, read as `(~ x)` and `(~@ xs)`.
+fn read_unquote(reader: &mut RawReader) -> ReadState {
+    let start = reader.position();
+    reader.consume_char();
+    let mut l = AstVal::new_list();
+    if reader.pre_read_next_char() == Some('@') {
+        l.push(AstVal::AstSymbol("~@".to_string()));
+    } else {
+        reader.unread_char('~');
+        return wrapping_reader(reader, '~');
+    }
+    reader.consume_char();
+    match read_internal(reader) {
+        ReadState::Continue(x) => l.push(x),
+        _ => error_at(
+            start,
+            start + 2,
+            "EOF while reading, after ~@".to_string(),
+            "nothing after it",
+        ),
     }
     ReadState::Continue(l)
 }
@@ -256,41 +327,56 @@ fn desugar_meta(f: AstVal) -> AstVal {
     match f {
         AstVal::AstKeyword(s) => {
             m.insert(AstVal::AstKeyword(s), AstVal::AstBool(true));
-            return m;
+            m
         }
         AstVal::AstSymbol(s) | AstVal::AstString(s) => {
             m.insert(
                 AstVal::AstKeyword(String::from("tag")),
                 AstVal::AstString(s),
             );
-            return m;
+            m
         }
         _ => f,
     }
 }
-fn read_meta_sugar(reader: &mut RawReader) -> AstVal {
+fn read_meta_sugar(reader: &mut RawReader, start: usize) -> AstVal {
     match read_internal(reader) {
         ReadState::Continue(f) => {
             if let AstVal::AstMeta(m) = desugar_meta(f) {
-                return AstVal::AstMeta(m);
+                AstVal::AstMeta(m)
             } else {
-                panic!("read meta 1")
+                error_at(
+                    start,
+                    reader.position(),
+                    "Metadata must be Symbol, Keyword, String or Map".to_string(),
+                    "not metadata",
+                )
             }
         }
-        _ => panic!("read meta"),
+        _ => eof_after_meta(start),
     }
 }
 
+fn eof_after_meta(start: usize) -> ! {
+    error_at(
+        start,
+        start + 1,
+        "EOF while reading, after ^".to_string(),
+        "nothing after it",
+    )
+}
+
 fn read_meta(reader: &mut RawReader) -> ReadState {
-    // println!("into meta");
+    let start = reader.position();
     reader.consume_char();
     match reader.pre_read_next_char() {
         Some('{') => match read_map(reader) {
-            ReadState::Continue(a) => ReadState::Continue(a.map_to_meta()),
-            _ => panic!("read meta 3"),
+            ReadState::Continue(AstVal::AstMap(v)) => ReadState::Continue(AstVal::AstMeta(v)),
+            // `{}` reads as nil.
+            _ => ReadState::Continue(AstVal::new_meta()),
         },
-        Some(_) => ReadState::Continue(read_meta_sugar(reader)),
-        _ => panic!("read meta 0"),
+        Some(_) => ReadState::Continue(read_meta_sugar(reader, start)),
+        _ => eof_after_meta(start),
     }
 }
 
@@ -304,10 +390,22 @@ fn read_vector(reader: &mut RawReader) -> ReadState {
     }
 }
 fn read_map(reader: &mut RawReader) -> ReadState {
-    let mut into_map = AstVal::new_map();
-    let the_map = read_delimited(reader, '}', &mut into_map);
-    if the_map.len() % 2 == 1 {
-        panic!("map count")
+    let start = reader.position();
+    let mut into_vec = AstVal::new_vec();
+    let the_items = read_delimited(reader, '}', &mut into_vec);
+    if the_items.len() % 2 == 1 {
+        error_at(
+            start,
+            reader.position(),
+            "Map literal must contain an even number of forms".to_string(),
+            "an odd number of forms",
+        )
+    }
+    let mut the_map = AstVal::new_map();
+    if let AstVal::AstVec(v) = the_items {
+        for pair in v.chunks(2) {
+            the_map.insert(pair[0].clone(), pair[1].clone());
+        }
     }
     if the_map == AstVal::AstNil {
         ReadState::Continue(AstVal::AstNil)
@@ -320,7 +418,7 @@ fn read_symbol_or_number(reader: &mut RawReader) -> AstVal {
     match reader.pre_read_next_char() {
         Some(ch) if ch.is_ascii_digit() => {
             reader.unread_char('-');
-        read_number(reader)
+            read_number(reader)
         }
         Some(_) | None => {
             reader.unread_char('-');
@@ -340,18 +438,28 @@ fn which_macro(ch: char) -> Option<fn(&mut RawReader) -> ReadState> {
         '{' => Some(read_map),
         '}' => Some(read_unmatched_delimiter),
         ';' => Some(read_comment),
-        // TODO: support macro
-        // '`' => Some(read_syntax_quote),
-        // '~' => Some(read_unquote),
         // TODO: lambda macro fn #(%)
         // '%'=>Some(read_arg),
         // TODO: # macro
         _ => None,
     }
 }
+/// Read a form, recording where it is.
 fn read_internal(reader: &mut RawReader) -> ReadState {
-    // println!("{:#?}", reader);
-    // println!("into read internal!");
+    while let Some(' ') | Some('\t') | Some('\n') = reader.pre_read_next_char() {
+        reader.consume_char();
+    }
+    let start = reader.position();
+    let state = read_form(reader);
+    if let ReadState::Continue(form) = &state {
+        form.set_span(Span {
+            start,
+            end: reader.position(),
+        });
+    }
+    state
+}
+fn read_form(reader: &mut RawReader) -> ReadState {
     loop {
         match reader.pre_read_next_char() {
             Some(' ') | Some('\t') | Some('\n') => {
@@ -364,9 +472,10 @@ fn read_internal(reader: &mut RawReader) -> ReadState {
                 // println!("read number");
                 return ReadState::Continue(read_number(reader));
             }
-            Some(ch) if (ch == '\'') | (ch == '@') => {
+            Some(ch) if (ch == '\'') | (ch == '@') | (ch == '`') => {
                 return wrapping_reader(reader, ch);
             }
+            Some('~') => return read_unquote(reader),
             Some(ch) => match which_macro(ch) {
                 None => {
                     // println!("read symbol");
@@ -376,13 +485,13 @@ fn read_internal(reader: &mut RawReader) -> ReadState {
                     // println!("read macro");
                     match f(reader) {
                         ReadState::Continue(res) => return ReadState::Continue(res),
-                        ReadState::EOF => return ReadState::EOF,
+                        ReadState::Eof => return ReadState::Eof,
                         ReadState::Delimited(ch) => return ReadState::Delimited(ch),
                     }
                 }
             },
             None => {
-                return ReadState::EOF;
+                return ReadState::Eof;
             }
         }
     }
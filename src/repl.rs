@@ -0,0 +1,471 @@
+use ast::AstVal;
+use clojure_rust_core::{attempt, ex_info__2, CljVal};
+use diagnostic::{self, in_source, Diagnostic, Source, Span};
+use eval::Evaluator;
+use mangle::{demangle, mangle};
+use namespace::{load_required, read_source};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::sync::Arc;
+use syntax::{fn_tail, is_special_form, parse_ns, syntax, Exports, Globals, SyntaxNode};
+
+/// The vars holding the values of the last three expressions, and the last
+/// error.
+const RESULT_VARS: &[&str] = &["*1", "*2", "*3"];
+const ERROR_VAR: &str = "*e";
+
+/// What `doc` shows of the functions and macros of `clojure.core` the
+/// compiler knows: their name, arglists and docstring.
+const CORE_DOCS: &[(&str, &str, &str)] = &[
+    ("println", "([& more])", "Prints the more args to the output, followed by a newline."),
+    ("vector", "([& args])", "Creates a new vector containing the args."),
+    ("list", "([& items])", "Creates a new list containing the items."),
+    ("hash-map", "([& keyvals])", "Returns a new hash map with supplied mappings."),
+    ("=", "([x y])", "Equality. Returns true if x equals y, false if not."),
+    ("map", "([f coll])", "Returns a seq of the result of applying f to each item of coll."),
+    (
+        "reduce",
+        "([f coll] [f val coll])",
+        "f should be a function of 2 arguments. Applies f to val, or the first item of coll, and the next item, then to that result and the item after, and so on.",
+    ),
+    ("get", "([map key] [map key not-found])", "Returns the value mapped to key, not-found or nil if key not present."),
+    ("nth", "([coll index] [coll index not-found])", "Returns the value at the index."),
+    ("nthnext", "([coll n])", "Returns the nth next of coll, (seq coll) when n is 0."),
+    ("inc", "([x])", "Returns a number one greater than num."),
+    ("assoc", "([map key val])", "When applied to a map, returns a new map of the same type, that contains the mapping of key to val."),
+    ("keys", "([map])", "Returns a sequence of the map's keys, in the same order as (seq map)."),
+    ("vals", "([map])", "Returns a sequence of the map's values, in the same order as (seq map)."),
+    ("<", "([x y])", "Returns non-nil if nums are in monotonically increasing order."),
+    ("satisfies?", "([protocol x])", "Returns true if x satisfies the protocol."),
+    ("defmulti", "([name docstring? attr-map? dispatch-fn & options])", "Creates a new multimethod with the associated dispatch function. The one option is :default, the default dispatch value, which defaults to :default."),
+    ("defmethod", "([multifn dispatch-val & fn-tail])", "Creates and installs a new method of multimethod associated with dispatch-value."),
+    ("prefer-method", "([multifn dispatch-val-x dispatch-val-y])", "Causes the multimethod to prefer matches of dispatch-val-x over dispatch-val-y when there is a conflict."),
+    ("remove-method", "([multifn dispatch-val])", "Removes the method of multimethod associated with dispatch-value."),
+    ("get-method", "([multifn dispatch-val])", "Given a multimethod and a dispatch value, returns the dispatch fn that would apply to that value, or nil if none apply and no default."),
+    ("derive", "([tag parent])", "Establishes a parent/child relationship between parent and tag in the global hierarchy."),
+    ("underive", "([tag parent])", "Removes a parent/child relationship between parent and tag in the global hierarchy."),
+    ("isa?", "([child parent])", "Returns true if (= child parent), or child is directly or indirectly derived from parent, or both are vectors of the same length whose elements are each isa? the other's."),
+    ("parents", "([tag])", "Returns the immediate parents of tag in the global hierarchy, or nil."),
+    ("ancestors", "([tag])", "Returns the immediate and indirect parents of tag in the global hierarchy, or nil."),
+    ("descendants", "([tag])", "Returns the immediate and indirect children of tag in the global hierarchy, or nil."),
+    ("try", "([expr* catch-clause* finally-clause?])", "catch-clause => (catch classname name expr*), classname being ExceptionInfo, Exception, RuntimeException, Throwable, :default, or a map the ex-data must contain. finally-clause => (finally expr*). Evaluates the exprs and, if no exceptions occur, returns the last. If an exception occurs and a catch-clause catches it, its exprs are evaluated with name bound to the exception, and the last is the value. The finally exprs are evaluated in every case, for their side effects."),
+    ("throw", "([expr])", "The expr is evaluated and thrown, therefore it should yield an exception."),
+    ("ex-info", "([msg map] [msg map cause])", "Create an instance of ExceptionInfo, a RuntimeException subclass that carries a map of additional data."),
+    ("ex-data", "([ex])", "Returns exception data (a map) if ex is an ExceptionInfo. Otherwise returns nil."),
+    ("ex-message", "([ex])", "Returns the message attached to ex if ex is an exception. Otherwise returns nil."),
+    ("ex-cause", "([ex])", "Returns the cause of ex if ex is an exception. Otherwise returns nil."),
+    ("when", "([test & body])", "Evaluates test. If logical true, evaluates body in an implicit do."),
+    ("when-not", "([test & body])", "Evaluates test. If logical false, evaluates body in an implicit do."),
+    ("cond", "([& clauses])", "Takes a set of test/expr pairs. It evaluates each test one at a time. If a test returns logical true, cond evaluates and returns the value of the corresponding expr."),
+    ("condp", "([pred expr & clauses])", "Takes a binary predicate, an expression, and a set of clauses."),
+    ("case", "([e & clauses])", "Takes an expression, and a set of clauses, each a test-constant and a result-expr."),
+    ("->", "([x & forms])", "Threads the expr through the forms, as the second item of each."),
+    ("->>", "([x & forms])", "Threads the expr through the forms, as the last item of each."),
+    ("as->", "([expr name & forms])", "Binds name to expr, evaluates the first form with it, and so on for each form."),
+    ("some->", "([expr & forms])", "When expr is not nil, threads it into the first form (via ->), and so on while the result is not nil."),
+    ("and", "([] [x] [x & next])", "Evaluates exprs one at a time, from left to right, until one is logical false."),
+    ("or", "([] [x] [x & next])", "Evaluates exprs one at a time, from left to right, until one is logical true."),
+    ("if-let", "([bindings then] [bindings then else])", "If test is true, evaluates then with binding-form bound to the value of test, if not, yields else."),
+    ("when-let", "([bindings & body])", "When test is true, evaluates body with binding-form bound to the value of test."),
+    ("doto", "([x & forms])", "Evaluates x then calls all of the forms with the value of x as the first argument, returning x."),
+    ("dotimes", "([bindings & body])", "Repeatedly executes body with name bound to integers from 0 through n-1."),
+];
+
+const CORE_MACROS: &[&str] = &[
+    "when", "when-not", "cond", "condp", "case", "->", "->>", "as->", "some->", "and", "or",
+    "if-let", "when-let", "doto", "dotimes",
+];
+
+/// What `doc` shows of a definition.
+struct Doc {
+    arglists: String,
+    doc: Option<String>,
+    is_macro: bool,
+}
+
+/// The docstrings and arglists of what a `defn`, `defmacro`, `def` or
+/// `defprotocol` form defines.
+fn docs_of(form: &AstVal) -> Vec<(String, Doc)> {
+    let v = match form {
+        AstVal::AstList(v) if v.len() > 2 => v,
+        _ => return Vec::new(),
+    };
+    if v[0] == sym("defprotocol") {
+        return docs_of_protocol(v);
+    }
+    let (head, name) = match (&v[0], &v[1]) {
+        (AstVal::AstSymbol(head), AstVal::AstSymbol(name)) => (head.as_str(), name.clone()),
+        _ => return Vec::new(),
+    };
+    let arglist = |params: &AstVal| match params {
+        AstVal::AstNil => "[]".to_string(),
+        params => params.to_string(),
+    };
+    let doc = match head {
+        "defn" | "defn-" | "defmacro" => {
+            let tail = fn_tail(v);
+            let arglists: Vec<String> = match &tail[0] {
+                AstVal::AstList(_) => tail
+                    .iter()
+                    .filter_map(|arity| match arity {
+                        AstVal::AstList(a) if !a.is_empty() => Some(arglist(&a[0])),
+                        _ => None,
+                    })
+                    .collect(),
+                params => vec![arglist(params)],
+            };
+            Doc {
+                arglists: format!("({})", arglists.join(" ")),
+                doc: match &v[2] {
+                    AstVal::AstString(doc) if tail.len() < v.len() - 2 => Some(doc.clone()),
+                    _ => None,
+                },
+                is_macro: head == "defmacro",
+            }
+        }
+        "def" | "defonce" => Doc {
+            arglists: String::new(),
+            doc: match &v[2] {
+                AstVal::AstString(doc) if v.len() == 4 => Some(doc.clone()),
+                _ => None,
+            },
+            is_macro: false,
+        },
+        _ => return Vec::new(),
+    };
+    vec![(name, doc)]
+}
+
+/// The docs of a protocol and of its methods, the protocol first.
+fn docs_of_protocol(v: &[AstVal]) -> Vec<(String, Doc)> {
+    let name = match v.get(1) {
+        Some(AstVal::AstSymbol(name)) => name.clone(),
+        _ => return Vec::new(),
+    };
+    let mut docs = vec![(
+        name,
+        Doc {
+            arglists: String::new(),
+            doc: match v.get(2) {
+                Some(AstVal::AstString(doc)) => Some(doc.clone()),
+                _ => None,
+            },
+            is_macro: false,
+        },
+    )];
+    for signature in &v[2..] {
+        if let AstVal::AstList(m) = signature {
+            if let Some(AstVal::AstSymbol(method)) = m.first() {
+                let arglists: Vec<String> = m[1..]
+                    .iter()
+                    .filter(|a| matches!(a, AstVal::AstVec(_) | AstVal::AstNil))
+                    .map(|a| a.to_string())
+                    .collect();
+                let doc = m[1..].iter().find_map(|a| match a {
+                    AstVal::AstString(doc) => Some(doc.clone()),
+                    _ => None,
+                });
+                docs.push((
+                    method.clone(),
+                    Doc {
+                        arglists: format!("({})", arglists.join(" ")),
+                        doc,
+                        is_macro: false,
+                    },
+                ));
+            }
+        }
+    }
+    docs
+}
+
+/// A value the way the REPL prints it, readable back.
+fn pr_str(v: &CljVal) -> String {
+    let items = |v: &[CljVal]| v.iter().map(pr_str).collect::<Vec<_>>().join(" ");
+    match v {
+        CljVal::CljString(s) => format!("{:?}", s),
+        CljVal::CljList(v) => format!("({})", items(v)),
+        CljVal::CljVec(v) => format!("[{}]", items(v)),
+        CljVal::CljMap(v) | CljVal::CljMeta(v) => {
+            let pairs: Vec<String> = v
+                .iter()
+                .map(|(k, x)| format!("{} {}", pr_str(k), pr_str(x)))
+                .collect();
+            format!("{{{}}}", pairs.join(", "))
+        }
+        CljVal::CljRecord(r) => format!(
+            "#{}{}",
+            r.record.type_name(),
+            pr_str(&CljVal::CljMap(r.entries()))
+        ),
+        CljVal::CljCommentLine(_) => "nil".to_string(),
+        v => v.to_string(),
+    }
+}
+
+/// Whether reading stopped only because the input ended in a form.
+fn is_incomplete(d: &Diagnostic) -> bool {
+    d.code == "E0015" && d.message.starts_with("EOF while reading")
+}
+
+/// The forms of a quoted argument of `require`.
+fn unquote(a: &AstVal) -> AstVal {
+    match a {
+        AstVal::AstList(v)
+            if v.len() == 2
+                && (v[0] == AstVal::AstSymbol("quote".to_string())
+                    || v[0] == AstVal::AstSymbol("'".to_string())) =>
+        {
+            v[1].clone()
+        }
+        a => a.clone(),
+    }
+}
+
+fn sym(s: &str) -> AstVal {
+    AstVal::AstSymbol(s.to_string())
+}
+
+/// A session of the REPL: what has been defined and loaded so far.
+struct Repl {
+    globals: Globals,
+    ev: Evaluator,
+    loaded: HashMap<String, Exports>,
+    /// By the name of what they document, with its namespace.
+    docs: HashMap<String, Doc>,
+    /// Where `require` looks for namespaces.
+    source_paths: Vec<PathBuf>,
+}
+
+impl Repl {
+    fn new(source_paths: Vec<PathBuf>) -> Repl {
+        let mut repl = Repl {
+            globals: Globals::new(),
+            ev: Evaluator::new(),
+            loaded: HashMap::new(),
+            docs: HashMap::new(),
+            source_paths,
+        };
+        syntax(
+            &AstVal::AstList(vec![sym("ns"), sym("user")]),
+            &mut repl.globals,
+        );
+        for name in RESULT_VARS.iter().chain(&[ERROR_VAR]) {
+            repl.globals.define_var(name);
+            repl.ev.set(&mangle(name), CljVal::CljNil);
+        }
+        repl
+    }
+
+    fn eval_form(&mut self, source: &Arc<Source>, form: &AstVal, span: Span) {
+        if let AstVal::AstList(v) = form {
+            if v.len() == 2 && v[0] == sym("doc") {
+                if let AstVal::AstSymbol(s) = &v[1] {
+                    self.doc(s);
+                    println!("nil");
+                    self.shift(CljVal::CljNil);
+                    return;
+                }
+            }
+        }
+        let analyzed = diagnostic::catch(|| {
+            in_source(source, Some(span), || match form {
+                AstVal::AstList(v) if v.first() == Some(&sym("require")) => {
+                    self.require(&v[1..]);
+                    SyntaxNode::Nothing
+                }
+                _ => {
+                    for (name, doc) in docs_of(form) {
+                        self.docs.insert(format!("user/{}", name), doc);
+                    }
+
+                    self.globals.prescan(slice::from_ref(form));
+                    syntax(form, &mut self.globals)
+                }
+            })
+        });
+        let node = match analyzed {
+            Ok(node) => node,
+            Err(d) => {
+                d.report();
+                self.failed(&d);
+                return;
+            }
+        };
+        let ev = &self.ev;
+        match diagnostic::catch(|| attempt(|| ev.eval(&node))) {
+            Ok(Ok(value)) => match &node {
+                // There are no vars at run time, so a definition keeps
+                // what its var holds.
+                SyntaxNode::Fn { name, .. } | SyntaxNode::Def { name, .. } => {
+                    println!("#'user/{}", demangle(name));
+                    self.shift(self.ev.get(name));
+                }
+                SyntaxNode::Record { type_name, .. } => {
+                    println!("{}", type_name);
+                    self.shift(CljVal::CljNil);
+                }
+                SyntaxNode::Protocol(protocol) => {
+                    println!("{}", protocol.name);
+                    self.shift(CljVal::CljNil);
+                }
+                SyntaxNode::Nothing => {
+                    match docs_of(form).first() {
+                        Some((name, _)) => println!("#'user/{}", name),
+                        None => println!("nil"),
+                    }
+                    self.shift(CljVal::CljNil);
+                }
+                _ => {
+                    println!("{}", pr_str(&value));
+                    self.shift(value);
+                }
+            },
+            Ok(Err(err)) => {
+                let e = err.exception();
+                eprintln!("Execution error: {}", e);
+                self.ev.set(&mangle(ERROR_VAR), e);
+            }
+            Err(d) => {
+                eprintln!("Execution error: {}", d.message);
+                self.failed(&d);
+            }
+        }
+    }
+
+    /// Keep a value as `*1`, the one before as `*2`, and so on.
+    fn shift(&self, value: CljVal) {
+        let names: Vec<String> = RESULT_VARS.iter().map(|name| mangle(name)).collect();
+        for i in (1..names.len()).rev() {
+            self.ev.set(&names[i], self.ev.get(&names[i - 1]));
+        }
+        self.ev.set(&names[0], value);
+    }
+    /// Keep an error of the compiler as `*e`, an exception with its code as
+    /// `:code` of its data.
+    fn failed(&self, d: &Diagnostic) {
+        let data = CljVal::CljMap(vec![(
+            CljVal::CljKeyword("code".to_string()),
+            CljVal::CljString(d.code.to_string()),
+        )]);
+        self.ev.set(
+            &mangle(ERROR_VAR),
+            ex_info__2(&CljVal::CljString(d.message.clone()), &data),
+        );
+    }
+
+    /// `(require 'my.util '[my.other :as o])` loads the namespaces from the
+    /// files under the source paths, evaluating them, and brings them in the
+    /// way an `ns` form would.
+    fn require(&mut self, specs: &[AstVal]) {
+        let mut clause = vec![AstVal::AstKeyword("require".to_string())];
+        clause.extend(specs.iter().map(unquote));
+        let form = AstVal::AstList(vec![sym("ns"), sym("user"), AstVal::AstList(clause)]);
+        let ns = parse_ns(&form).unwrap();
+        let (ev, docs) = (&self.ev, &mut self.docs);
+        load_required(
+            &self.source_paths,
+            &ns,
+            &mut self.loaded,
+            |ns, x, globals| {
+                for (name, doc) in docs_of(x) {
+                    docs.insert(format!("{}/{}", ns, name), doc);
+                }
+                ev.in_ns(ns).eval(&syntax(x, globals));
+                String::new()
+            },
+        );
+        for r in &ns.requires {
+            self.globals.require(&r.ns, self.loaded[&r.ns].clone());
+        }
+        syntax(&form, &mut self.globals);
+    }
+
+    /// Print the documentation of what a symbol names, as `doc` does.
+    fn doc(&self, s: &str) {
+        println!("-------------------------");
+        if is_special_form(s) {
+            println!("{}\n  Special Form", s);
+            return;
+        }
+        let name = self.globals.qualify(s);
+        let core = name
+            .strip_prefix("clojure.core/")
+            .and_then(|core| CORE_DOCS.iter().find(|(n, _, _)| *n == core));
+        match (self.docs.get(&name), core) {
+            (Some(doc), _) => {
+                println!("{}", name);
+                if !doc.arglists.is_empty() {
+                    println!("{}", doc.arglists);
+                }
+                if doc.is_macro {
+                    println!("Macro");
+                }
+                if let Some(text) = &doc.doc {
+                    println!("  {}", text);
+                }
+            }
+            (None, Some((core, arglists, text))) => {
+                println!("clojure.core/{}\n{}", core, arglists);
+                if CORE_MACROS.contains(core) {
+                    println!("Macro");
+                }
+                println!("  {}", text);
+            }
+            (None, None) => println!("{}\n  No documentation found.", name),
+        }
+    }
+}
+
+/// Read forms from stdin and evaluate them, printing their values. A form
+/// can span several lines, which are read until it is complete. `require`
+/// looks for namespaces in `source_paths`, or else in `src` or the current
+/// directory.
+pub fn repl(json: bool, source_paths: &[PathBuf]) {
+    diagnostic::set_json(json);
+    let source_paths = match source_paths {
+        [] if Path::new("src").is_dir() => vec![PathBuf::from("src")],
+        [] => vec![PathBuf::from(".")],
+        paths => paths.to_vec(),
+    };
+    let mut repl = Repl::new(source_paths);
+    let stdin = io::stdin();
+    let mut input = String::new();
+    loop {
+        print!(
+            "{}",
+            if input.is_empty() {
+                "user=> "
+            } else {
+                "  #_=> "
+            }
+        );
+        io::stdout().flush().unwrap();
+        match stdin.lock().read_line(&mut input) {
+            Ok(0) | Err(_) => {
+                println!();
+                return;
+            }
+            Ok(_) => {}
+        }
+        let text = input.clone();
+        let source = match diagnostic::catch(|| read_source("<repl>", text)) {
+            Ok(source) => source,
+            Err(ref d) if is_incomplete(d) => continue,
+            Err(d) => {
+                d.report();
+                repl.failed(&d);
+                input.clear();
+                continue;
+            }
+        };
+        input.clear();
+        for (form, span) in source.forms.iter().zip(&source.spans) {
+            if !matches!(form, AstVal::AstCommentLine(_)) {
+                repl.eval_form(&source.source, form, *span);
+            }
+        }
+    }
+}
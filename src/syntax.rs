@@ -1,299 +1,3314 @@
 use ast::AstVal;
+use diagnostic::{self, Diagnostic};
+use interpreter::Interpreter;
+use mangle::{demangle_path, mangle};
+use std::collections::{HashMap, HashSet};
+/// A form once analyzed, which `translate` turns into Rust.
 #[derive(Debug, Clone)]
-pub struct SyntaxNode {
-    pub this: AstVal,
+pub enum SyntaxNode {
+    /// A `defn`.
+    Fn {
+        name: String,
+        public: bool,
+        arities: Vec<Arity>,
+        /// The docstring, which becomes its rustdoc.
+        doc: Option<String>,
+    },
+    /// A `fn` literal, with the locals from around it that it uses, and the
+    /// local it calls itself by when it is named.
+    Closure {
+        name: Option<String>,
+        captures: Vec<String>,
+        arities: Vec<Arity>,
+    },
+    /// A top-level `def`.
+    Def {
+        name: String,
+        kind: VarKind,
+        public: bool,
+        value: Box<SyntaxNode>,
+    },
+    /// What compiles to nothing, like `ns`, `declare` and `defmacro`.
+    Nothing,
+    Do(Vec<SyntaxNode>),
+    Let {
+        bindings: Vec<(String, SyntaxNode)>,
+        body: Vec<SyntaxNode>,
+    },
+    If {
+        test: Box<SyntaxNode>,
+        then: Box<SyntaxNode>,
+        otherwise: Box<SyntaxNode>,
+    },
+    Loop {
+        bindings: Vec<(String, SyntaxNode)>,
+        body: Vec<SyntaxNode>,
+    },
+    /// With the names of the loop bindings or parameters it rebinds.
+    Recur {
+        targets: Vec<String>,
+        args: Vec<SyntaxNode>,
+    },
+    /// A `case` with literal keys, and its default if it has one.
+    Case {
+        expr: Box<SyntaxNode>,
+        clauses: Vec<(Vec<AstVal>, SyntaxNode)>,
+        default: Option<Box<SyntaxNode>>,
+    },
+    /// A call to a function known at compile time, by its path in Rust.
+    Call {
+        path: String,
+        args: Vec<SyntaxNode>,
+    },
+    /// A call to a function value, like a local or a `def`.
+    Invoke {
+        target: Box<SyntaxNode>,
+        args: Vec<SyntaxNode>,
+    },
+    Vector(Vec<SyntaxNode>),
+    List(Vec<SyntaxNode>),
+    HashMap(Vec<(SyntaxNode, SyntaxNode)>),
+    Println(Vec<SyntaxNode>),
+    Equal(Box<SyntaxNode>, Box<SyntaxNode>),
+    /// A literal, or quoted data.
+    Const(AstVal),
+    /// A local, by binding id, with its name in Rust.
+    LocalRef {
+        id: usize,
+        name: String,
+    },
+    /// A definition of a namespace or a function of the runtime, in value
+    /// position, by its path in Rust.
+    GlobalRef {
+        path: String,
+        kind: RefKind,
+    },
+    /// A call into Rust code, with the type each argument is converted to
+    /// when it is hinted with one.
+    RustCall {
+        callee: RustCallee,
+        args: Vec<(Option<String>, SyntaxNode)>,
+    },
+    /// A `defrecord`: its struct and the functions making one, and the
+    /// protocols it implements inline.
+    Record {
+        name: String,
+        /// Its name qualified with its namespace, as it prints.
+        type_name: String,
+        fields: Vec<Field>,
+        extensions: Vec<Extension>,
+    },
+    /// A `defprotocol`: a Rust trait, and a function for each method
+    /// dispatching on the type of its first argument.
+    Protocol(Protocol),
+    /// Protocols extended to types, by `extend-type` or `extend-protocol`.
+    Extend(Vec<Extension>),
+    /// A `try`, with its `catch` clauses in turn and its `finally` body.
+    Try {
+        body: Vec<SyntaxNode>,
+        catches: Vec<Catch>,
+        finally: Vec<SyntaxNode>,
+    },
+    /// A top-level form that is not a definition, run by the `__init` of
+    /// its module.
+    Init {
+        name: String,
+        body: Box<SyntaxNode>,
+    },
+    /// A field of a value known to be a record of the type named `record`,
+    /// read from its struct.
+    GetField {
+        record: String,
+        field: String,
+        target: Box<SyntaxNode>,
+    },
+}
+
+/// A `catch` of a `try`, binding what it catches to a local.
+#[derive(Debug, Clone)]
+pub struct Catch {
+    pub catches: Catches,
+    pub binding: String,
     pub body: Vec<SyntaxNode>,
-    pub context: Vec<Vec<AstVal>>,
 }
 
-impl SyntaxNode {
-    fn new() -> SyntaxNode {
-        SyntaxNode {
-            this: AstVal::AstNil,
-            body: Vec::new(),
-            context: Vec::new(),
+/// What a `catch` catches.
+#[derive(Debug, Clone)]
+pub enum Catches {
+    /// Exceptions of a class, `ExceptionInfo` or one catching everything,
+    /// like `Exception` or `:default`.
+    Class(String),
+    /// The `ex-info`s with the entries of a map in their data.
+    Data(Box<SyntaxNode>),
+}
+
+/// A field of a record, and the Rust type it is hinted with.
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    pub hint: Option<String>,
+}
+
+/// A protocol, as the code using it knows it.
+#[derive(Debug, Clone)]
+pub struct Protocol {
+    pub name: String,
+    /// Its name qualified with its namespace, as errors show it.
+    pub qualified: String,
+    /// The Rust path of the module defining it, empty in that module.
+    pub module: String,
+    pub methods: Vec<Method>,
+    pub doc: Option<String>,
+}
+
+impl Protocol {
+    /// The Rust path of what the protocol defines, the trait or the
+    /// function of a method, from where it is used.
+    pub fn path(&self, name: &str) -> String {
+        self.rust_path(mangle(name))
+    }
+    /// The name in Rust of the function telling whether a value satisfies
+    /// the protocol.
+    pub fn satisfies_name(&self) -> String {
+        format!("{}__satisfies", mangle(&self.name))
+    }
+    pub fn satisfies_path(&self) -> String {
+        self.rust_path(self.satisfies_name())
+    }
+    fn rust_path(&self, name: String) -> String {
+        match self.module.as_str() {
+            "" => name,
+            module => format!("{}::{}", module, name),
+        }
+    }
+}
+
+/// A method of a protocol, with the names of its parameters in Rust, the
+/// first one being the value it dispatches on.
+#[derive(Debug, Clone)]
+pub struct Method {
+    pub name: String,
+    pub params: Vec<String>,
+    pub doc: Option<String>,
+}
+
+/// The methods of a protocol implemented for a type.
+#[derive(Debug, Clone)]
+pub struct Extension {
+    pub protocol: Protocol,
+    /// The type, by the name the runtime gives it: `String`, or the
+    /// qualified name of a record.
+    pub type_name: String,
+    /// The Rust type that implements the trait of the protocol, if any.
+    pub rust_type: Option<String>,
+    /// Whether the Rust type is the struct of a record.
+    pub record: bool,
+    /// The functions implementing the methods are named with it and the
+    /// name of the method, like `Shape__String__area`.
+    pub prefix: String,
+    /// The Rust path of the module of those functions, empty in that
+    /// module.
+    pub module: String,
+    /// The methods, by name, with the arity implementing each.
+    pub methods: Vec<(String, Arity)>,
+}
+
+impl Extension {
+    /// The function implementing a method, from where it is used.
+    pub fn path(&self, method: &str) -> String {
+        let name = format!("{}__{}", self.prefix, mangle(method));
+        match self.module.as_str() {
+            "" => name,
+            module => format!("{}::{}", module, name),
+        }
+    }
+}
+
+/// One arity of a function: its fixed parameters, the one after `&`, and
+/// its body.
+#[derive(Debug, Clone)]
+pub struct Arity {
+    pub fixed: Vec<String>,
+    pub rest: Option<String>,
+    pub body: Vec<SyntaxNode>,
+}
+
+/// How a global in value position is compiled.
+#[derive(Debug, Clone, Copy)]
+pub enum RefKind {
+    /// A function with one fixed arity, wrapped into a function value.
+    Fn(usize),
+    /// A function with a dispatcher taking the arguments as a slice.
+    Dispatcher,
+    Var(VarKind),
+}
+
+/// A typed Rust wrapper of a public function, from the `:rust/sig` in its
+/// metadata: `^{:rust/sig "fn(i64, &str) -> String"}`.
+#[derive(Debug, Clone)]
+pub struct Signature {
+    /// The name of the function in Rust.
+    pub name: String,
+    pub params: Vec<String>,
+    /// What it returns, if anything.
+    pub ret: Option<String>,
+    /// Whether the function has several arities, and so is called with its
+    /// arguments as a slice.
+    pub dispatcher: bool,
+    pub doc: Option<String>,
+}
+
+/// What Rust code is called.
+#[derive(Debug, Clone)]
+pub enum RustCallee {
+    /// A function or associated function, by its path: `(rust/std::cmp::max
+    /// a b)` or `(String/from s)`.
+    Fn(String),
+    /// A method of the first argument: `(.len s)`.
+    Method(String),
+    /// A macro, by its name without the `!`: `(rust-macro! format "{}" x)`.
+    Macro(String),
+}
+
+/// A pass over analyzed forms. `visit` is called on every node, and goes on
+/// to its children unless a pass overrides it to stop.
+pub trait Visitor {
+    fn visit(&mut self, n: &SyntaxNode) {
+        walk(self, n)
+    }
+}
+
+/// Visit the children of a node.
+pub fn walk<V: Visitor + ?Sized>(v: &mut V, n: &SyntaxNode) {
+    for child in n.children() {
+        v.visit(child);
+    }
+}
+
+/// The parameter counts a function accepts: its fixed arities, and how many
+/// parameters come before `&` if it has a variadic one.
+#[derive(Debug, Clone, Default)]
+struct FnInfo {
+    fixed: Vec<usize>,
+    variadic: Option<usize>,
+}
+
+impl FnInfo {
+    /// One fixed arity, compiled to a plain Rust function.
+    fn is_simple(&self) -> bool {
+        self.fixed.len() == 1 && self.variadic.is_none()
+    }
+}
+
+/// Functions of the `clojure-rust-core` runtime, with their fixed arities. The ones
+/// with several are implemented there the way `translate` compiles a
+/// multi-arity `defn`.
+const CORE_FNS: &[(&str, &str, &[usize])] = &[
+    ("map", "map", &[2]),
+    ("reduce", "reduce", &[2, 3]),
+    ("get", "get", &[2, 3]),
+    ("nth", "nth", &[2, 3]),
+    ("nthnext", "nthnext", &[2]),
+    (
+        "seq_to_map_for_destructuring",
+        "seq_to_map_for_destructuring",
+        &[1],
+    ),
+    ("inc", "inc", &[1]),
+    ("assoc", "assoc", &[3]),
+    ("keys", "keys", &[1]),
+    ("vals", "vals", &[1]),
+    ("<", "lt", &[2]),
+    ("no_matching_clause", "no_matching_clause", &[1]),
+    ("multi_fn", "multi_fn", &[3]),
+    ("add_method", "add_method", &[3]),
+    ("prefer-method", "prefer_method", &[3]),
+    ("remove-method", "remove_method", &[2]),
+    ("get-method", "get_method", &[2]),
+    ("derive", "derive", &[2]),
+    ("underive", "underive", &[2]),
+    ("isa?", "isa", &[2]),
+    ("parents", "parents", &[1]),
+    ("ancestors", "ancestors", &[1]),
+    ("descendants", "descendants", &[1]),
+    ("throw", "throw", &[1]),
+    ("ex-info", "ex_info", &[2, 3]),
+    ("ex-data", "ex_data", &[1]),
+    ("ex-message", "ex_message", &[1]),
+    ("ex-cause", "ex_cause", &[1]),
+];
+
+/// How a `def` is compiled: a `const` for literals, otherwise a lazily
+/// initialised thread local static.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VarKind {
+    Const,
+    Static,
+}
+
+#[derive(Debug, Clone)]
+enum Global {
+    Fn(FnInfo),
+    Var(VarKind),
+}
+
+/// A function of the runtime, with its name in Rust.
+fn core_fn(name: &str) -> Option<(String, Global)> {
+    CORE_FNS
+        .iter()
+        .find(|(core_name, _, _)| *core_name == name)
+        .map(|(_, rust_name, fixed)| {
+            let info = FnInfo {
+                fixed: fixed.to_vec(),
+                variadic: None,
+            };
+            (rust_name.to_string(), Global::Fn(info))
+        })
+}
+
+/// What a namespace makes available to the namespaces requiring it.
+#[derive(Debug, Clone)]
+pub struct Exports {
+    /// The Rust path of its module, like `crate::my::util`.
+    module: String,
+    defs: HashMap<String, Global>,
+    macros: HashMap<String, AstVal>,
+    private: HashSet<String>,
+    protocols: Vec<Protocol>,
+    /// Whether it has an `__init` to run before its functions are called.
+    init: bool,
+}
+
+impl Exports {
+    pub fn defines(&self, name: &str) -> bool {
+        self.defs.contains_key(name)
+    }
+}
+
+/// What the file has defined so far, shared by all top-level forms.
+#[derive(Debug)]
+pub struct Globals {
+    defs: HashMap<String, Global>,
+    private: HashSet<String>,
+    /// What the rest of the file defines, for `declare` to bring in early.
+    later: HashMap<String, Global>,
+    /// The namespaces required by this one, by name.
+    namespaces: HashMap<String, Exports>,
+    aliases: HashMap<String, String>,
+    /// Names referred from other namespaces, with the namespace of each.
+    refers: HashMap<String, String>,
+    /// Runs the macros at compile time.
+    interp: Interpreter,
+    /// The locals in scope, innermost last.
+    scope: Vec<Binding>,
+    /// The names in Rust of what a `recur` here rebinds, if it can be here.
+    recur_target: Option<Vec<String>>,
+    /// Numbers gensyms and bindings alike, so their names in Rust never clash.
+    gensym_counter: usize,
+    /// The functions defined with a `:rust/sig`.
+    signatures: Vec<Signature>,
+    /// The records defined, with their fields.
+    records: HashMap<String, Vec<String>>,
+    /// The protocols defined.
+    protocols: Vec<Protocol>,
+    /// The records and protocols defined, by name in Rust, with what each is.
+    types: HashMap<String, String>,
+    /// The protocols extended to types here, in turn.
+    extensions: Vec<Extension>,
+    /// The functions running the top-level forms that are not definitions.
+    inits: Vec<String>,
+}
+
+/// A local in scope.
+#[derive(Debug, Clone)]
+struct Binding {
+    name: String,
+    /// Unique in the file.
+    id: usize,
+    /// The name is kept in Rust unless the binding shadows another local, as
+    /// `recur` must still be able to assign to the one it shadows, or a var.
+    rust_name: String,
+    parameter: bool,
+    /// The record type of its value, when that is known.
+    record: Option<String>,
+}
+
+/// What a symbol names where it is used.
+#[derive(Debug, Clone)]
+enum Resolution {
+    /// A local, by binding id, with its name in Rust.
+    Local(usize, String),
+    Parameter(usize, String),
+    /// A definition of this namespace or of another one, with its path.
+    Global(String, Global),
+    /// A function of the runtime.
+    Core(String, Global),
+    Unresolved,
+}
+
+/// The types the generated code names, which no record or protocol can
+/// take the place of.
+const RUST_TYPES: &[&str] = &[
+    "CljVal",
+    "CljFn",
+    "FnBody",
+    "MultiFn",
+    "Exception",
+    "Record",
+    "CljRecord",
+    "DynRecord",
+    "FromClj",
+    "IntoClj",
+    "ConversionError",
+    "CljErr",
+    "CljResult",
+    "String",
+    "Vec",
+    "Box",
+    "Option",
+    "Result",
+    "Rc",
+];
+
+/// Forms the compiler itself understands, which no macro can take over.
+const SPECIAL_FORMS: &[&str] = &[
+    "def",
+    "defn",
+    "defn-",
+    "defmacro",
+    "defonce",
+    "declare",
+    "ns",
+    "fn",
+    "if",
+    "do",
+    "let",
+    "loop",
+    "recur",
+    "quote",
+    "'",
+    "`",
+    "macroexpand",
+    "macroexpand-1",
+    "rust-macro!",
+    "defrecord",
+    "defprotocol",
+    "extend-type",
+    "extend-protocol",
+    "defmulti",
+    "defmethod",
+    "try",
+    "catch",
+    "finally",
+];
+
+impl Globals {
+    pub fn new() -> Globals {
+        Globals {
+            defs: HashMap::new(),
+            private: HashSet::new(),
+            later: HashMap::new(),
+            namespaces: HashMap::new(),
+            aliases: HashMap::new(),
+            refers: HashMap::new(),
+            interp: Interpreter::new(),
+            scope: Vec::new(),
+            recur_target: None,
+            gensym_counter: 0,
+            signatures: Vec::new(),
+            records: HashMap::new(),
+            protocols: Vec::new(),
+            extensions: Vec::new(),
+            inits: Vec::new(),
+            types: HashMap::new(),
+        }
+    }
+    /// Make a compiled namespace available to the `ns` form requiring it.
+    pub fn require(&mut self, ns: &str, exports: Exports) {
+        self.namespaces.insert(ns.to_string(), exports);
+    }
+    /// What this namespace, compiled to the module `module`, makes available.
+    pub fn exports(&self, module: &str) -> Exports {
+        Exports {
+            module: module.to_string(),
+            defs: self.defs.clone(),
+            macros: self
+                .interp
+                .defs
+                .borrow()
+                .iter()
+                .filter(|(name, _)| self.interp.find_macro(name).is_some())
+                .map(|(name, mac)| (name.clone(), mac.clone()))
+                .collect(),
+            private: self.private.clone(),
+            protocols: self
+                .protocols
+                .iter()
+                .map(|p| Protocol {
+                    module: module.to_string(),
+                    ..p.clone()
+                })
+                .collect(),
+            init: self.has_init(),
+        }
+    }
+    /// The functions running the top-level forms that are not definitions,
+    /// in turn.
+    pub fn inits(&self) -> &[String] {
+        &self.inits
+    }
+    /// The modules of the required namespaces that have an `__init`.
+    pub fn required_inits(&self) -> Vec<String> {
+        let mut modules: Vec<String> = self
+            .namespaces
+            .values()
+            .filter(|exports| exports.init)
+            .map(|exports| exports.module.clone())
+            .collect();
+        modules.sort();
+        modules
+    }
+    /// Whether the module has an `__init`: it runs forms, requires a module
+    /// that does, or has a `main` that runs it.
+    pub fn has_init(&self) -> bool {
+        !self.inits.is_empty()
+            || !self.required_inits().is_empty()
+            || self.defs.contains_key("main")
+    }
+    /// The macro a symbol names, unless a local shadows it.
+    fn find_macro(&self, s: &str) -> Option<AstVal> {
+        if SPECIAL_FORMS.contains(&s) || self.local(s).is_some() {
+            return None;
+        }
+        let (ns, name) = match s.find('/') {
+            Some(i) if s.len() > 1 => {
+                let ns = &s[..i];
+                (
+                    self.aliases.get(ns).map(|ns| ns.as_str()).unwrap_or(ns),
+                    &s[i + 1..],
+                )
+            }
+            _ => match self.refers.get(s) {
+                Some(ns) => (ns.as_str(), s),
+                None => return self.interp.find_macro(s),
+            },
+        };
+        if Some(ns) == self.interp.ns.as_deref() {
+            return self.interp.find_macro(name);
         }
+        let exports = self.namespaces.get(ns)?;
+        if exports.private.contains(name) {
+            return None;
+        }
+        exports.macros.get(name).cloned()
+    }
+    /// Expand a form once if it is a call to a macro.
+    fn macroexpand_1(&mut self, form: &AstVal) -> Option<AstVal> {
+        let s = match form {
+            AstVal::AstList(v) => match v.first() {
+                Some(AstVal::AstSymbol(s)) => s,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        if SPECIAL_FORMS.contains(&s.as_str()) || self.local(s).is_some() {
+            return None;
+        }
+        let mac = match self.find_macro(s) {
+            Some(mac) => mac,
+            None => return expand_core_macro(form, true, &mut |prefix| self.gensym(prefix)),
+        };
+        let locals: Vec<AstVal> = self.scope.iter().map(|b| sym(&b.name)).collect();
+        Some(self.interp.expand(&mac, form, &locals))
+    }
+    fn macroexpand(&mut self, form: &AstVal) -> AstVal {
+        let mut form = form.clone();
+        while let Some(expanded) = self.macroexpand_1(&form) {
+            form = expanded;
+        }
+        form
+    }
+    /// Bring a local into scope, and give the name it has in Rust.
+    fn bind(&mut self, name: &AstVal, parameter: bool) -> AstVal {
+        let name = match name {
+            AstVal::AstSymbol(name) => name.clone(),
+            _ => Diagnostic::error(
+                "E0012",
+                format!("Bad binding form, expected symbol, got: {}", name),
+            )
+            .raise(),
+        };
+        self.gensym_counter += 1;
+        let id = self.gensym_counter;
+        let rust_name = match self.local(&name).is_some() || self.is_var(&name) {
+            true => format!("{}__{}", mangle(&name), id),
+            false => mangle(&name),
+        };
+        self.scope.push(Binding {
+            name,
+            id,
+            rust_name: rust_name.clone(),
+            parameter,
+            record: None,
+        });
+        AstVal::AstSymbol(rust_name)
     }
-    fn push_body(&mut self, next: SyntaxNode) {
-        self.body.push(next);
+    /// Whether a name is a var of this namespace, defined already or later
+    /// on, which in Rust no local can have the name of.
+    fn is_var(&self, name: &str) -> bool {
+        matches!(
+            self.defs.get(name).or_else(|| self.later.get(name)),
+            Some(Global::Var(_))
+        )
     }
-    fn set_this(&mut self, this: AstVal) {
-        self.this = this
+    /// Leave the scope of the locals bound since it had `depth` of them.
+    fn unbind(&mut self, depth: usize) {
+        self.scope.truncate(depth);
     }
-    fn new_leaf(a: AstVal) -> SyntaxNode {
-        SyntaxNode {
-            this: a,
-            body: Vec::new(),
-            context: Vec::new(),
+    fn local(&self, s: &str) -> Option<&Binding> {
+        self.scope.iter().rev().find(|b| b.name == s)
+    }
+    /// Find what a symbol names: a local, a definition of this namespace, a
+    /// name referred or qualified from another, or a function of the runtime.
+    /// Comes with the name to use for it in Rust.
+    fn resolve(&self, s: &str) -> Resolution {
+        match self.local(s) {
+            Some(b) if b.parameter => Resolution::Parameter(b.id, b.rust_name.clone()),
+            Some(b) => Resolution::Local(b.id, b.rust_name.clone()),
+            None => self.resolve_global(s),
         }
     }
-    fn push_string(&mut self, s: String) {
-        let n = SyntaxNode::new_leaf(AstVal::AstString(s));
-        self.push_body(n);
+    /// Find what a symbol names outside of the locals, as a qualified one
+    /// does even when it is qualified with this namespace.
+    fn resolve_global(&self, s: &str) -> Resolution {
+        if let Some(i) = s.find('/').filter(|_| s.len() > 1) {
+            let (ns, name) = (&s[..i], &s[i + 1..]);
+            if ns == "clojure.core" {
+                return core_fn(name).map_or(Resolution::Unresolved, |(path, f)| {
+                    Resolution::Core(path, f)
+                });
+            }
+            let ns = self.aliases.get(ns).map(|ns| ns.as_str()).unwrap_or(ns);
+            if Some(ns) == self.interp.ns.as_deref() {
+                return self.resolve_global(name);
+            }
+            return match self.namespaces.get(ns) {
+                Some(exports) => {
+                    let (path, global) = resolve_in(exports, ns, name);
+                    Resolution::Global(path, global)
+                }
+                None => {
+                    Diagnostic::error("E0006", format!("No such namespace: {}", &s[..i])).raise()
+                }
+            };
+        }
+        if let Some(global) = self.defs.get(s) {
+            return Resolution::Global(mangle(s), global.clone());
+        }
+        if let Some(ns) = self.refers.get(s) {
+            let (path, global) = resolve_in(&self.namespaces[ns], ns, s);
+            return Resolution::Global(path, global);
+        }
+        core_fn(s).map_or(Resolution::Unresolved, |(path, f)| {
+            Resolution::Core(path, f)
+        })
     }
-    fn push_str(&mut self, s: &str) {
-        self.push_string(s.to_string());
+    /// Look ahead at the definitions of every top-level form, so that a
+    /// `declare` knows what the names it declares will be.
+    pub fn prescan(&mut self, forms: &[AstVal]) {
+        let mut scratch = Globals::new();
+        for form in forms {
+            let v = match form {
+                AstVal::AstList(v) if !v.is_empty() => strip_meta(v).0,
+                _ => continue,
+            };
+            let global = match (&v[0], v.get(1)) {
+                (AstVal::AstSymbol(head), Some(AstVal::AstSymbol(name))) => match head.as_str() {
+                    "defn" | "defn-" if v.len() > 2 => {
+                        let arities = split_arities(fn_tail(&v), &mut scratch);
+                        (name, Global::Fn(fn_info(&arities, &mut scratch)))
+                    }
+                    "def" | "defonce" => (name, Global::Var(var_kind(v.last().unwrap()))),
+                    "defmulti" => (name, Global::Var(VarKind::Static)),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            self.later.entry(global.0.clone()).or_insert(global.1);
+        }
+    }
+    /// The typed wrappers to generate for the functions defined so far.
+    pub fn signatures(&self) -> &[Signature] {
+        &self.signatures
+    }
+    /// The protocols defined so far, the functions of their methods being
+    /// generated once every type they are extended to is known.
+    pub fn protocols(&self) -> &[Protocol] {
+        &self.protocols
+    }
+    /// The protocols extended to types so far.
+    pub fn extensions(&self) -> &[Extension] {
+        &self.extensions
+    }
+    /// The protocol a symbol names: one defined here, or a public one of
+    /// another namespace, referred or qualified.
+    fn protocol(&self, s: &str) -> Option<Protocol> {
+        let (exports, name) = match s.find('/').filter(|_| s.len() > 1) {
+            Some(i) => {
+                let ns = &s[..i];
+                let ns = self.aliases.get(ns).map(|ns| ns.as_str()).unwrap_or(ns);
+                if Some(ns) == self.interp.ns.as_deref() {
+                    return self.protocol(&s[i + 1..]);
+                }
+                (self.namespaces.get(ns)?, &s[i + 1..])
+            }
+            None => match self.protocols.iter().find(|p| p.name == s) {
+                Some(p) => return Some(p.clone()),
+                None => (&self.namespaces[self.refers.get(s)?], s),
+            },
+        };
+        exports.protocols.iter().find(|p| p.name == name).cloned()
+    }
+    /// Define a var that is set from outside the code, like `*1` in the REPL.
+    pub fn define_var(&mut self, name: &str) {
+        self.defs
+            .insert(name.to_string(), Global::Var(VarKind::Static));
+    }
+    /// The name of what a symbol names with its namespace, as `doc` shows it:
+    /// `u/f` is `my.util/f` when `u` is an alias of `my.util`.
+    pub fn qualify(&self, s: &str) -> String {
+        if let Some(i) = s.find('/').filter(|_| s.len() > 1) {
+            let ns = &s[..i];
+            return format!(
+                "{}/{}",
+                self.aliases.get(ns).map_or(ns, |ns| ns.as_str()),
+                &s[i + 1..]
+            );
+        }
+        let protocol = self.protocols.iter().any(|p| p.name == s);
+        if self.defs.contains_key(s) || self.interp.find_macro(s).is_some() || protocol {
+            return format!("{}/{}", self.interp.ns.as_deref().unwrap_or("user"), s);
+        }
+        match self.refers.get(s) {
+            Some(ns) => format!("{}/{}", ns, s),
+            None => format!("clojure.core/{}", s),
+        }
+    }
+    /// Give the struct of a record or the trait of a protocol its name in
+    /// Rust, where they can not share one with each other, nor with a type
+    /// the generated code uses.
+    fn claim_type(&mut self, what: &str, name: &str) {
+        let rust_name = mangle(name);
+        let owner = format!("{} {}", what, name);
+        let other = match self.types.get(&rust_name) {
+            Some(other) if *other != owner => other.clone(),
+            None if RUST_TYPES.contains(&rust_name.as_str()) => {
+                format!("the Rust type {}", rust_name)
+            }
+            _ => {
+                self.types.insert(rust_name, owner);
+                return;
+            }
+        };
+        Diagnostic::error(
+            "E0027",
+            format!("{} would have the same name in Rust as {}", owner, other),
+        )
+        .culprit(name, "defined here")
+        .help("rename one of them")
+        .raise()
     }
-    fn push_context_vec(&mut self, v: Vec<AstVal>) {
-        self.context.push(v);
+    fn gensym(&mut self, prefix: &str) -> AstVal {
+        self.gensym_counter += 1;
+        AstVal::AstSymbol(format!("{}__{}", prefix, self.gensym_counter))
     }
-    fn pop_context_vec(&mut self) -> Option<Vec<AstVal>> {
-        self.context.pop()
+}
+
+fn resolve_in(exports: &Exports, ns: &str, name: &str) -> (String, Global) {
+    if exports.macros.contains_key(name) && !exports.defs.contains_key(name) {
+        Diagnostic::error(
+            "E0009",
+            format!("Can't take value of a macro: #'{}/{}", ns, name),
+        )
+        .raise()
     }
-    fn merge_context_vec(&mut self, v: &Vec<Vec<AstVal>>) {
-        for i in v {
-            self.push_context_vec(i.clone());
+    match exports.defs.get(name) {
+        Some(_) if exports.private.contains(name) => {
+            Diagnostic::error("E0008", format!("var: {}/{} is not public", ns, name))
+                .help(format!(
+                    "{} is defined with ^:private or defn- in {}",
+                    name, ns
+                ))
+                .raise()
         }
+        Some(global) => (
+            format!("{}::{}", exports.module, mangle(name)),
+            global.clone(),
+        ),
+        None => Diagnostic::error("E0007", format!("No such var: {}/{}", ns, name)).raise(),
     }
-    fn merge_context(&mut self, n: &SyntaxNode) {
-        self.merge_context_vec(&n.context);
+}
+
+impl SyntaxNode {
+    pub fn children(&self) -> Vec<&SyntaxNode> {
+        let mut children = Vec::new();
+        match self {
+            SyntaxNode::Fn { arities, .. } | SyntaxNode::Closure { arities, .. } => {
+                for arity in arities {
+                    children.extend(arity.body.iter());
+                }
+            }
+            SyntaxNode::Def { value, .. } => children.push(&**value),
+            SyntaxNode::Nothing
+            | SyntaxNode::Const(_)
+            | SyntaxNode::LocalRef { .. }
+            | SyntaxNode::GlobalRef { .. } => {}
+            SyntaxNode::Do(items)
+            | SyntaxNode::Vector(items)
+            | SyntaxNode::List(items)
+            | SyntaxNode::Println(items)
+            | SyntaxNode::Recur { args: items, .. }
+            | SyntaxNode::Call { args: items, .. } => children.extend(items.iter()),
+            SyntaxNode::RustCall { args, .. } => children.extend(args.iter().map(|(_, arg)| arg)),
+            SyntaxNode::Record { extensions, .. } | SyntaxNode::Extend(extensions) => {
+                for extension in extensions {
+                    for (_, arity) in &extension.methods {
+                        children.extend(arity.body.iter());
+                    }
+                }
+            }
+            SyntaxNode::Protocol(_) => {}
+            SyntaxNode::GetField { target, .. } | SyntaxNode::Init { body: target, .. } => {
+                children.push(&**target)
+            }
+            SyntaxNode::Let { bindings, body } | SyntaxNode::Loop { bindings, body } => {
+                children.extend(bindings.iter().map(|(_, value)| value));
+                children.extend(body.iter());
+            }
+            SyntaxNode::If {
+                test,
+                then,
+                otherwise,
+            } => children.extend([&**test, &**then, &**otherwise]),
+            SyntaxNode::Case {
+                expr,
+                clauses,
+                default,
+            } => {
+                children.push(&**expr);
+                children.extend(clauses.iter().map(|(_, then)| then));
+                children.extend(default.iter().map(|d| &**d));
+            }
+            SyntaxNode::Invoke { target, args } => {
+                children.push(&**target);
+                children.extend(args.iter());
+            }
+            SyntaxNode::HashMap(pairs) => {
+                for (k, v) in pairs {
+                    children.push(k);
+                    children.push(v);
+                }
+            }
+            SyntaxNode::Equal(a, b) => children.extend([&**a, &**b]),
+            SyntaxNode::Try {
+                body,
+                catches,
+                finally,
+            } => {
+                children.extend(body.iter());
+                for catch in catches {
+                    if let Catches::Data(data) = &catch.catches {
+                        children.push(&**data);
+                    }
+                    children.extend(catch.body.iter());
+                }
+                children.extend(finally.iter());
+            }
+        }
+        children
     }
-    pub fn is_in_context(&self, a: &AstVal) -> bool {
-        for v in &self.context {
-            for i in v {
-                if a == i {
-                    return true;
+
+    /// Check that every `recur` is in tail position of its loop or function
+    /// arity.
+    fn check_recur(&self, tail: bool) {
+        let body = |body: &[SyntaxNode], tail: bool| {
+            for (i, item) in body.iter().enumerate() {
+                item.check_recur(tail && i + 1 == body.len());
+            }
+        };
+        match self {
+            SyntaxNode::Recur { args, .. } => {
+                if !tail {
+                    Diagnostic::error("E0003", "Can only recur from tail position")
+                        .culprit("recur", "not in tail position")
+                        .raise()
+                }
+                body(args, false);
+            }
+            SyntaxNode::Fn { arities, .. } | SyntaxNode::Closure { arities, .. } => {
+                for arity in arities {
+                    body(&arity.body, true);
+                }
+            }
+            SyntaxNode::Record { extensions, .. } | SyntaxNode::Extend(extensions) => {
+                for extension in extensions {
+                    for (_, arity) in &extension.methods {
+                        body(&arity.body, true);
+                    }
+                }
+            }
+            SyntaxNode::Loop {
+                bindings,
+                body: items,
+            }
+            | SyntaxNode::Let {
+                bindings,
+                body: items,
+            } => {
+                for (_, value) in bindings {
+                    value.check_recur(false);
+                }
+                body(items, tail || matches!(self, SyntaxNode::Loop { .. }));
+            }
+            SyntaxNode::Do(items) => body(items, tail),
+            SyntaxNode::If {
+                test,
+                then,
+                otherwise,
+            } => {
+                test.check_recur(false);
+                then.check_recur(tail);
+                otherwise.check_recur(tail);
+            }
+            SyntaxNode::Case {
+                expr,
+                clauses,
+                default,
+            } => {
+                expr.check_recur(false);
+                for (_, then) in clauses {
+                    then.check_recur(tail);
+                }
+                if let Some(d) = default {
+                    d.check_recur(tail);
+                }
+            }
+            _ => {
+                for child in self.children() {
+                    child.check_recur(false);
                 }
             }
         }
-        false
     }
+}
 
-    fn disseminate_context(&mut self) {
-        for mut item in self.body.iter_mut() {
-            item.merge_context_vec(&self.context);
-            item.disseminate_context();
+/// Collects the names in Rust of the locals used that were bound before the
+/// binding `first`, which a closure starting there has to capture.
+struct Captures {
+    first: usize,
+    names: Vec<String>,
+}
+
+impl Visitor for Captures {
+    fn visit(&mut self, n: &SyntaxNode) {
+        if let SyntaxNode::LocalRef { id, name } = n {
+            if *id <= self.first && !self.names.contains(name) {
+                self.names.push(name.clone());
+            }
         }
+        walk(self, n)
     }
 }
-fn syntax_let(a: &AstVal) -> SyntaxNode {
-    let mut result = SyntaxNode::new();
+fn syntax_let(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
     match a {
         AstVal::AstList(v) => {
             if v.len() < 2 {
-                panic!("not enough let")
+                Diagnostic::error("E0012", "let requires a vector for its binding").raise()
             }
-            match &v[0] {
-                AstVal::AstSymbol(s) => {
-                    if s == "let" {
-                        result.set_this(AstVal::AstSymbol("do".to_string()));
-                    } else {
-                        panic!("not a let")
-                    }
+            let depth = globals.scope.len();
+            let mut bindings = Vec::new();
+            for (symbol, value) in syntax_bindings(&v[1], globals) {
+                let record = record_of(&value, globals);
+                let value = dispatch_syntax(&value, globals);
+                bindings.push((globals.bind(&symbol, false).to_string(), value));
+                globals.scope.last_mut().unwrap().record = record;
+            }
+            let body = v[2..]
+                .iter()
+                .map(|item| dispatch_syntax(item, globals))
+                .collect();
+            globals.unbind(depth);
+            SyntaxNode::Let { bindings, body }
+        }
+        _ => unreachable!(),
+    }
+}
+/// A binding vector with its binding forms destructured into symbols.
+fn syntax_bindings(a: &AstVal, globals: &mut Globals) -> Vec<(AstVal, AstVal)> {
+    match a {
+        AstVal::AstVec(v1) => {
+            if v1.len() % 2 != 0 {
+                Diagnostic::error(
+                    "E0012",
+                    "let requires an even number of forms in binding vector",
+                )
+                .raise()
+            }
+            let mut bindings = Vec::new();
+            for pair in v1.chunks(2) {
+                bindings.append(&mut destructure(&pair[0], pair[1].clone(), globals));
+            }
+            bindings
+        }
+        AstVal::AstNil => Vec::new(),
+        _ => Diagnostic::error("E0012", "let requires a vector for its binding").raise(),
+    }
+}
+fn call_form(f: &str, args: Vec<AstVal>) -> AstVal {
+    let mut l = vec![AstVal::AstSymbol(f.to_string())];
+    l.extend(args);
+    AstVal::AstList(l)
+}
+/// Desugar a binding form into plain symbol bindings over `nth`, `nthnext`
+/// and `get`, the way `clojure.core/destructure` does, so that every form
+/// that binds locals gets destructuring by going through here.
+fn destructure(target: &AstVal, value: AstVal, globals: &mut Globals) -> Vec<(AstVal, AstVal)> {
+    match target {
+        AstVal::AstSymbol(_) => vec![(target.clone(), value)],
+        AstVal::AstVec(v) => destructure_vec(v, value, globals),
+        AstVal::AstMap(v) => destructure_map(v, value, globals),
+        _ => Diagnostic::error("E0012", format!("unsupported binding form: {:?}", target)).raise(),
+    }
+}
+/// `[a b & rest :as all]`
+fn destructure_vec(v: &[AstVal], value: AstVal, globals: &mut Globals) -> Vec<(AstVal, AstVal)> {
+    let g = globals.gensym("vec");
+    let mut bindings = vec![(g.clone(), value)];
+    let mut n = 0;
+    let mut items = v.iter();
+    while let Some(item) = items.next() {
+        match item {
+            AstVal::AstSymbol(s) if s == "&" => {
+                let rest = match items.next() {
+                    Some(rest) => rest,
+                    None => Diagnostic::error("E0012", "no binding form after &").raise(),
+                };
+                let value = call_form("nthnext", vec![g.clone(), AstVal::AstInt(n)]);
+                bindings.append(&mut destructure(rest, value, globals));
+            }
+            AstVal::AstKeyword(k) if k == "as" => match items.next() {
+                Some(AstVal::AstSymbol(s)) => {
+                    bindings.push((AstVal::AstSymbol(s.clone()), g.clone()));
                 }
-                _ => panic!("not a let"),
+                _ => Diagnostic::error("E0012", "no symbol after :as").raise(),
+            },
+            _ => {
+                let value = call_form("nth", vec![g.clone(), AstVal::AstInt(n), AstVal::AstNil]);
+                bindings.append(&mut destructure(item, value, globals));
+                n += 1;
             }
-            match &v[1] {
-                AstVal::AstVec(v1) => {
-                    if v1.len() % 2 != 0 {
-                        panic!("not enoug")
+        }
+    }
+    bindings
+}
+/// `{:keys [a b] :strs [c] :syms [d] :ns/keys [e] f :f :or {a 1} :as m}`
+fn destructure_map(
+    v: &[(AstVal, AstVal)],
+    value: AstVal,
+    globals: &mut Globals,
+) -> Vec<(AstVal, AstVal)> {
+    let g = globals.gensym("map");
+    let value = call_form("seq_to_map_for_destructuring", vec![value]);
+    let mut bindings = vec![(g.clone(), value)];
+    let mut defaults: Vec<(AstVal, AstVal)> = Vec::new();
+    for (k, d) in v {
+        if k == &AstVal::AstKeyword("or".to_string()) {
+            match d {
+                AstVal::AstMap(d) => defaults = d.clone(),
+                AstVal::AstNil => {}
+                _ => Diagnostic::error("E0012", ":or is not a map").raise(),
+            }
+        }
+    }
+    let lookup = |local: &AstVal, key: AstVal| -> AstVal {
+        let mut args = vec![g.clone(), key];
+        if let Some((_, d)) = defaults.iter().find(|(s, _)| s == local) {
+            args.push(d.clone());
+        }
+        call_form("get", args)
+    };
+    for (k, target) in v {
+        match k {
+            AstVal::AstKeyword(s) if s == "or" => {}
+            AstVal::AstKeyword(s) if s == "as" => match target {
+                AstVal::AstSymbol(_) => bindings.push((target.clone(), g.clone())),
+                _ => Diagnostic::error("E0012", "no symbol after :as").raise(),
+            },
+            AstVal::AstKeyword(s) if is_keys_directive(s) => {
+                let (ns, kind) = match s.find('/') {
+                    Some(i) => (Some(&s[..i]), &s[i + 1..]),
+                    None => (None, s.as_str()),
+                };
+                let names = match target {
+                    AstVal::AstVec(names) => names.clone(),
+                    AstVal::AstNil => Vec::new(),
+                    _ => Diagnostic::error("E0012", format!("{} is not a vector", s)).raise(),
+                };
+                for name in names {
+                    let full = match &name {
+                        AstVal::AstSymbol(n) | AstVal::AstKeyword(n) => match ns {
+                            Some(ns) if !n.contains('/') => format!("{}/{}", ns, n),
+                            _ => n.clone(),
+                        },
+                        _ => Diagnostic::error(
+                            "E0012",
+                            format!("unsupported binding form: {:?}", name),
+                        )
+                        .raise(),
+                    };
+                    let local_name = match full.find('/') {
+                        Some(i) => full[i + 1..].to_string(),
+                        None => full.clone(),
+                    };
+                    let local = AstVal::AstSymbol(local_name);
+                    let key = match kind {
+                        "keys" => AstVal::AstKeyword(full),
+                        "strs" => AstVal::AstString(full),
+                        _ => call_form("quote", vec![AstVal::AstSymbol(full)]),
+                    };
+                    bindings.push((local.clone(), lookup(&local, key)));
+                }
+            }
+            _ => {
+                let value = lookup(k, target.clone());
+                bindings.append(&mut destructure(k, value, globals));
+            }
+        }
+    }
+    bindings
+}
+fn is_keys_directive(s: &str) -> bool {
+    let kind = match s.find('/') {
+        Some(i) => &s[i + 1..],
+        None => s,
+    };
+    kind == "keys" || kind == "syms" || (kind == "strs" && !s.contains('/'))
+}
+fn syntax_if(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    match a {
+        AstVal::AstList(v) => {
+            if v.len() != 3 && v.len() != 4 {
+                Diagnostic::error("E0002", "Too few or too many arguments to if").raise();
+            }
+            SyntaxNode::If {
+                test: Box::new(dispatch_syntax(&v[1], globals)),
+                then: Box::new(dispatch_syntax(&v[2], globals)),
+                otherwise: Box::new(dispatch_syntax(
+                    v.get(3).unwrap_or(&AstVal::AstNil),
+                    globals,
+                )),
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+fn syntax_call(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    let v = match a {
+        AstVal::AstList(v) => v,
+        _ => unreachable!(),
+    };
+    let s = match &v[0] {
+        AstVal::AstSymbol(s) => s,
+        _ => Diagnostic::error("E0016", "can't be called").raise(),
+    };
+    let args: Vec<SyntaxNode> = v[1..]
+        .iter()
+        .map(|item| dispatch_syntax(item, globals))
+        .collect();
+    match globals.resolve(s) {
+        Resolution::Global(path, Global::Fn(info)) | Resolution::Core(path, Global::Fn(info)) => {
+            dispatch_arity(&path, &info, args)
+        }
+        Resolution::Unresolved => match s.as_str() {
+            "println" => SyntaxNode::Println(args),
+            "vector" => SyntaxNode::Vector(args),
+            "list" => SyntaxNode::List(args),
+            "hash-map" => {
+                if !args.len().is_multiple_of(2) {
+                    Diagnostic::error("E0002", "hash-map needs key value pairs").raise()
+                }
+                let mut pairs = Vec::new();
+                let mut items = args.into_iter();
+                while let (Some(k), Some(v)) = (items.next(), items.next()) {
+                    pairs.push((k, v));
+                }
+                SyntaxNode::HashMap(pairs)
+            }
+            _ => Diagnostic::error(
+                "E0001",
+                format!("Unable to resolve symbol: {} in this context", s),
+            )
+            .culprit(s, "not found in this scope")
+            .raise(),
+        },
+        _ => SyntaxNode::Invoke {
+            target: Box::new(syntax_symbol(&v[0], globals)),
+            args,
+        },
+    }
+}
+fn arity_mismatch(n: usize, path: &str) -> ! {
+    let name = demangle_path(path);
+    let called = name.rsplit('/').next().unwrap_or(&name).to_string();
+    Diagnostic::error(
+        "E0002",
+        format!("Wrong number of args ({}) passed to: {}", n, name),
+    )
+    .culprit(&called, "called here")
+    .raise()
+}
+/// The number of arguments is known at the call site, so pick the arity of a
+/// function defined in the file now instead of going through its dispatcher.
+fn dispatch_arity(path: &str, info: &FnInfo, mut args: Vec<SyntaxNode>) -> SyntaxNode {
+    let n = args.len();
+    let path = if info.is_simple() {
+        if info.fixed[0] != n {
+            arity_mismatch(n, path)
+        }
+        path.to_string()
+    } else if info.fixed.contains(&n) {
+        format!("{}__{}", path, n)
+    } else {
+        match info.variadic {
+            Some(required) if n >= required => {
+                let rest_args = args.split_off(required);
+                args.push(if rest_args.is_empty() {
+                    SyntaxNode::Const(AstVal::AstNil)
+                } else {
+                    SyntaxNode::List(rest_args)
+                });
+                format!("{}__rest", path)
+            }
+            _ => arity_mismatch(n, path),
+        }
+    };
+    SyntaxNode::Call { path, args }
+}
+fn syntax_equal(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    match a {
+        AstVal::AstList(v) if v.len() == 3 => SyntaxNode::Equal(
+            Box::new(dispatch_syntax(&v[1], globals)),
+            Box::new(dispatch_syntax(&v[2], globals)),
+        ),
+        _ => Diagnostic::error("E0002", "Wrong number of args passed to =").raise(),
+    }
+}
+/// Parse a parameter vector into the fixed parameters and the one after `&`.
+fn syntax_parameters(a: &AstVal, globals: &mut Globals) -> (Vec<String>, Option<String>) {
+    let mut fixed = Vec::new();
+    let mut rest = None;
+    match a {
+        AstVal::AstVec(v1) => {
+            let mut rest_flag = false;
+            for item in v1 {
+                match item {
+                    AstVal::AstSymbol(s) if s == "&" => {
+                        if rest_flag {
+                            Diagnostic::error("E0012", "more than one &").raise()
+                        }
+                        rest_flag = true;
                     }
-                    let mut symbol_flag = true;
-                    let mut let_expr = SyntaxNode::new();
-                    let mut context_vec: Vec<AstVal> = Vec::new();
-
-                    for item in v1 {
-                        if symbol_flag {
-                            let mut symbol = SyntaxNode::new();
-                            symbol.set_this(item.clone());
-                            let_expr.set_this(AstVal::AstSymbol("let".to_string()));
-                            let_expr.push_body(symbol);
-                            context_vec.push(item.clone());
+                    AstVal::AstSymbol(_) => {
+                        let name = globals.bind(item, true).to_string();
+                        if !rest_flag {
+                            fixed.push(name);
+                        } else if rest.is_none() {
+                            rest = Some(name);
                         } else {
-                            let expr = dispatch_syntax(item);
-                            let_expr.push_body(expr);
-                            result.push_body(let_expr.clone());
-                            let_expr = SyntaxNode::new();
+                            Diagnostic::error("E0012", "only one parameter after &").raise()
                         }
-                        symbol_flag = !symbol_flag;
                     }
-                    result.push_context_vec(context_vec);
+                    _ => {
+                        Diagnostic::error("E0012", "Unsupported binding form in a parameter vector")
+                            .raise()
+                    }
                 }
-                _ => panic!("not a let vec"),
             }
-            if v.len() == 2 {
-                return result;
-            } else {
-                for item in v[2..].to_vec() {
-                    result.push_body(dispatch_syntax(&item));
-                }
-                return result;
+            if rest_flag && rest.is_none() {
+                Diagnostic::error("E0012", "no parameter after &").raise()
+            }
+        }
+        AstVal::AstNil => {}
+        _ => Diagnostic::error("E0013", "Parameter declaration missing").raise(),
+    }
+    (fixed, rest)
+}
+fn syntax_body(v: &[AstVal], globals: &mut Globals) -> Vec<SyntaxNode> {
+    v.iter()
+        .map(|item| dispatch_syntax(item, globals))
+        .collect()
+}
+/// Split what follows the name of a `defn` or `fn` into its arities, either
+/// `[x] body...` or `([x] body...) ([x y] body...)`.
+fn split_arities(v: &[AstVal], globals: &mut Globals) -> Vec<(AstVal, Vec<AstVal>)> {
+    let arities = match v.first() {
+        Some(AstVal::AstVec(_)) | Some(AstVal::AstNil) => vec![(v[0].clone(), v[1..].to_vec())],
+        Some(AstVal::AstList(_)) => v
+            .iter()
+            .map(|item| match item {
+                AstVal::AstList(l) => match &l[0] {
+                    AstVal::AstVec(_) | AstVal::AstNil => (l[0].clone(), l[1..].to_vec()),
+                    _ => Diagnostic::error("E0013", "Parameter declaration missing").raise(),
+                },
+                _ => Diagnostic::error("E0013", "Parameter declaration missing").raise(),
+            })
+            .collect(),
+        _ => Diagnostic::error("E0013", "Parameter declaration missing").raise(),
+    };
+    arities
+        .into_iter()
+        .map(|(parameters, body)| destructure_parameters(&parameters, body, globals))
+        .collect()
+}
+/// Replace the parameters that are binding forms with plain symbols, and
+/// destructure those in a `let` around the body.
+fn destructure_parameters(
+    parameters: &AstVal,
+    body: Vec<AstVal>,
+    globals: &mut Globals,
+) -> (AstVal, Vec<AstVal>) {
+    let v = match parameters {
+        AstVal::AstVec(v) => v,
+        _ => return (parameters.clone(), body),
+    };
+    let mut plain = AstVal::new_vec();
+    let mut bindings = AstVal::new_vec();
+    for item in v {
+        match item {
+            AstVal::AstSymbol(_) => plain.push(item.clone()),
+            _ => {
+                let p = globals.gensym("p");
+                plain.push(p.clone());
+                bindings.push(item.clone());
+                bindings.push(p);
+            }
+        }
+    }
+    if bindings.len() == 0 {
+        return (plain, body);
+    }
+    let mut l = vec![AstVal::AstSymbol("let".to_string()), bindings];
+    l.extend(body);
+    (plain, vec![AstVal::AstList(l)])
+}
+fn fn_info(arities: &[(AstVal, Vec<AstVal>)], globals: &mut Globals) -> FnInfo {
+    let mut info = FnInfo::default();
+    for (parameters, _) in arities {
+        let depth = globals.scope.len();
+        let (fixed, rest) = syntax_parameters(parameters, globals);
+        globals.unbind(depth);
+        let n = fixed.len();
+        if rest.is_some() {
+            if info.variadic.is_some() {
+                Diagnostic::error("E0011", "Can't have more than 1 variadic overload").raise()
             }
+            info.variadic = Some(n);
+        } else if info.fixed.contains(&n) {
+            Diagnostic::error("E0011", "Can't have 2 overloads with same arity").raise()
+        } else {
+            info.fixed.push(n);
         }
-        _ => panic!("not a let"),
     }
+    info
+}
+fn syntax_arity(parameters: &AstVal, body: &[AstVal], globals: &mut Globals) -> Arity {
+    syntax_method(parameters, body, None, globals)
+}
+/// An arity the first parameter of which is known to be a record of the
+/// type `record`, if it is given, like the one of a protocol method
+/// implemented for it.
+fn syntax_method(
+    parameters: &AstVal,
+    body: &[AstVal],
+    record: Option<String>,
+    globals: &mut Globals,
+) -> Arity {
+    let depth = globals.scope.len();
+    let (fixed, rest) = syntax_parameters(parameters, globals);
+    if let Some(this) = globals.scope.get_mut(depth) {
+        this.record = record;
+    }
+    let target = fixed.iter().chain(rest.iter()).cloned().collect();
+    let outer = globals.recur_target.replace(target);
+    let body = syntax_body(body, globals);
+    globals.recur_target = outer;
+    globals.unbind(depth);
+    Arity { fixed, rest, body }
 }
-fn syntax_if(a: &AstVal) -> SyntaxNode {
-    let mut result = SyntaxNode::new();
+fn syntax_defn(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
     match a {
         AstVal::AstList(v) => {
-            if v.len() != 4 {
-                panic!("not a full if");
+            if v.len() < 3 {
+                Diagnostic::error("E0002", "Wrong number of args passed to defn").raise()
             }
-            match &v[0] {
+            let (doc, sig) = defn_meta(v);
+            let (v, private) = strip_meta(v);
+            let tail = fn_tail(&v);
+            let arities = split_arities(tail, globals);
+            let name = match &v[1] {
                 AstVal::AstSymbol(s) => {
-                    if s == "if" {
-                        result.set_this(v[0].clone());
-                        let mut n = SyntaxNode::new();
-                        n.set_this(AstVal::AstSymbol("do".to_string()));
-                        n.push_body(dispatch_syntax(&v[1]));
-                        result.push_body(n);
-                        let mut n = SyntaxNode::new();
-                        n.set_this(AstVal::AstSymbol("do".to_string()));
-                        n.push_body(dispatch_syntax(&v[2]));
-                        result.push_body(n);
-                        let mut n = SyntaxNode::new();
-                        n.set_this(AstVal::AstSymbol("do".to_string()));
-                        n.push_body(dispatch_syntax(&v[3]));
-                        result.push_body(n);
-                        return result;
-                    } else {
-                        panic!("not a if")
+                    warn_if_core(s);
+                    let info = fn_info(&arities, globals);
+                    if let Some(sig) = sig {
+                        let signature = signature(s, &sig, &info, private, doc.clone());
+                        globals
+                            .signatures
+                            .retain(|other| other.name != signature.name);
+                        globals.signatures.push(signature);
                     }
+                    globals.defs.insert(s.clone(), Global::Fn(info));
+                    globals.interp.defn(s, tail);
+                    if private {
+                        globals.private.insert(s.clone());
+                    }
+                    mangle(s)
+                }
+                _ => Diagnostic::error("E0013", "First argument to defn must be a symbol").raise(),
+            };
+            SyntaxNode::Fn {
+                name,
+                public: !private,
+                arities: arities
+                    .into_iter()
+                    .map(|(parameters, body)| syntax_arity(&parameters, &body, globals))
+                    .collect(),
+                doc,
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+/// The docstring of a `defn`, and the `:rust/sig` of the metadata before
+/// its name or of the attribute map after it.
+fn defn_meta(v: &[AstVal]) -> (Option<String>, Option<String>) {
+    let mut entries = Vec::new();
+    let mut i = 1;
+    while let Some(AstVal::AstMeta(m)) = v.get(i) {
+        entries.extend(m.iter());
+        i += 1;
+    }
+    let mut doc = None;
+    let mut rest = v.get(i + 1..).unwrap_or(&[]);
+    while rest.len() > 1 {
+        match &rest[0] {
+            AstVal::AstString(s) => doc = Some(s.clone()),
+            AstVal::AstMap(m) => entries.extend(m.iter()),
+            _ => break,
+        }
+        rest = &rest[1..];
+    }
+    let mut sig = None;
+    for (k, value) in entries {
+        match (k, value) {
+            (AstVal::AstKeyword(k), AstVal::AstString(s)) if k == "rust/sig" => {
+                sig = Some(s.clone())
+            }
+            (AstVal::AstKeyword(k), AstVal::AstString(s)) if k == "doc" => doc = Some(s.clone()),
+            (AstVal::AstKeyword(k), _) if k == "rust/sig" => Diagnostic::error(
+                "E0023",
+                format!("bad :rust/sig {}, expected a string", value),
+            )
+            .help("like \"fn(i64, &str) -> String\"")
+            .raise(),
+            _ => {}
+        }
+    }
+    (doc, sig)
+}
+/// The typed wrapper of the function `name` with the signature `sig`, which
+/// takes one of its arities.
+fn signature(
+    name: &str,
+    sig: &str,
+    info: &FnInfo,
+    private: bool,
+    doc: Option<String>,
+) -> Signature {
+    let bad = |message: String| -> ! {
+        Diagnostic::error("E0023", message)
+            .culprit(name, "defined here")
+            .help("like ^{:rust/sig \"fn(i64, &str) -> String\"}")
+            .raise()
+    };
+    if private {
+        Diagnostic::error(
+            "E0023",
+            format!("{} is private, and so gets no Rust signature", name),
+        )
+        .culprit(name, "defined here")
+        .help("make it public with defn, or remove its :rust/sig")
+        .raise()
+    }
+    let sig = sig.trim();
+    let (params, ret) = match (sig.strip_prefix("fn"), sig.rfind(')')) {
+        (Some(rest), Some(close)) if rest.trim_start().starts_with('(') => {
+            let open = sig.find('(').unwrap();
+            (&sig[open + 1..close], sig[close + 1..].trim())
+        }
+        _ => bad(format!(
+            "bad :rust/sig \"{}\" of {}, expected a fn type",
+            sig, name
+        )),
+    };
+    let ret = match ret.strip_prefix("->").map(str::trim) {
+        None if ret.is_empty() => None,
+        Some("()") => None,
+        Some(t) if t.starts_with('&') => {
+            bad(format!("{} can not return the reference {}", name, t))
+        }
+        Some(t) if !t.is_empty() => Some(t.to_string()),
+        _ => bad(format!(
+            "bad return type in :rust/sig \"{}\" of {}",
+            sig, name
+        )),
+    };
+    let params = split_types(params);
+    let n = params.len();
+    if !info.fixed.contains(&n) && info.variadic.is_none_or(|required| n < required) {
+        bad(format!(
+            "the :rust/sig of {} takes {} arguments, which it has no arity for",
+            name, n
+        ))
+    }
+    Signature {
+        name: mangle(name),
+        params,
+        ret,
+        dispatcher: !info.is_simple(),
+        doc,
+    }
+}
+/// The types in a list of them, split at the commas that are not in the
+/// brackets of one, like `HashMap<String, i64>`.
+fn split_types(list: &str) -> Vec<String> {
+    let mut types = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in list.char_indices() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            '>' | ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                types.push(list[start..i].trim().to_string());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    let last = list[start..].trim();
+    if !last.is_empty() {
+        types.push(last.to_string());
+    }
+    types
+}
+/// What follows the name of a `defn`, without its docstring and attribute
+/// map.
+pub fn fn_tail(v: &[AstVal]) -> &[AstVal] {
+    let mut rest = &v[2..];
+    while rest.len() > 1 {
+        match rest[0] {
+            AstVal::AstString(_) | AstVal::AstMap(_) => rest = &rest[1..],
+            _ => break,
+        }
+    }
+    rest
+}
+/// `(defmacro name [params] body...)` defines a function the interpreter
+/// runs at compile time on the forms calling it, and compiles to nothing.
+fn syntax_defmacro(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    match a {
+        AstVal::AstList(v) if v.len() > 2 => {
+            let (v, private) = strip_meta(v);
+            let name = globals.interp.defmacro(&v);
+            if private {
+                globals.private.insert(name);
+            }
+            SyntaxNode::Nothing
+        }
+        _ => Diagnostic::error("E0002", "Wrong number of args passed to defmacro").raise(),
+    }
+}
+/// `(macroexpand '(m x))` is expanded at compile time, into the quoted
+/// expansion.
+fn syntax_macroexpand(a: &AstVal, globals: &mut Globals, once: bool) -> SyntaxNode {
+    let form = match a {
+        AstVal::AstList(v) if v.len() == 2 => match &v[1] {
+            AstVal::AstList(q)
+                if q.len() == 2
+                    && (q[0] == AstVal::AstSymbol("quote".to_string())
+                        || q[0] == AstVal::AstSymbol("'".to_string())) =>
+            {
+                &q[1]
+            }
+            _ => Diagnostic::error("E0016", "macroexpand needs a quoted form at compile time")
+                .raise(),
+        },
+        _ => Diagnostic::error("E0002", "Wrong number of args passed to macroexpand").raise(),
+    };
+    let expanded = if once {
+        globals.macroexpand_1(form).unwrap_or_else(|| form.clone())
+    } else {
+        globals.macroexpand(form)
+    };
+    SyntaxNode::Const(expanded)
+}
+/// The form without the metadata in front of the name, and whether that
+/// metadata makes the definition private.
+fn strip_meta(v: &[AstVal]) -> (Vec<AstVal>, bool) {
+    let mut private = v[0] == AstVal::AstSymbol("defn-".to_string());
+    let mut result = vec![v[0].clone()];
+    let mut items = v[1..].iter().peekable();
+    while let Some(AstVal::AstMeta(m)) = items.peek() {
+        for (k, item) in m {
+            if k == &AstVal::AstKeyword("private".to_string()) && item == &AstVal::AstBool(true) {
+                private = true;
+            }
+        }
+        items.next();
+    }
+    result.extend(items.cloned());
+    (result, private)
+}
+/// Defining a name of clojure.core is allowed, but hides the core function.
+fn warn_if_core(name: &str) {
+    if core_fn(name).is_some() {
+        Diagnostic::warning(
+            "W0001",
+            format!(
+                "{0} already refers to: #'clojure.core/{0}, being replaced by this definition",
+                name
+            ),
+        )
+        .culprit(name, "defined here")
+        .help(format!(
+            "rename it, or use clojure.core/{} where the core function is meant",
+            name
+        ))
+        .emit();
+    }
+}
+fn var_kind(value: &AstVal) -> VarKind {
+    match value {
+        AstVal::AstNil | AstVal::AstBool(_) | AstVal::AstInt(_) | AstVal::AstFloat(_) => {
+            VarKind::Const
+        }
+        _ => VarKind::Static,
+    }
+}
+/// `(def name doc? value)` at the top level. A `defonce` of a name that is
+/// already defined compiles to nothing.
+fn syntax_def(a: &AstVal, globals: &mut Globals, once: bool) -> SyntaxNode {
+    match a {
+        AstVal::AstList(v) => {
+            let (v, private) = strip_meta(v);
+            let name = match v.get(1) {
+                Some(AstVal::AstSymbol(s)) => {
+                    warn_if_core(s);
+                    s.clone()
+                }
+                _ => Diagnostic::error("E0013", "First argument to def must be a Symbol").raise(),
+            };
+            let value = match v.len() {
+                3 => v[2].clone(),
+                4 => v[3].clone(),
+                _ => Diagnostic::error(
+                    "E0002",
+                    "def takes a name, an optional docstring and a value",
+                )
+                .raise(),
+            };
+            if once {
+                if let Some(Global::Var(_)) = globals.defs.get(&name) {
+                    return SyntaxNode::Nothing;
                 }
-                _ => panic!("not a symbol list"),
+            }
+            let kind = var_kind(&value);
+            if private {
+                globals.private.insert(name.clone());
+            }
+            globals.defs.insert(name.clone(), Global::Var(kind));
+            SyntaxNode::Def {
+                name: mangle(&name),
+                kind,
+                public: !private,
+                value: Box::new(dispatch_syntax(&value, globals)),
             }
         }
-        _ => panic!("not a list"),
+        _ => unreachable!(),
     }
 }
-fn syntax_call(a: &AstVal) -> SyntaxNode {
-    let mut result = SyntaxNode::new();
+/// `(declare a b)` makes the names usable before their definitions further
+/// down the file, and compiles to nothing itself.
+fn syntax_declare(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
     match a {
-        AstVal::AstList(v) => match &v[0] {
-            AstVal::AstSymbol(s) => {
-                result.set_this(AstVal::AstSymbol(s.clone()));
-                if v.len() > 1 {
-                    for brother in v[1..].to_vec() {
-                        result.push_body(dispatch_syntax(&brother));
+        AstVal::AstList(v) => {
+            for item in &v[1..] {
+                match item {
+                    AstVal::AstSymbol(s) => match globals.later.get(s) {
+                        Some(global) => {
+                            let global = global.clone();
+                            globals.defs.entry(s.clone()).or_insert(global);
+                        }
+                        None => Diagnostic::error(
+                            "E0013",
+                            format!("{} is declared but never defined", s),
+                        )
+                        .raise(),
+                    },
+                    _ => Diagnostic::error("E0013", "declare takes symbols").raise(),
+                }
+            }
+            SyntaxNode::Nothing
+        }
+        _ => unreachable!(),
+    }
+}
+/// One `[my.util :as u :refer [f]]` of the `:require` of an `ns` form.
+#[derive(Debug, Clone)]
+pub struct Require {
+    pub ns: String,
+    pub alias: Option<String>,
+    pub refer: Vec<String>,
+    pub refer_all: bool,
+}
+/// What an `ns` form says: the name of the namespace and what it requires.
+#[derive(Debug, Clone)]
+pub struct NsForm {
+    pub name: String,
+    pub requires: Vec<Require>,
+}
+fn parse_require(spec: &AstVal) -> Require {
+    let v = match spec {
+        AstVal::AstSymbol(ns) => {
+            return Require {
+                ns: ns.clone(),
+                alias: None,
+                refer: Vec::new(),
+                refer_all: false,
+            }
+        }
+        AstVal::AstVec(v) if !v.is_empty() => v,
+        _ => Diagnostic::error("E0014", format!("bad require: {}", spec)).raise(),
+    };
+    let mut result = Require {
+        ns: match &v[0] {
+            AstVal::AstSymbol(ns) => ns.clone(),
+            _ => Diagnostic::error("E0014", format!("bad require: {}", spec)).raise(),
+        },
+        alias: None,
+        refer: Vec::new(),
+        refer_all: false,
+    };
+    for option in v[1..].chunks(2) {
+        match (&option[0], option.get(1)) {
+            (AstVal::AstKeyword(k), Some(AstVal::AstSymbol(alias))) if k == "as" => {
+                result.alias = Some(alias.clone())
+            }
+            (AstVal::AstKeyword(k), Some(AstVal::AstKeyword(all)))
+                if k == "refer" && all == "all" =>
+            {
+                result.refer_all = true
+            }
+            (AstVal::AstKeyword(k), Some(AstVal::AstVec(names))) if k == "refer" => {
+                for name in names {
+                    match name {
+                        AstVal::AstSymbol(name) => result.refer.push(name.clone()),
+                        _ => Diagnostic::error("E0014", format!("bad refer: {}", name)).raise(),
                     }
                 }
-                return result;
             }
-            _ => panic!("can't be called"),
+            _ => Diagnostic::error("E0014", format!("bad require: {}", spec)).raise(),
+        }
+    }
+    result
+}
+/// Read `(ns name (:require ...))`, or `None` for any other form.
+pub fn parse_ns(a: &AstVal) -> Option<NsForm> {
+    let v = match a {
+        AstVal::AstList(v) if a.list_type() == Some("ns".to_string()) => strip_meta(v).0,
+        _ => return None,
+    };
+    let mut result = NsForm {
+        name: match v.get(1) {
+            Some(AstVal::AstSymbol(name)) => name.clone(),
+            _ => Diagnostic::error("E0014", "ns needs a name").raise(),
         },
-        _ => {
-            result.set_this(a.clone());
-            return result;
+        requires: Vec::new(),
+    };
+    for clause in &v[2..] {
+        match clause {
+            AstVal::AstString(_) => {}
+            AstVal::AstList(c) if c[0] == AstVal::AstKeyword("require".to_string()) => {
+                result.requires.extend(c[1..].iter().map(parse_require))
+            }
+            AstVal::AstList(c) if c[0] == AstVal::AstKeyword("refer-clojure".to_string()) => {}
+            _ => {
+                Diagnostic::error("E0014", format!("not support ns clause yet: {}", clause)).raise()
+            }
+        }
+    }
+    Some(result)
+}
+/// `(ns ...)` sets up the aliases and referred names of the namespaces it
+/// requires, which must have been handed over with `Globals::require` by
+/// then, and compiles to nothing itself.
+fn syntax_ns(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    let ns = parse_ns(a).unwrap();
+    globals.interp.ns = Some(ns.name.clone());
+    for r in &ns.requires {
+        let exports = match globals.namespaces.get(&r.ns) {
+            Some(exports) => exports,
+            None => Diagnostic::error("E0006", format!("No such namespace: {}", r.ns)).raise(),
+        };
+        if let Some(alias) = &r.alias {
+            globals.aliases.insert(alias.clone(), r.ns.clone());
+        }
+        let refer: Vec<String> = if r.refer_all {
+            exports
+                .defs
+                .keys()
+                .chain(exports.macros.keys())
+                .filter(|name| !exports.private.contains(*name))
+                .cloned()
+                .chain(exports.protocols.iter().map(|p| p.name.clone()))
+                .collect()
+        } else {
+            r.refer.clone()
+        };
+        for name in refer {
+            if !exports.macros.contains_key(&name)
+                && !exports.protocols.iter().any(|p| p.name == name)
+            {
+                resolve_in(exports, &r.ns, &name);
+            }
+            globals.refers.insert(name, r.ns.clone());
         }
     }
+    SyntaxNode::Nothing
 }
-fn syntax_equal(a: &AstVal) -> SyntaxNode {
-    let mut result = SyntaxNode::new();
+/// `(fn [x] ...)` in expression position, compiled to a Rust closure.
+/// `(fn name [x] ...)` is bound to `name` in its body.
+fn syntax_fn(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
     match a {
         AstVal::AstList(v) => {
-            if v.len() != 3 {
-                panic!("not a equal = call")
+            let first = globals.gensym_counter;
+            let depth = globals.scope.len();
+            let (name, v) = match v.get(1) {
+                Some(name @ AstVal::AstSymbol(_)) => {
+                    (Some(globals.bind(name, false).to_string()), &v[1..])
+                }
+                _ => (None, &v[..]),
+            };
+            if v.len() < 2 {
+                Diagnostic::error("E0013", "Parameter declaration missing").raise()
             }
-            match &v[0] {
-                AstVal::AstSymbol(s) => {
-                    if s == "=" {
-                        result.set_this(AstVal::AstSymbol(s.to_string()));
-                        result.push_body(dispatch_syntax(&v[1]));
-                        result.push_body(dispatch_syntax(&v[2]));
-                        return result;
-                    }else{
-                        panic!("not a equal")
-                    }
+            let arities = split_arities(&v[1..], globals);
+            fn_info(&arities, globals);
+            let arities: Vec<Arity> = arities
+                .into_iter()
+                .map(|(parameters, body)| syntax_arity(&parameters, &body, globals))
+                .collect();
+            let mut captures = Captures {
+                first,
+                names: Vec::new(),
+            };
+            for arity in &arities {
+                for item in &arity.body {
+                    captures.visit(item);
                 }
-                _ => panic!("not a equal"),
+            }
+            globals.unbind(depth);
+            SyntaxNode::Closure {
+                name,
+                captures: captures.names,
+                arities,
             }
         }
-        _ => panic!("not a equal list"),
+        _ => unreachable!(),
     }
 }
-fn syntax_defn(a: &AstVal) -> SyntaxNode {
-    let mut result = SyntaxNode::new();
+/// `(loop [bindings] body...)`. Binding forms that are not symbols are
+/// bound to a fresh symbol, which `recur` rebinds, and destructured from it
+/// in a `let` around the body, as with function parameters.
+fn syntax_loop(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
     match a {
         AstVal::AstList(v) => {
-            if v.len() <= 3 {
-                panic!("not enough defn")
+            if v.len() < 2 {
+                Diagnostic::error("E0012", "loop requires a vector for its binding").raise()
             }
-            match &v[0] {
-                AstVal::AstSymbol(s) => {
-                    if s != "defn" {
-                        panic!("not a defn")
-                    } else {
-                        result.set_this(AstVal::AstSymbol("fn".to_string()));
+            let pairs = match &v[1] {
+                AstVal::AstVec(v1) => {
+                    if v1.len() % 2 != 0 {
+                        Diagnostic::error(
+                            "E0012",
+                            "loop requires an even number of forms in binding vector",
+                        )
+                        .raise()
                     }
+                    v1.chunks(2)
+                        .map(|pair| (pair[0].clone(), pair[1].clone()))
+                        .collect()
                 }
-                _ => panic!("not a defn"),
+                AstVal::AstNil => Vec::new(),
+                _ => Diagnostic::error("E0012", "loop requires a vector for its binding").raise(),
+            };
+            let mut bindings = Vec::new();
+            let mut destructured = AstVal::new_vec();
+            let depth = globals.scope.len();
+            for (target, value) in pairs {
+                let symbol = match target {
+                    AstVal::AstSymbol(_) => target,
+                    _ => {
+                        let g = globals.gensym("loop");
+                        destructured.push(target);
+                        destructured.push(g.clone());
+                        g
+                    }
+                };
+                let value = dispatch_syntax(&value, globals);
+                bindings.push((globals.bind(&symbol, false).to_string(), value));
             }
-            match &v[1] {
-                AstVal::AstSymbol(s) => {
-                    let mut n = SyntaxNode::new();
-                    n.set_this(AstVal::AstSymbol("name".to_string()));
-                    n.push_string(s.clone());
-                    result.push_body(n);
+            let target = bindings.iter().map(|(name, _)| name.clone()).collect();
+            let outer = globals.recur_target.replace(target);
+            let body = if destructured.len() == 0 {
+                syntax_body(&v[2..], globals)
+            } else {
+                let mut l = vec![AstVal::AstSymbol("let".to_string()), destructured];
+                l.extend(v[2..].iter().cloned());
+                syntax_body(&[AstVal::AstList(l)], globals)
+            };
+            globals.recur_target = outer;
+            globals.unbind(depth);
+            SyntaxNode::Loop { bindings, body }
+        }
+        _ => unreachable!(),
+    }
+}
+fn syntax_recur(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    match a {
+        AstVal::AstList(v) => {
+            let targets = match &globals.recur_target {
+                Some(targets) => targets.clone(),
+                None => Diagnostic::error("E0005", "recur outside of loop or fn")
+                    .culprit("recur", "no loop or fn to recur to")
+                    .raise(),
+            };
+            if v.len() - 1 != targets.len() {
+                Diagnostic::error(
+                    "E0004",
+                    format!(
+                        "Mismatched argument count to recur, expected: {} args, got: {}",
+                        targets.len(),
+                        v.len() - 1
+                    ),
+                )
+                .culprit("recur", &format!("expected {} args", targets.len()))
+                .raise()
+            }
+            SyntaxNode::Recur {
+                targets,
+                args: syntax_body(&v[1..], globals),
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+/// `(quote x)`, and `'x`. The metadata of `x` is left out.
+fn syntax_quote(a: &AstVal) -> SyntaxNode {
+    let v = match a {
+        AstVal::AstList(v) => v,
+        _ => unreachable!(),
+    };
+    let quoted: Vec<&AstVal> = v[1..]
+        .iter()
+        .filter(|x| !matches!(x, AstVal::AstMeta(_)))
+        .collect();
+    match quoted[..] {
+        [x] => SyntaxNode::Const(x.clone()),
+        _ => Diagnostic::error(
+            "E0002",
+            format!("Wrong number of args ({}) passed to quote", quoted.len()),
+        )
+        .culprit("quote", "called here")
+        .raise(),
+    }
+}
+fn syntax_map(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    match a {
+        AstVal::AstMap(v) => SyntaxNode::HashMap(
+            v.iter()
+                .map(|(k, item)| (dispatch_syntax(k, globals), dispatch_syntax(item, globals)))
+                .collect(),
+        ),
+        _ => unreachable!(),
+    }
+}
+fn syntax_vector(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    match a {
+        AstVal::AstVec(v) => SyntaxNode::Vector(syntax_body(v, globals)),
+        _ => unreachable!(),
+    }
+}
+/// A bare symbol. Naming a function defined earlier in the file, referred
+/// from another namespace or of the runtime, makes it a function value, so
+/// the parameter count goes along for the wrapper, unless the function
+/// already has a dispatcher taking a slice. Naming a `def` brings along how
+/// it is compiled.
+fn syntax_symbol(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    let s = match a {
+        AstVal::AstSymbol(s) => s,
+        _ => return SyntaxNode::Const(a.clone()),
+    };
+    let (path, global) = match globals.resolve(s) {
+        Resolution::Local(id, name) | Resolution::Parameter(id, name) => {
+            return SyntaxNode::LocalRef { id, name };
+        }
+        Resolution::Global(path, global) | Resolution::Core(path, global) => (path, global),
+        Resolution::Unresolved => Diagnostic::error(
+            "E0001",
+            format!("Unable to resolve symbol: {} in this context", s),
+        )
+        .culprit(s, "not found in this scope")
+        .raise(),
+    };
+    let kind = match global {
+        Global::Fn(ref info) if info.is_simple() => RefKind::Fn(info.fixed[0]),
+        Global::Fn(_) => RefKind::Dispatcher,
+        Global::Var(kind) => RefKind::Var(kind),
+    };
+    SyntaxNode::GlobalRef { path, kind }
+}
+fn sym(s: &str) -> AstVal {
+    AstVal::AstSymbol(s.to_string())
+}
+fn let_form(bindings: Vec<AstVal>, body: &[AstVal]) -> AstVal {
+    let mut l = vec![sym("let"), AstVal::AstVec(bindings)];
+    l.extend(body.iter().cloned());
+    AstVal::AstList(l)
+}
+fn do_form(body: &[AstVal]) -> AstVal {
+    call_form("do", body.to_vec())
+}
+/// Whether every key of a `case` can be a pattern of a Rust `match`.
+fn literal_case_keys(clauses: &[AstVal]) -> bool {
+    let literal = |k: &AstVal| {
+        matches!(
+            k,
+            AstVal::AstNil
+                | AstVal::AstBool(_)
+                | AstVal::AstInt(_)
+                | AstVal::AstFloat(_)
+                | AstVal::AstString(_)
+                | AstVal::AstKeyword(_)
+                | AstVal::AstSymbol(_)
+        )
+    };
+    clauses
+        .chunks(2)
+        .filter(|c| c.len() == 2)
+        .all(|c| match &c[0] {
+            AstVal::AstList(keys) => keys.iter().all(literal),
+            k => literal(k),
+        })
+}
+/// Expand the macros of `clojure.core` the compiler has built in into the
+/// special forms. A `case` with literal keys is left to become a `match`
+/// when `match_case` is set, and is a chain of `if` otherwise.
+pub fn expand_core_macro(
+    form: &AstVal,
+    match_case: bool,
+    gensym: &mut dyn FnMut(&str) -> AstVal,
+) -> Option<AstVal> {
+    let (head, args) = match form {
+        AstVal::AstList(v) => match v.first() {
+            Some(AstVal::AstSymbol(s)) => (s.as_str(), &v[1..]),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let head = head.strip_prefix("clojure.core/").unwrap_or(head);
+    let arg = |i: usize| match args.get(i) {
+        Some(a) => a.clone(),
+        None => Diagnostic::error(
+            "E0002",
+            format!("Wrong number of args ({}) passed to: {}", args.len(), head),
+        )
+        .raise(),
+    };
+    // The arguments from the `i`th on, of which there must be some before.
+    let rest = |i: usize| {
+        if args.len() < i {
+            arg(i);
+        }
+        &args[i..]
+    };
+    let bindings = |head: &str| match args.first() {
+        Some(AstVal::AstVec(b)) if b.len() == 2 => (b[0].clone(), b[1].clone()),
+        _ => Diagnostic::error(
+            "E0002",
+            format!(
+                "{} requires a vector for its binding with exactly 2 forms",
+                head
+            ),
+        )
+        .raise(),
+    };
+    let expanded = match head {
+        "when" => call_form("if", vec![arg(0), do_form(&args[1..]), AstVal::AstNil]),
+        "when-not" => call_form("if", vec![arg(0), AstVal::AstNil, do_form(&args[1..])]),
+        "cond" => {
+            if args.len() % 2 != 0 {
+                Diagnostic::error("E0002", "cond requires an even number of forms").raise()
+            }
+            args.chunks(2)
+                .rev()
+                .fold(AstVal::AstNil, |rest, c| match &c[0] {
+                    AstVal::AstKeyword(_) | AstVal::AstBool(true) => c[1].clone(),
+                    test => call_form("if", vec![test.clone(), c[1].clone(), rest]),
+                })
+        }
+        "condp" => {
+            let clauses = rest(2);
+            let g = gensym("condp");
+            if clauses.contains(&AstVal::AstKeyword(">>".to_string())) {
+                Diagnostic::error("E0016", "condp :>> not support yet").raise()
+            }
+            let default = match clauses.len() % 2 {
+                1 => clauses[clauses.len() - 1].clone(),
+                _ => call_form("no_matching_clause", vec![g.clone()]),
+            };
+            let chain =
+                clauses
+                    .chunks(2)
+                    .filter(|c| c.len() == 2)
+                    .rev()
+                    .fold(default, |rest, c| {
+                        let test = AstVal::AstList(vec![arg(0), c[0].clone(), g.clone()]);
+                        call_form("if", vec![test, c[1].clone(), rest])
+                    });
+            let_form(vec![g, arg(1)], &[chain])
+        }
+        "case" => {
+            let clauses = rest(1);
+            if match_case && literal_case_keys(clauses) {
+                return None;
+            }
+            let g = gensym("case");
+            let default = match clauses.len() % 2 {
+                1 => clauses[clauses.len() - 1].clone(),
+                _ => call_form("no_matching_clause", vec![g.clone()]),
+            };
+            let chain =
+                clauses
+                    .chunks(2)
+                    .filter(|c| c.len() == 2)
+                    .rev()
+                    .fold(default, |rest, c| {
+                        let keys = match &c[0] {
+                            AstVal::AstList(keys) => keys.clone(),
+                            k => vec![k.clone()],
+                        };
+                        let tests = keys
+                            .into_iter()
+                            .map(|k| call_form("=", vec![g.clone(), call_form("quote", vec![k])]))
+                            .collect();
+                        call_form("if", vec![call_form("or", tests), c[1].clone(), rest])
+                    });
+            let_form(vec![g, arg(0)], &[chain])
+        }
+        "->" | "->>" => rest(1).iter().fold(arg(0), |acc, f| match f {
+            AstVal::AstList(v) if v.is_empty() => AstVal::AstList(vec![f.clone(), acc]),
+            AstVal::AstList(v) if head == "->" => {
+                let mut l = vec![v[0].clone(), acc];
+                l.extend(v[1..].iter().cloned());
+                AstVal::AstList(l)
+            }
+            AstVal::AstList(v) => {
+                let mut l = v.clone();
+                l.push(acc);
+                AstVal::AstList(l)
+            }
+            _ => AstVal::AstList(vec![f.clone(), acc]),
+        }),
+        "as->" => {
+            let name = arg(1);
+            let mut b = vec![name.clone(), arg(0)];
+            for f in &args[2..] {
+                b.push(name.clone());
+                b.push(f.clone());
+            }
+            let_form(b, &[name])
+        }
+        "some->" => {
+            let g = gensym("some");
+            if args.len() == 1 {
+                return Some(arg(0));
+            }
+            let step = call_form("->", vec![g.clone(), arg(1)]);
+            let mut rest = vec![sym("some->"), step];
+            rest.extend(args[2..].iter().cloned());
+            let test = call_form("=", vec![g.clone(), AstVal::AstNil]);
+            let_form(
+                vec![g, arg(0)],
+                &[call_form(
+                    "if",
+                    vec![test, AstVal::AstNil, AstVal::AstList(rest)],
+                )],
+            )
+        }
+        "and" | "or" => match args.len() {
+            0 if head == "and" => AstVal::AstBool(true),
+            0 => AstVal::AstNil,
+            1 => arg(0),
+            _ => {
+                let g = gensym(head);
+                let mut rest = vec![sym(head)];
+                rest.extend(args[1..].iter().cloned());
+                let (then, els) = if head == "and" {
+                    (AstVal::AstList(rest), g.clone())
+                } else {
+                    (g.clone(), AstVal::AstList(rest))
+                };
+                let_form(
+                    vec![g.clone(), arg(0)],
+                    &[call_form("if", vec![g, then, els])],
+                )
+            }
+        },
+        "if-let" | "when-let" => {
+            let (target, test) = bindings(head);
+            let g = gensym("temp");
+            let (then, els) = if head == "if-let" {
+                (
+                    let_form(vec![target, g.clone()], &[arg(1)]),
+                    args.get(2).cloned().unwrap_or(AstVal::AstNil),
+                )
+            } else {
+                (
+                    let_form(vec![target, g.clone()], &args[1..]),
+                    AstVal::AstNil,
+                )
+            };
+            let_form(
+                vec![g.clone(), test],
+                &[call_form("if", vec![g, then, els])],
+            )
+        }
+        "doto" => {
+            let g = gensym("doto");
+            let mut body: Vec<AstVal> = rest(1)
+                .iter()
+                .map(|f| match f {
+                    AstVal::AstList(v) if !v.is_empty() => {
+                        let mut l = vec![v[0].clone(), g.clone()];
+                        l.extend(v[1..].iter().cloned());
+                        AstVal::AstList(l)
+                    }
+                    _ => AstVal::AstList(vec![f.clone(), g.clone()]),
+                })
+                .collect();
+            body.push(g.clone());
+            let_form(vec![g, arg(0)], &body)
+        }
+        "dotimes" => {
+            let (i, n) = bindings(head);
+            let g = gensym("n");
+            let mut body = args[1..].to_vec();
+            body.push(call_form("recur", vec![call_form("inc", vec![i.clone()])]));
+            let test = call_form("<", vec![i.clone(), g.clone()]);
+            let l = call_form(
+                "loop",
+                vec![
+                    AstVal::AstVec(vec![i, AstVal::AstInt(0)]),
+                    call_form("if", vec![test, do_form(&body), AstVal::AstNil]),
+                ],
+            );
+            let_form(vec![g, n], &[l])
+        }
+        _ => return None,
+    };
+    Some(expanded)
+}
+/// `(case x 1 "one" (2 3) "two or three" "other")` with literal keys, each
+/// tested once, which becomes a Rust `match`.
+fn syntax_case(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    let v = match a {
+        AstVal::AstList(v) if v.len() > 2 => v,
+        _ => Diagnostic::error("E0002", "Wrong number of args passed to case").raise(),
+    };
+    let expr = Box::new(dispatch_syntax(&v[1], globals));
+    let mut clauses = Vec::new();
+    let mut seen = Vec::new();
+    let mut default = None;
+    for c in v[2..].chunks(2) {
+        if c.len() == 1 {
+            default = Some(Box::new(dispatch_syntax(&c[0], globals)));
+        } else {
+            let keys = match &c[0] {
+                AstVal::AstList(ks) => ks.clone(),
+                k => vec![k.clone()],
+            };
+            for key in &keys {
+                if seen.contains(key) {
+                    Diagnostic::error("E0012", format!("Duplicate case test constant: {}", key))
+                        .raise();
                 }
-                _ => panic!("no function name"),
+                seen.push(key.clone());
             }
-            match &v[2] {
-                AstVal::AstVec(v1) => {
-                    let mut context_vec: Vec<AstVal> = Vec::new();
-                    let mut n = SyntaxNode::new();
-
-                    n.set_this(AstVal::AstSymbol("parameters".to_string()));
-                    for item in v1 {
-                        match item {
-                            AstVal::AstSymbol(s) => {
-                                n.push_string(s.clone());
-                                context_vec.push(AstVal::AstSymbol(s.clone()));
-                            }
-                            _ => panic!("not a parameter"),
+            clauses.push((keys, dispatch_syntax(&c[1], globals)));
+        }
+    }
+    SyntaxNode::Case {
+        expr,
+        clauses,
+        default,
+    }
+}
+/// `(defrecord Name [fields...])` defines a record type, with `->Name`
+/// taking its fields in order and `map->Name` taking a map of them. A
+/// field hinted with a type, as in `[^i64 id name]`, has that type in the
+/// struct. Protocols can follow, with the methods implementing them, in
+/// which the fields are locals.
+fn syntax_defrecord(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    let v = match a {
+        AstVal::AstList(v) if v.len() >= 3 => v,
+        _ => Diagnostic::error("E0002", "defrecord takes a name and a vector of fields")
+            .culprit("defrecord", "called here")
+            .help("like (defrecord User [id name])")
+            .raise(),
+    };
+    let name = match &v[1] {
+        AstVal::AstSymbol(s) if !s.contains(['/', '.']) => s.clone(),
+        _ => Diagnostic::error("E0013", format!("Bad record name: {}", v[1])).raise(),
+    };
+    globals.claim_type("record", &name);
+    let items = match &v[2] {
+        AstVal::AstVec(items) => items.clone(),
+        // `[]` reads as nil.
+        AstVal::AstNil => Vec::new(),
+        _ => Diagnostic::error(
+            "E0012",
+            format!("the fields of record {} should be a vector", name),
+        )
+        .raise(),
+    };
+    let mut fields = Vec::new();
+    let mut hint = None;
+    for item in items {
+        match item {
+            AstVal::AstMeta(m) => {
+                for (k, value) in m {
+                    if let (AstVal::AstKeyword(k), AstVal::AstString(tag)) = (k, value) {
+                        if k == "tag" {
+                            hint = Some(tag.clone());
                         }
                     }
-                    result.push_body(n);
-                    result.push_context_vec(context_vec);
                 }
-                AstVal::AstNil => {
-                    let mut n = SyntaxNode::new();
-                    n.set_this(AstVal::AstSymbol("parameters".to_string()));
-                    result.push_body(n);
+            }
+            AstVal::AstSymbol(field) if !fields.iter().any(|f: &Field| f.name == field) => fields
+                .push(Field {
+                    name: field,
+                    hint: hint.take(),
+                }),
+            AstVal::AstSymbol(field) => Diagnostic::error(
+                "E0012",
+                format!("Duplicate field {} of record {}", field, name),
+            )
+            .culprit(&field, "declared again")
+            .raise(),
+            item => Diagnostic::error("E0012", format!("Bad field of record {}: {}", name, item))
+                .raise(),
+        }
+    }
+    for (constructor, arity) in [
+        (format!("->{}", name), fields.len()),
+        (format!("map->{}", name), 1),
+    ] {
+        warn_if_core(&constructor);
+        let info = FnInfo {
+            fixed: vec![arity],
+            variadic: None,
+        };
+        globals.defs.insert(constructor, Global::Fn(info));
+    }
+    let names: Vec<String> = fields.iter().map(|f| f.name.clone()).collect();
+    globals.records.insert(name.clone(), names.clone());
+    let target = extended_type(&v[1], globals);
+    let extensions = impl_groups(&v[3..], "defrecord")
+        .into_iter()
+        .map(|(protocol, impls)| syntax_extension(&protocol, &target, &impls, &names, globals))
+        .collect();
+    SyntaxNode::Record {
+        type_name: target.type_name,
+        name,
+        fields,
+        extensions,
+    }
+}
+/// `(defprotocol Name "doc"? (method [this args...] "doc"?)...)` defines a
+/// protocol, the methods of which are functions of the namespace
+/// dispatching on the type of their first argument.
+fn syntax_defprotocol(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    let v = match a {
+        AstVal::AstList(v) if v.len() > 1 => v,
+        _ => Diagnostic::error(
+            "E0002",
+            "defprotocol takes a name and the signatures of its methods",
+        )
+        .culprit("defprotocol", "called here")
+        .help("like (defprotocol Shape (area [this]))")
+        .raise(),
+    };
+    let name = match &v[1] {
+        AstVal::AstSymbol(s) if !s.contains(['/', '.']) => s.clone(),
+        _ => Diagnostic::error("E0024", format!("Bad protocol name: {}", v[1])).raise(),
+    };
+    globals.claim_type("protocol", &name);
+    let mut doc = None;
+    let mut methods = Vec::new();
+    for item in &v[2..] {
+        match item {
+            AstVal::AstString(s) if methods.is_empty() => doc = Some(s.clone()),
+            AstVal::AstList(signature) => {
+                let method = protocol_method(&name, signature, globals);
+                if methods.iter().any(|m: &Method| m.name == method.name) {
+                    Diagnostic::error(
+                        "E0024",
+                        format!("Duplicate method {} of protocol {}", method.name, name),
+                    )
+                    .culprit(&method.name, "declared again")
+                    .raise()
                 }
-                _ => panic!("no function parameter"),
+                methods.push(method);
             }
-            let mut d = SyntaxNode::new();
-            for item in v[3..].to_vec() {
-                d.set_this(AstVal::AstSymbol("do".to_string()));
-                d.push_body(dispatch_syntax(&item));
+            _ => Diagnostic::error(
+                "E0024",
+                format!("Bad signature of a method of protocol {}: {}", name, item),
+            )
+            .help("like (area [this])")
+            .raise(),
+        }
+    }
+    for method in &methods {
+        warn_if_core(&method.name);
+        let info = FnInfo {
+            fixed: vec![method.params.len()],
+            variadic: None,
+        };
+        globals.defs.insert(method.name.clone(), Global::Fn(info));
+    }
+    let protocol = Protocol {
+        qualified: format!(
+            "{}/{}",
+            globals.interp.ns.as_deref().unwrap_or("user"),
+            name
+        ),
+        name,
+        module: String::new(),
+        methods,
+        doc,
+    };
+    globals.protocols.retain(|p| p.name != protocol.name);
+    globals.protocols.push(protocol.clone());
+    SyntaxNode::Protocol(protocol)
+}
+/// A method of a protocol from its signature, like `(area [this])`. It
+/// has one arglist, which the functions implementing it take too, with no
+/// `&` rest.
+fn protocol_method(protocol: &str, signature: &[AstVal], globals: &Globals) -> Method {
+    let name = match signature.first() {
+        Some(AstVal::AstSymbol(s)) => s.clone(),
+        _ => Diagnostic::error(
+            "E0024",
+            format!("Bad signature of a method of protocol {}", protocol),
+        )
+        .help("like (area [this])")
+        .raise(),
+    };
+    let mut arglists = Vec::new();
+    let mut doc = None;
+    for item in &signature[1..] {
+        match item {
+            AstVal::AstVec(v) => arglists.push(v.clone()),
+            AstVal::AstNil => arglists.push(Vec::new()),
+            AstVal::AstString(s) => doc = Some(s.clone()),
+            AstVal::AstMeta(_) => {}
+            _ => Diagnostic::error(
+                "E0024",
+                format!(
+                    "Bad arglist of method {} of protocol {}: {}",
+                    name, protocol, item
+                ),
+            )
+            .raise(),
+        }
+    }
+    let arglist = match arglists.as_slice() {
+        [arglist] if !arglist.is_empty() => arglist,
+        [_] => Diagnostic::error(
+            "E0024",
+            format!(
+                "Method {} of protocol {} needs a parameter for the value it is called on",
+                name, protocol
+            ),
+        )
+        .culprit(&name, "declared here")
+        .raise(),
+        _ => Diagnostic::error(
+            "E0024",
+            format!(
+                "Method {} of protocol {} should have one arglist",
+                name, protocol
+            ),
+        )
+        .culprit(&name, "declared here")
+        .help("each arity can be a method of its own")
+        .raise(),
+    };
+    let mut params: Vec<String> = Vec::new();
+    for (i, param) in arglist.iter().enumerate() {
+        let rust_name = match param {
+            AstVal::AstSymbol(s) if s == "&" => Diagnostic::error(
+                "E0024",
+                format!(
+                    "Method {} of protocol {} can not take & rest",
+                    name, protocol
+                ),
+            )
+            .culprit(&name, "declared here")
+            .raise(),
+            AstVal::AstSymbol(s) if s != "_" && !globals.is_var(s) => mangle(s),
+            _ => format!("arg__{}", i),
+        };
+        params.push(match params.contains(&rust_name) {
+            true => format!("arg__{}", i),
+            false => rust_name,
+        });
+    }
+    Method { name, params, doc }
+}
+/// The types of the runtime a protocol can be extended to, by the names
+/// Clojure has for them, the first being the one the runtime gives them,
+/// with the Rust type that implements the trait of the protocol, if any.
+const PROTOCOL_TYPES: &[(&[&str], Option<&str>)] = &[
+    (&["nil"], Some("()")),
+    (&["Boolean"], Some("bool")),
+    (&["Long", "Integer"], Some("i64")),
+    (&["Double", "Float"], Some("f64")),
+    (&["String"], Some("String")),
+    (&["Keyword"], None),
+    (&["Symbol"], None),
+    (
+        &["PersistentVector", "IPersistentVector"],
+        Some("Vec<CljVal>"),
+    ),
+    (&["PersistentList", "IPersistentList", "ISeq"], None),
+    (
+        &["PersistentArrayMap", "PersistentHashMap", "IPersistentMap"],
+        None,
+    ),
+    (&["Fn", "IFn"], None),
+    (&["Object"], None),
+];
+/// A type a protocol is extended to.
+struct Target {
+    /// The name the runtime gives it.
+    type_name: String,
+    /// Its name without a namespace, which the functions implementing the
+    /// methods for it are named with.
+    short: String,
+    rust_type: Option<String>,
+    /// The record it is, if it is one.
+    record: Option<String>,
+}
+/// The type a symbol names to extend a protocol to: a record of the
+/// namespace, or a type of the runtime, with or without its JVM package.
+fn extended_type(t: &AstVal, globals: &Globals) -> Target {
+    let s = match t {
+        // `nil` reads as nil.
+        AstVal::AstNil => "nil",
+        AstVal::AstSymbol(s) => s.as_str(),
+        _ => Diagnostic::error("E0024", format!("Bad type to extend a protocol to: {}", t)).raise(),
+    };
+    let name = s
+        .strip_prefix("java.lang.")
+        .or_else(|| s.strip_prefix("clojure.lang."))
+        .unwrap_or(s);
+    if globals.records.contains_key(name) {
+        return Target {
+            type_name: format!(
+                "{}.{}",
+                globals.interp.ns.as_deref().unwrap_or("user"),
+                name
+            ),
+            short: name.to_string(),
+            rust_type: Some(mangle(name)),
+            record: Some(name.to_string()),
+        };
+    }
+    match PROTOCOL_TYPES.iter().find(|(names, _)| names.contains(&name)) {
+        Some((names, rust_type)) => Target {
+            type_name: names[0].to_string(),
+            short: names[0].to_string(),
+            rust_type: rust_type.map(str::to_string),
+            record: None,
+        },
+        None => Diagnostic::error("E0024", format!("Unable to resolve classname: {}", s))
+            .culprit(s, "not a record of this namespace or a type of the runtime")
+            .help(
+                "the types of the runtime are nil, Boolean, Long, Double, String, Keyword, Symbol, \
+                 PersistentVector, PersistentList, PersistentArrayMap, Fn and Object",
+            )
+            .raise(),
+    }
+}
+/// The implementations of methods in `extend-type`, `extend-protocol` and
+/// `defrecord`, grouped after the protocol or type each group is for.
+fn impl_groups(items: &[AstVal], form: &str) -> Vec<(AstVal, Vec<Vec<AstVal>>)> {
+    let mut groups: Vec<(AstVal, Vec<Vec<AstVal>>)> = Vec::new();
+    for item in items {
+        match (item, groups.last_mut()) {
+            (AstVal::AstList(m), Some((_, impls))) => impls.push(m.clone()),
+            (AstVal::AstSymbol(_) | AstVal::AstNil, _) => groups.push((item.clone(), Vec::new())),
+            (AstVal::AstCommentLine(_), _) => {}
+            _ => Diagnostic::error(
+                "E0024",
+                format!("Bad method implementation in {}: {}", form, item),
+            )
+            .help("like (area [this] ...), after the protocol or type it is for")
+            .raise(),
+        }
+    }
+    groups
+}
+/// The methods of the protocol `p` implemented for a type. The fields of a
+/// record are locals in the methods implemented in its `defrecord`.
+fn syntax_extension(
+    p: &AstVal,
+    target: &Target,
+    impls: &[Vec<AstVal>],
+    fields: &[String],
+    globals: &mut Globals,
+) -> Extension {
+    let protocol = match p {
+        AstVal::AstSymbol(s) => globals.protocol(s).unwrap_or_else(|| {
+            Diagnostic::error("E0024", format!("Unable to resolve protocol: {}", s))
+                .culprit(s, "not a protocol")
+                .raise()
+        }),
+        _ => Diagnostic::error("E0024", format!("Bad protocol: {}", p)).raise(),
+    };
+    let extended = globals
+        .extensions
+        .iter()
+        .any(|e| e.protocol.qualified == protocol.qualified && e.type_name == target.type_name);
+    if extended {
+        Diagnostic::error(
+            "E0024",
+            format!(
+                "{} already extends protocol {}",
+                target.type_name, protocol.name
+            ),
+        )
+        .culprit(&protocol.name, "extended again")
+        .help("implement the methods of a protocol for a type in one place")
+        .raise()
+    }
+    let mut methods: Vec<(String, Arity)> = Vec::new();
+    for m in impls {
+        let name = match m.first() {
+            Some(AstVal::AstSymbol(s)) => s.clone(),
+            _ => Diagnostic::error(
+                "E0024",
+                format!("Bad method implementation of protocol {}", protocol.name),
+            )
+            .raise(),
+        };
+        let method = match protocol.methods.iter().find(|method| method.name == name) {
+            Some(method) => method,
+            None => Diagnostic::error(
+                "E0024",
+                format!(
+                    "Can't define method not in protocol {}: {}",
+                    protocol.name, name
+                ),
+            )
+            .culprit(&name, "not a method of the protocol")
+            .raise(),
+        };
+        if methods.iter().any(|(other, _)| *other == name) {
+            Diagnostic::error(
+                "E0024",
+                format!(
+                    "Duplicate implementation of method {} for {}",
+                    name, target.type_name
+                ),
+            )
+            .culprit(&name, "implemented again")
+            .raise()
+        }
+        let (parameters, body) = match split_arities(&m[1..], globals).as_slice() {
+            [(AstVal::AstVec(parameters), body)]
+                if parameters.len() == method.params.len() && !parameters.contains(&sym("&")) =>
+            {
+                (parameters.clone(), body.clone())
             }
-            result.push_body(d);
-            return result;
+            _ => Diagnostic::error(
+                "E0024",
+                format!(
+                    "Method {} of protocol {} takes {} arguments, the value it is called on first",
+                    name,
+                    protocol.name,
+                    method.params.len()
+                ),
+            )
+            .culprit(&name, "implemented here")
+            .raise(),
+        };
+        // The fields, unless a parameter shadows them, are read from the
+        // record, which needs a name then.
+        let mut parameters = parameters;
+        if parameters[0] == sym("_") && !fields.is_empty() {
+            parameters[0] = globals.gensym("this");
         }
-        _ => panic!("not a defn"),
+        let mut bindings = Vec::new();
+        for field in fields.iter().filter(|f| !parameters.contains(&sym(f))) {
+            bindings.push(sym(field));
+            bindings.push(AstVal::AstList(vec![
+                AstVal::AstKeyword(field.clone()),
+                parameters[0].clone(),
+            ]));
+        }
+        let body = match bindings.is_empty() {
+            true => body,
+            false => vec![let_form(bindings, &body)],
+        };
+        let arity = syntax_method(
+            &AstVal::AstVec(parameters),
+            &body,
+            target.record.clone(),
+            globals,
+        );
+        methods.push((name, arity));
     }
+    let extension = Extension {
+        prefix: format!("{}__{}", mangle(&protocol.name), mangle(&target.short)),
+        protocol,
+        type_name: target.type_name.clone(),
+        rust_type: target.rust_type.clone(),
+        record: target.record.is_some(),
+        module: String::new(),
+        methods,
+    };
+    globals.extensions.push(extension.clone());
+    extension
 }
-fn dispatch_syntax(c: &AstVal) -> SyntaxNode {
-    let mut result = SyntaxNode::new();
-    match c.list_type() {
-        Some(s) => match s.as_str() {
-            "defn" => {
-                result = syntax_defn(c);
+/// `(extend-type Type Protocol (method [this ...] ...)...)`, with any
+/// number of protocols.
+fn syntax_extend_type(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    let v = match a {
+        AstVal::AstList(v) if v.len() > 2 => v,
+        _ => Diagnostic::error(
+            "E0002",
+            "extend-type takes a type and the protocols it implements",
+        )
+        .culprit("extend-type", "called here")
+        .help("like (extend-type String Shape (area [s] 0))")
+        .raise(),
+    };
+    let target = extended_type(&v[1], globals);
+    let extensions = impl_groups(&v[2..], "extend-type")
+        .into_iter()
+        .map(|(protocol, impls)| syntax_extension(&protocol, &target, &impls, &[], globals))
+        .collect();
+    SyntaxNode::Extend(extensions)
+}
+/// `(extend-protocol Protocol Type (method [this ...] ...)...)`, with any
+/// number of types.
+fn syntax_extend_protocol(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    let v = match a {
+        AstVal::AstList(v) if v.len() > 2 => v,
+        _ => Diagnostic::error(
+            "E0002",
+            "extend-protocol takes a protocol and the types implementing it",
+        )
+        .culprit("extend-protocol", "called here")
+        .help("like (extend-protocol Shape String (area [s] 0))")
+        .raise(),
+    };
+    let extensions = impl_groups(&v[2..], "extend-protocol")
+        .into_iter()
+        .map(|(t, impls)| {
+            let target = extended_type(&t, globals);
+            syntax_extension(&v[1], &target, &impls, &[], globals)
+        })
+        .collect();
+    SyntaxNode::Extend(extensions)
+}
+/// `(satisfies? Protocol x)`, whether the protocol is extended to the type
+/// of `x`.
+fn syntax_satisfies(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    let v = match a {
+        AstVal::AstList(v) if v.len() == 3 => v,
+        _ => Diagnostic::error("E0002", "Wrong number of args passed to satisfies?")
+            .culprit("satisfies?", "called here")
+            .raise(),
+    };
+    let protocol = match &v[1] {
+        AstVal::AstSymbol(s) => globals.protocol(s),
+        _ => None,
+    };
+    let protocol = match protocol {
+        Some(protocol) => protocol,
+        None => Diagnostic::error(
+            "E0024",
+            format!("satisfies? takes a protocol, not {}", v[1]),
+        )
+        .culprit("satisfies?", "called here")
+        .raise(),
+    };
+    SyntaxNode::Call {
+        path: protocol.satisfies_path(),
+        args: vec![dispatch_syntax(&v[2], globals)],
+    }
+}
+/// `(defmulti name doc? attr-map? dispatch-fn & options)` defines a var
+/// holding a multimethod without methods, unless it is defined already.
+/// The one option is `:default`, the dispatch value of the default method.
+fn syntax_defmulti(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    let v = match a {
+        AstVal::AstList(v) => strip_meta(v).0,
+        _ => unreachable!(),
+    };
+    let name = match v.get(1) {
+        Some(AstVal::AstSymbol(s)) => s.clone(),
+        _ => Diagnostic::error("E0025", "First argument to defmulti must be a Symbol").raise(),
+    };
+    let doc = match v.get(2) {
+        Some(AstVal::AstString(doc)) => Some(doc.clone()),
+        _ => None,
+    };
+    let mut rest = &v[2 + doc.iter().len()..];
+    if let Some(AstVal::AstMap(_)) = rest.first() {
+        rest = &rest[1..];
+    }
+    let (dispatch, options) = match rest.split_first() {
+        Some((dispatch, options)) if options.len() % 2 == 0 => (dispatch.clone(), options),
+        _ => Diagnostic::error(
+            "E0025",
+            format!(
+                "defmulti {} takes a dispatch function and options in pairs",
+                name
+            ),
+        )
+        .culprit(&name, "defined here")
+        .raise(),
+    };
+    let mut default = AstVal::AstKeyword("default".to_string());
+    for option in options.chunks(2) {
+        match &option[0] {
+            AstVal::AstKeyword(k) if k == "default" => default = option[1].clone(),
+            k => Diagnostic::error(
+                "E0025",
+                format!("Unsupported option to defmulti {}: {}", name, k),
+            )
+            .culprit(&name, "defined here")
+            .raise(),
+        }
+    }
+    let value = call_form(
+        "multi_fn",
+        vec![AstVal::AstString(name.clone()), dispatch, default],
+    );
+    let mut def = vec![sym("defonce"), sym(&name)];
+    def.extend(doc.map(AstVal::AstString));
+    def.push(value);
+    syntax_def(&AstVal::AstList(def), globals, true)
+}
+/// `(defmethod multifn dispatch-value & fn-tail)` adds a method to a
+/// multimethod, which at the top level happens when the program starts.
+fn syntax_defmethod(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    let v = match a {
+        AstVal::AstList(v) if v.len() > 3 => v,
+        _ => Diagnostic::error(
+            "E0025",
+            "defmethod takes a multimethod, a dispatch value and a function body",
+        )
+        .culprit("defmethod", "called here")
+        .raise(),
+    };
+    let mut method = vec![sym("fn")];
+    method.extend(v[3..].iter().cloned());
+    let add = call_form(
+        "add_method",
+        vec![v[1].clone(), v[2].clone(), AstVal::AstList(method)],
+    );
+    dispatch_syntax(&add, globals)
+}
+/// `(try body* (catch class e handler*)* (finally cleanup*)?)`. A `catch`
+/// takes a class, `:default`, or a map the data of an `ex-info` must have
+/// the entries of.
+fn syntax_try(a: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    let v = match a {
+        AstVal::AstList(v) => v,
+        _ => unreachable!(),
+    };
+    let clause = |form: &AstVal| match form {
+        AstVal::AstList(items) => match items.first() {
+            Some(AstVal::AstSymbol(head)) if head == "catch" || head == "finally" => {
+                Some((head.clone(), items.clone()))
             }
-            "if" => {
-                result = syntax_if(c);
+            _ => None,
+        },
+        _ => None,
+    };
+    let start = v[1..]
+        .iter()
+        .position(|form| clause(form).is_some())
+        .map_or(v.len(), |i| i + 1);
+    let body = v[1..start]
+        .iter()
+        .map(|form| dispatch_syntax(form, globals))
+        .collect();
+    let mut catches = Vec::new();
+    let mut finally = None;
+    for (i, form) in v[start..].iter().enumerate() {
+        match clause(form) {
+            Some((head, items)) if head == "finally" && start + i + 1 == v.len() => {
+                finally = Some(
+                    items[1..]
+                        .iter()
+                        .map(|form| dispatch_syntax(form, globals))
+                        .collect(),
+                )
             }
-            "let" => {
-                result = syntax_let(c);
+            Some((head, _)) if head == "finally" => {
+                Diagnostic::error("E0026", "finally clause must be last in try expression")
+                    .culprit("finally", "not last")
+                    .raise()
             }
-            "=" => {
-                result = syntax_equal(c);
+            Some((_, items)) => catches.push(syntax_catch(&items, globals)),
+            None => Diagnostic::error(
+                "E0026",
+                "Only catch or finally clause can follow catch in try expression",
+            )
+            .culprit("try", "in this try")
+            .raise(),
+        }
+    }
+    SyntaxNode::Try {
+        body,
+        catches,
+        finally: finally.unwrap_or_default(),
+    }
+}
+/// Classes a `catch` can name, without their package: the one of `ex-info`,
+/// and those of every exception.
+const CATCH_CLASSES: &[&str] = &[
+    "ExceptionInfo",
+    "Exception",
+    "RuntimeException",
+    "Throwable",
+];
+
+fn syntax_catch(items: &[AstVal], globals: &mut Globals) -> Catch {
+    let (class, binding) = match items {
+        [_, class, binding @ AstVal::AstSymbol(_), ..] => (class, binding),
+        _ => Diagnostic::error("E0026", "catch takes a class, a symbol to bind and a body")
+            .culprit("catch", "here")
+            .raise(),
+    };
+    let catches = match class {
+        AstVal::AstKeyword(k) if k == "default" => Catches::Class("Throwable".to_string()),
+        AstVal::AstSymbol(s) => {
+            let short = s
+                .strip_prefix("java.lang.")
+                .or_else(|| s.strip_prefix("clojure.lang."))
+                .unwrap_or(s);
+            if !CATCH_CLASSES.contains(&short) {
+                Diagnostic::error("E0026", format!("Unable to resolve classname: {}", s))
+                    .culprit(s, "caught here")
+                    .help(format!(
+                        "a catch takes one of {}, :default or a map",
+                        CATCH_CLASSES.join(", ")
+                    ))
+                    .raise()
             }
-            _ => {
-                result = syntax_call(c);
+            Catches::Class(short.to_string())
+        }
+        AstVal::AstMap(_) => Catches::Data(Box::new(dispatch_syntax(class, globals))),
+        _ => Diagnostic::error("E0026", format!("Bad class to catch: {}", class))
+            .culprit("catch", "here")
+            .raise(),
+    };
+    let depth = globals.scope.len();
+    let binding = globals.bind(binding, true).to_string();
+    let body = items[3..]
+        .iter()
+        .map(|form| dispatch_syntax(form, globals))
+        .collect();
+    globals.unbind(depth);
+    Catch {
+        catches,
+        binding,
+        body,
+    }
+}
+/// The record type a form is known to make: a local bound to a record, or
+/// a call to a constructor of one.
+fn record_of(form: &AstVal, globals: &Globals) -> Option<String> {
+    match form {
+        AstVal::AstSymbol(s) => globals.local(s)?.record.clone(),
+        AstVal::AstList(v) => {
+            let head = match v.first() {
+                Some(AstVal::AstSymbol(head)) if globals.local(head).is_none() => head,
+                _ => return None,
+            };
+            let name = head
+                .strip_prefix("->")
+                .or_else(|| head.strip_prefix("map->"))?;
+            // Unless the constructor is another function of that name.
+            let constructor = globals.defs.get(head)?;
+            match (globals.records.contains_key(name), constructor) {
+                (true, Global::Fn(_)) => Some(name.to_string()),
+                _ => None,
             }
-        },
-        None => {
-            result.set_this(c.clone());
         }
+        _ => None,
     }
-    result
 }
-
-pub fn syntax(c: &AstVal) -> SyntaxNode {
-    let mut z = dispatch_syntax(&c);
-    z.disseminate_context();
-    z
+/// What Rust code a call calls, if its head is interop syntax rather than
+/// a Clojure function: `rust/` and a path, `.` and a method, a capitalized
+/// type that is not a namespace and an associated function, or
+/// `rust-macro!`.
+fn rust_callee(v: &[AstVal], globals: &Globals) -> Option<RustCallee> {
+    let s = match v.first() {
+        Some(AstVal::AstSymbol(s)) if globals.local(s).is_none() => s,
+        _ => return None,
+    };
+    if s == "rust-macro!" {
+        return match v.get(1) {
+            Some(AstVal::AstSymbol(name)) => {
+                Some(RustCallee::Macro(name.trim_end_matches('!').to_string()))
+            }
+            _ => Diagnostic::error("E0002", "rust-macro! needs the name of a macro")
+                .culprit("rust-macro!", "called here")
+                .help("like (rust-macro! format \"{}\" x)")
+                .raise(),
+        };
+    }
+    if s.len() > 1 && s.starts_with('.') && s != ".." {
+        return Some(RustCallee::Method(s[1..].to_string()));
+    }
+    let i = s.find('/').filter(|_| s.len() > 1)?;
+    let (ns, name) = (&s[..i], &s[i + 1..]);
+    let known = globals.aliases.contains_key(ns)
+        || globals.namespaces.contains_key(ns)
+        || Some(ns) == globals.interp.ns.as_deref();
+    let path = match ns {
+        "rust" => name.to_string(),
+        _ if ns.starts_with(|c: char| c.is_ascii_uppercase()) && !known => {
+            format!("{}::{}", ns, name)
+        }
+        _ => return None,
+    };
+    // A path may start at the crate root, but has no empty segment.
+    if path.trim_start_matches("::").split("::").any(str::is_empty) {
+        Diagnostic::error("E0002", format!("Malformed Rust path: {}", s))
+            .culprit(s, "a name is missing")
+            .help("like (rust/std::cmp::max 1 2) or (String/from \"a\")")
+            .raise()
+    }
+    Some(RustCallee::Fn(path))
+}
+/// A call into Rust code. A `^type` hint before an argument gives the Rust
+/// type it is converted to.
+fn syntax_rust_call(c: &AstVal, globals: &mut Globals) -> Option<SyntaxNode> {
+    let v = match c {
+        AstVal::AstList(v) => v,
+        _ => return None,
+    };
+    let callee = rust_callee(v, globals)?;
+    let skip = match callee {
+        RustCallee::Macro(_) => 2,
+        _ => 1,
+    };
+    let mut args = Vec::new();
+    let mut hint = None;
+    for item in &v[skip..] {
+        match item {
+            AstVal::AstMeta(m) => {
+                for (k, value) in m {
+                    if let (AstVal::AstKeyword(k), AstVal::AstString(tag)) = (k, value) {
+                        if k == "tag" {
+                            hint = Some(tag.clone());
+                        }
+                    }
+                }
+            }
+            _ => args.push((hint.take(), dispatch_syntax(item, globals))),
+        }
+    }
+    if let (RustCallee::Method(method), true) = (&callee, args.is_empty()) {
+        Diagnostic::error(
+            "E0002",
+            format!(
+                "Malformed member expression, expecting (.{} target ...)",
+                method
+            ),
+        )
+        .culprit(&format!(".{}", method), "no object to call it on")
+        .raise()
+    }
+    Some(SyntaxNode::RustCall { callee, args })
+}
+pub fn is_special_form(s: &str) -> bool {
+    matches!(
+        s,
+        "def" | "do" | "fn" | "if" | "let" | "loop" | "recur" | "quote" | "'" | "`"
+    )
+}
+fn dispatch_syntax(c: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    diagnostic::within(c, || analyze(c, globals))
+}
+fn analyze(c: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    if let Some(expanded) = globals.macroexpand_1(c) {
+        return dispatch_syntax(&expanded, globals);
+    }
+    // A keyword looks itself up in the map it is called with.
+    if let AstVal::AstList(v) = c {
+        // `(.-name user)` is `(:name user)`.
+        if let Some(AstVal::AstSymbol(s)) = v.first() {
+            if s.len() > 2 && s.starts_with(".-") && v.len() == 2 && globals.local(s).is_none() {
+                let form =
+                    AstVal::AstList(vec![AstVal::AstKeyword(s[2..].to_string()), v[1].clone()]);
+                return dispatch_syntax(&form, globals);
+            }
+        }
+        if let (Some(AstVal::AstKeyword(field)), 2) = (v.first(), v.len()) {
+            if let Some(record) = record_of(&v[1], globals) {
+                if globals.records[&record].contains(field) {
+                    return SyntaxNode::GetField {
+                        record,
+                        field: field.clone(),
+                        target: Box::new(dispatch_syntax(&v[1], globals)),
+                    };
+                }
+            }
+        }
+        if let Some(AstVal::AstKeyword(_)) = v.first() {
+            let mut args = v[1..].to_vec();
+            args.insert(1, v[0].clone());
+            return dispatch_syntax(&call_form("get", args), globals);
+        }
+    }
+    if let Some(node) = syntax_rust_call(c, globals) {
+        return node;
+    }
+    match c.list_type() {
+        // Only special forms can not be shadowed by a local.
+        Some(s) if globals.local(&s).is_some() && !is_special_form(&s) => syntax_call(c, globals),
+        Some(s) => match s.as_str() {
+            "defn" | "defn-" => syntax_defn(c, globals),
+            "defrecord" => syntax_defrecord(c, globals),
+            "defprotocol" => syntax_defprotocol(c, globals),
+            "extend-type" => syntax_extend_type(c, globals),
+            "extend-protocol" => syntax_extend_protocol(c, globals),
+            "satisfies?" => syntax_satisfies(c, globals),
+            "defmulti" => syntax_defmulti(c, globals),
+            "defmethod" => syntax_defmethod(c, globals),
+            "try" => syntax_try(c, globals),
+            "catch" | "finally" => {
+                Diagnostic::error("E0026", format!("{} is only allowed in a try", s))
+                    .culprit(&s, "used here")
+                    .raise()
+            }
+            "def" => syntax_def(c, globals, false),
+            "defonce" => syntax_def(c, globals, true),
+            "defmacro" => syntax_defmacro(c, globals),
+            "case" => syntax_case(c, globals),
+            "macroexpand" => syntax_macroexpand(c, globals, false),
+            "macroexpand-1" => syntax_macroexpand(c, globals, true),
+            "`" => SyntaxNode::Const(globals.interp.eval(c, &mut Vec::new())),
+            "declare" => syntax_declare(c, globals),
+            "ns" => syntax_ns(c, globals),
+            "fn" => syntax_fn(c, globals),
+            "do" => match c {
+                AstVal::AstList(v) => SyntaxNode::Do(syntax_body(&v[1..], globals)),
+                _ => unreachable!(),
+            },
+            "if" => syntax_if(c, globals),
+            "let" => syntax_let(c, globals),
+            "=" => syntax_equal(c, globals),
+            "quote" | "'" => syntax_quote(c),
+            "loop" => syntax_loop(c, globals),
+            "recur" => syntax_recur(c, globals),
+            _ => syntax_call(c, globals),
+        },
+        None => match c {
+            // A call of what a form evaluates to, like `((adder 2) 3)`.
+            AstVal::AstList(v) => match v.split_first() {
+                Some((head @ AstVal::AstList(_), args)) => SyntaxNode::Invoke {
+                    target: Box::new(dispatch_syntax(head, globals)),
+                    args: args
+                        .iter()
+                        .map(|arg| dispatch_syntax(arg, globals))
+                        .collect(),
+                },
+                Some((head, _)) => {
+                    let head = match head {
+                        AstVal::AstString(s) => format!("{:?}", s),
+                        head => head.to_string(),
+                    };
+                    Diagnostic::error("E0016", format!("{} can't be called", head)).raise()
+                }
+                None => SyntaxNode::Const(c.clone()),
+            },
+            AstVal::AstVec(_) => syntax_vector(c, globals),
+            AstVal::AstMap(_) => syntax_map(c, globals),
+            AstVal::AstSymbol(_) => syntax_symbol(c, globals),
+            AstVal::AstMeta(_) => Diagnostic::error(
+                "E0028",
+                format!("Metadata can't be applied here: ^{}", c),
+            )
+            .help("metadata goes on the names of definitions, parameters and fields, and on arguments to Rust")
+            .raise(),
+            _ => SyntaxNode::Const(c.clone()),
+        },
+    }
 }
 
+/// A top-level form. What is not a definition runs when the program starts,
+/// in a function of its own.
+pub fn syntax(c: &AstVal, globals: &mut Globals) -> SyntaxNode {
+    if let AstVal::AstCommentLine(_) = c {
+        return SyntaxNode::Nothing;
+    }
+    let z = dispatch_syntax(c, globals);
+    z.check_recur(false);
+    match z {
+        SyntaxNode::Fn { .. }
+        | SyntaxNode::Def { .. }
+        | SyntaxNode::Nothing
+        | SyntaxNode::Record { .. }
+        | SyntaxNode::Protocol(_)
+        | SyntaxNode::Extend(_) => z,
+        body => {
+            let name = globals.gensym("__init").to_string();
+            globals.inits.push(name.clone());
+            SyntaxNode::Init {
+                name,
+                body: Box::new(body),
+            }
+        }
+    }
+}
@@ -1,102 +1,444 @@
 use ast::AstVal;
-use syntax::SyntaxNode;
-fn translate_fn(n: &SyntaxNode) -> String {
-    let mut s = String::new();
-    let mut main_flag = false;
-    s.push_str("fn ");
-    match &n.body[0].body[0].this {
-        AstVal::AstString(s1) => {
-            if s1 == "main" {
-                main_flag = true;
-            }
-            s.push_str(&s1);
+use diagnostic::Diagnostic;
+use mangle::{demangle_path, mangle};
+use syntax::{
+    walk, Arity, Catch, Catches, Extension, Field, Method, Protocol, RefKind, RustCallee,
+    Signature, SyntaxNode, VarKind, Visitor,
+};
+
+/// The `match args__.len()` arm pattern of an arity, and the bindings of its
+/// parameters taken from `args__`, a name no local can have in Rust.
+fn translate_arity_arm(arity: &Arity) -> (String, String) {
+    let (fixed, rest) = (&arity.fixed, &arity.rest);
+    let mut bindings = String::new();
+    for (i, p) in fixed.iter().enumerate() {
+        bindings.push_str(&format!("let {} = &args__[{}];", p, i));
+    }
+    match rest {
+        Some(r) => {
+            bindings.push_str(&format!(
+                "let {} = &CljVal::rest_args(&args__[{}..]);",
+                r,
+                fixed.len()
+            ));
+            (format!("n__ if n__ >= {}", fixed.len()), bindings)
         }
-        _ => panic!("not a function name"),
-    }
-    s.push_str("(");
-    for item in &n.body[1].body {
-        match &item.this {
-            AstVal::AstString(s1) => {
-                s.push_str(&s1);
-                s.push_str(":&CljVal,");
+        None => (format!("{}", fixed.len()), bindings),
+    }
+}
+
+/// A docstring as the doc comment of what it documents, without the indent
+/// its lines have in the source.
+fn rustdoc(doc: &Option<String>) -> String {
+    let mut s = String::new();
+    for line in doc.iter().flat_map(|doc| doc.lines()) {
+        s.push_str(&format!("/// {}\n", line.trim()));
+    }
+    s
+}
+
+/// A multi-arity function is documented on its dispatcher, the function of
+/// its own name.
+fn translate_fn(name: &str, public: bool, arities: &[Arity], doc: &Option<String>) -> String {
+    let vis = if public { "pub " } else { "" };
+    if arities.len() == 1 && arities[0].rest.is_none() {
+        return format!(
+            "{}{}",
+            rustdoc(doc),
+            translate_fn_arity(vis, name, &arities[0])
+        );
+    }
+    let mut s = String::new();
+    for arity in arities {
+        let arity_name = match arity.rest {
+            Some(_) => format!("{}__rest", name),
+            None => format!("{}__{}", name, arity.fixed.len()),
+        };
+        s.push_str(&translate_fn_arity(vis, &arity_name, arity));
+        s.push('\n');
+    }
+    s.push_str(&rustdoc(doc));
+    s.push_str(&format!(
+        "{}fn {}(args__: &[CljVal]) -> CljVal {{match args__.len() {{",
+        vis, name
+    ));
+    for arity in arities {
+        let n = arity.fixed.len();
+        let (pattern, _) = translate_arity_arm(arity);
+        let mut call_args: Vec<String> = (0..n).map(|i| format!("&args__[{}]", i)).collect();
+        let callee = match arity.rest {
+            Some(_) => {
+                call_args.push(format!("&CljVal::rest_args(&args__[{}..])", n));
+                format!("{}__rest", name)
             }
-            _ => panic!("not a function parameter"),
+            None => format!("{}__{}", name, n),
+        };
+        s.push_str(&format!(
+            "{} => {}({}),",
+            pattern,
+            callee,
+            call_args.join(",")
+        ));
+    }
+    s.push_str(&format!(
+        "n__ => arity_error({:?}, n__),}}}}",
+        demangle_path(name)
+    ));
+    s
+}
+
+/// Finds whether a `recur` in a body goes back to its head, and not to a
+/// nested loop or function.
+struct Recurs(bool);
+
+impl Visitor for Recurs {
+    fn visit(&mut self, n: &SyntaxNode) {
+        match n {
+            SyntaxNode::Recur { .. } => self.0 = true,
+            SyntaxNode::Closure { .. } | SyntaxNode::Loop { .. } => {}
+            _ => walk(self, n),
         }
     }
+}
+
+/// The body of a function arity, turned into a loop when it recurs: the
+/// parameters become mutable locals that `recur` assigns to.
+fn translate_arity_body(arity: &Arity) -> String {
+    let body = translate_do(&arity.body);
+    let mut recurs = Recurs(false);
+    for item in &arity.body {
+        recurs.visit(item);
+    }
+    if !recurs.0 {
+        return body;
+    }
+    let mut s = String::new();
+    s.push('{');
+    for p in arity.fixed.iter().chain(arity.rest.iter()) {
+        s.push_str(&format!("let mut {} = {}.clone();", p, p));
+    }
+    s.push_str(&format!("loop {{break {};}}}}", body));
+    s
+}
+
+fn translate_fn_arity(vis: &str, name: &str, arity: &Arity) -> String {
+    let mut s = String::new();
+    let main_flag = name == "main";
+    s.push_str(vis);
+    s.push_str("fn ");
+    s.push_str(name);
+    s.push('(');
+    for p in arity.fixed.iter().chain(arity.rest.iter()) {
+        s.push_str(p);
+        s.push_str(":&CljVal,");
+    }
     if main_flag {
-        s.push_str(")");
+        s.push(')');
     } else {
         s.push_str(")->CljVal");
     }
-    s.push_str(&translate(&n.body[2]));
     if main_flag {
-        s.pop();
-        s.push_str(";}");
+        // The forms of the program that are not definitions run first.
+        s.push_str(&format!(
+            "{{__init();main_returned(&{});}}",
+            translate_arity_body(arity)
+        ));
+    } else {
+        s.push_str(&translate_arity_body(arity));
+    }
+    s
+}
+
+/// A `fn` literal becomes a boxed Rust closure. Captured locals are cloned
+/// into the closure so it owns everything it touches.
+fn translate_closure(name: Option<&str>, captures: &[String], arities: &[Arity]) -> String {
+    let mut s = String::new();
+    s.push_str(match name {
+        Some(_) => "CljVal::new_named_fn({",
+        None => "CljVal::new_fn({",
+    });
+    for name in captures {
+        s.push_str(&format!("let {} = {}.clone();", name, name));
+    }
+    match name {
+        Some(name) => s.push_str(&format!(
+            "move |me__: &CljVal, args__: &[CljVal]| -> CljVal {{let {} = me__.clone();match args__.len() {{",
+            name
+        )),
+        None => s.push_str("move |args__: &[CljVal]| -> CljVal {match args__.len() {"),
+    }
+    for arity in arities {
+        let (pattern, bindings) = translate_arity_arm(arity);
+        s.push_str(&format!(
+            "{} => {{{}{}}}",
+            pattern,
+            bindings,
+            translate_arity_body(arity)
+        ));
     }
+    s.push_str("n__ => arity_error(\"fn\", n__),}}})");
     s
 }
 
-fn translate_let(n: &SyntaxNode) -> String {
+/// The loop bindings become mutable locals, and the body the value the loop
+/// breaks with unless a `recur` continues it.
+fn translate_loop(bindings: &[(String, SyntaxNode)], body: &[SyntaxNode]) -> String {
     let mut s = String::new();
-    s.push_str("let ");
-    match &n.body[0].this {
-        AstVal::AstSymbol(s1) => {
-            s.push_str(&s1);
+    s.push('{');
+    for (name, value) in bindings {
+        s.push_str("let mut ");
+        s.push_str(name);
+        s.push_str(" = ");
+        s.push_str(&translate(value));
+        s.push(';');
+    }
+    s.push_str(&format!("loop {{break {};}}}}", translate_do(body)));
+    s
+}
+/// All the new values are computed before any binding is assigned.
+fn translate_recur(targets: &[String], args: &[SyntaxNode]) -> String {
+    let mut s = String::new();
+    s.push('{');
+    for (i, item) in args.iter().enumerate() {
+        s.push_str(&format!("let recur__{} = {};", i, translate(item)));
+    }
+    for (i, name) in targets.iter().enumerate() {
+        s.push_str(&format!("{} = recur__{};", name, i));
+    }
+    s.push_str("continue;}");
+    s
+}
+/// A `case` with literal keys is a `match`. Keys that are strings and the
+/// like cannot be patterns, and are compared in a guard instead.
+fn translate_case(
+    expr: &SyntaxNode,
+    clauses: &[(Vec<AstVal>, SyntaxNode)],
+    default: &Option<Box<SyntaxNode>>,
+) -> String {
+    let mut s = format!("match &{} {{", translate(expr));
+    for (keys, then) in clauses {
+        let then = translate(then);
+        let mut patterns: Vec<String> = Vec::new();
+        let mut guards: Vec<(&str, Vec<String>)> = Vec::new();
+        for key in keys {
+            let (variant, guard) = match key {
+                AstVal::AstString(k) => ("CljString", format!("*case__key == {:?}", k)),
+                AstVal::AstKeyword(k) => ("CljKeyword", format!("*case__key == {:?}", k)),
+                AstVal::AstSymbol(k) => ("CljSymbol", format!("*case__key == {:?}", k)),
+                AstVal::AstFloat(k) => ("CljFloat", format!("*case__key == {:?}", k)),
+                k => {
+                    patterns.push(translate_constant(k));
+                    continue;
+                }
+            };
+            match guards.iter_mut().find(|(v, _)| *v == variant) {
+                Some((_, g)) => g.push(guard),
+                None => guards.push((variant, vec![guard])),
+            }
+        }
+        if !patterns.is_empty() {
+            s.push_str(&format!("{} => {},", patterns.join(" | "), then));
         }
-        _ => panic!("can not let"),
+        for (variant, g) in guards {
+            s.push_str(&format!(
+                "CljVal::{}(case__key) if {} => {},",
+                variant,
+                g.join(" || "),
+                then
+            ));
+        }
+    }
+    match default {
+        Some(d) => s.push_str(&format!("_ => {}", translate(d))),
+        None => s.push_str("other => no_matching_clause(other)"),
+    }
+    s.push('}');
+    s
+}
+fn translate_let(bindings: &[(String, SyntaxNode)], body: &[SyntaxNode]) -> String {
+    let mut items: Vec<String> = bindings
+        .iter()
+        .map(|(name, value)| format!("let {} = {};", name, translate(value)))
+        .collect();
+    items.extend(body.iter().map(translate));
+    join_block(&items)
+}
+fn translate_do(body: &[SyntaxNode]) -> String {
+    let items: Vec<String> = body.iter().map(translate).collect();
+    join_block(&items)
+}
+/// A `try` runs its body, its catches and its `finally` as closures, which
+/// `try_catch` runs catching what they throw. Without any, it is a block.
+fn translate_try(body: &[SyntaxNode], catches: &[Catch], finally: &[SyntaxNode]) -> String {
+    let block = |body: &[SyntaxNode]| match body.is_empty() {
+        true => String::from("CljVal::CljNil"),
+        false => translate_do(body),
+    };
+    if catches.is_empty() && finally.is_empty() {
+        return block(body);
+    }
+    let mut s = format!(
+        "try_catch(||{},|caught__: &CljVal| -> Option<CljVal> {{",
+        block(body)
+    );
+    for catch in catches {
+        let test = match &catch.catches {
+            Catches::Class(class) => format!("catches({:?}, caught__)", class),
+            Catches::Data(data) => format!("catches_data(&{}, caught__)", translate(data)),
+        };
+        s.push_str(&format!(
+            "if {} {{let {} = caught__;return Some({});}}",
+            test,
+            catch.binding,
+            block(&catch.body)
+        ));
     }
-    s.push_str(" = ");
-    s.push_str(&translate(&n.body[1]));
-    s.push_str(";");
+    s.push_str(&format!("None}},||{})", block(finally)));
     s
 }
-fn translate_do(n: &SyntaxNode) -> String {
+/// The statements of a block, the last one giving its value.
+fn join_block(items: &[String]) -> String {
     let mut s = String::new();
-    s.push_str("{");
-    for item in &n.body {
-        s.push_str(&translate(&item));
-        s.push_str(";");
+    s.push('{');
+    for item in items {
+        s.push_str(item);
+        s.push(';');
     }
     s.pop();
-    s.push_str("}");
+    s.push('}');
     s
 }
 
-fn translate_if(n: &SyntaxNode) -> String {
+fn translate_if(test: &SyntaxNode, then: &SyntaxNode, otherwise: &SyntaxNode) -> String {
     let mut s = String::new();
     s.push_str("if(");
-    s.push_str(&translate(&n.body[0]));
-    s.push_str("){");
-    s.push_str(&translate(&n.body[1]));
+    s.push_str(&translate(test));
+    s.push_str(").truthy(){");
+    s.push_str(&translate(then));
     s.push_str("}else{");
-    s.push_str(&translate(&n.body[2]));
-    s.push_str("}");
+    s.push_str(&translate(otherwise));
+    s.push('}');
+    s
+}
+fn translate_call(path: &str, args: &[SyntaxNode]) -> String {
+    let mut s = String::new();
+    s.push_str(path);
+    s.push('(');
+    for item in args {
+        s.push('&');
+        s.push_str(&translate(item));
+        s.push(',');
+    }
+    s.push(')');
     s
 }
-fn translate_call(n: &SyntaxNode) -> String {
+/// Calling a value that can only be known at run time to be a function,
+/// like a local or a `def`.
+fn translate_invoke(target: &str, args: &[SyntaxNode]) -> String {
     let mut s = String::new();
-    match &n.this {
-        AstVal::AstSymbol(s1) => {
-            s.push_str(&s1);
-            s.push('(');
+    s.push_str(target);
+    s.push_str(".call(&[");
+    for item in args {
+        s.push_str(&translate(item));
+        s.push(',');
+    }
+    s.push_str("])");
+    s
+}
+/// A `def`: a `const` when its value is a literal, otherwise a thread local,
+/// which is initialised the first time it is used. As a `let` cannot shadow
+/// either in Rust, the locals named like it are renamed.
+fn translate_def(name: &str, kind: VarKind, public: bool, value: &SyntaxNode) -> String {
+    let vis = if public { "pub " } else { "" };
+    let value = translate(value);
+    match kind {
+        VarKind::Const => format!("{}const {}: CljVal = {};", vis, name, value),
+        VarKind::Static => format!(
+            "thread_local! {{{}static {}: CljVal = {};}}",
+            vis, name, value
+        ),
+    }
+}
+/// A symbol in value position that is not a local. Functions defined in the
+/// file are wrapped into a function value, so they can be passed around.
+fn translate_ref(path: &str, kind: RefKind) -> String {
+    match kind {
+        RefKind::Fn(arity) => {
+            let args: Vec<String> = (0..arity).map(|i| format!("&args__[{}]", i)).collect();
+            format!(
+                "CljVal::new_fn(|args__: &[CljVal]| match args__.len() {{{} => {}({}),n__ => arity_error({:?}, n__),}})",
+                arity,
+                path,
+                args.join(","),
+                demangle_path(path)
+            )
         }
-        _ => panic!("not callable"),
+        RefKind::Dispatcher => format!("CljVal::new_fn({})", path),
+        RefKind::Var(VarKind::Const) => path.to_string(),
+        RefKind::Var(VarKind::Static) => format!("{}.with(CljVal::clone)", path),
     }
-    for item in &n.body {
-        s.push_str("&");
-        s.push_str(&translate(&item));
+}
+fn translate_seq(variant: &str, items: &[SyntaxNode]) -> String {
+    let mut s = String::new();
+    s.push_str(variant);
+    s.push_str("(vec![");
+    for item in items {
+        s.push_str(&translate(item));
         s.push(',');
     }
-    s.push(')');
+    s.push_str("])");
     s
 }
-fn translate_println(n: &SyntaxNode) -> String {
-    let mut s0 = String::new();
+fn translate_hash_map(pairs: &[(SyntaxNode, SyntaxNode)]) -> String {
+    let mut s = String::new();
+    s.push_str("CljVal::CljMap(vec![");
+    for (k, v) in pairs {
+        s.push_str(&format!("({},{}),", translate(k), translate(v)));
+    }
+    s.push_str("])");
+    s
+}
+/// A literal, or a quoted form, which is data: symbols stay symbols and lists
+/// are not calls.
+fn translate_constant(a: &AstVal) -> String {
+    // Metadata in quoted data is left out, as values do not keep it.
+    let seq = |v: &[AstVal]| -> String {
+        let items: Vec<String> = v
+            .iter()
+            .filter(|x| !matches!(x, AstVal::AstMeta(_)))
+            .map(translate_constant)
+            .collect();
+        items.join(",")
+    };
+    match a {
+        AstVal::AstSymbol(s) => format!("CljVal::CljSymbol({:?}.to_string())", s),
+        AstVal::AstList(v) => format!("CljVal::CljList(vec![{}])", seq(v)),
+        AstVal::AstVec(v) => format!("CljVal::CljVec(vec![{}])", seq(v)),
+        AstVal::AstMap(v) => {
+            let pairs: Vec<String> = v
+                .iter()
+                .map(|(k, item)| {
+                    format!("({},{})", translate_constant(k), translate_constant(item))
+                })
+                .collect();
+            format!("CljVal::CljMap(vec![{}])", pairs.join(","))
+        }
+        AstVal::AstNil => String::from("CljVal::CljNil"),
+        AstVal::AstBool(b) => format!("CljVal::CljBool({})", b),
+        AstVal::AstInt(i) => format!("CljVal::CljInt({})", i),
+        AstVal::AstFloat(f) => format!("CljVal::CljFloat({:?})", f),
+        AstVal::AstString(s) => format!("CljVal::CljString({:?}.to_string())", s),
+        AstVal::AstKeyword(s) => format!("CljVal::CljKeyword({:?}.to_string())", s),
+        _ => Diagnostic::error("E0029", format!("Can't embed object in code: {}", a)).raise(),
+    }
+}
+/// `println` prints its arguments separated by spaces, as Clojure does.
+fn translate_println(items: &[SyntaxNode]) -> String {
+    let s0 = vec!["{}"; items.len()].join(" ");
     let mut s1 = String::new();
-    for item in &n.body {
-        s1.push_str(&translate(&item));
-        s1.push_str(",");
-        s0.push_str("{}");
+    for item in items {
+        s1.push_str(&translate(item));
+        s1.push(',');
     }
     let mut s = String::new();
     s.push_str("println!(\"");
@@ -106,36 +448,433 @@ fn translate_println(n: &SyntaxNode) -> String {
     s.push_str(");CljVal::CljNil");
     s
 }
-fn translate_equal(n: &SyntaxNode) -> String {
-    let n1 = &n.body[0];
-    let s1 = translate(&n1);
-    let n2 = &n.body[1];
-    let s2 = translate(&n2);
-    let s = format!("( {} == {} )", s1, s2);
+fn translate_equal(a: &SyntaxNode, b: &SyntaxNode) -> String {
+    format!("CljVal::CljBool( {} == {} )", translate(a), translate(b))
+}
+/// The owned type a borrowed one is converted from: a `&str` is borrowed
+/// from a `String` and a `&[T]` from a `Vec<T>`.
+fn owned_type(t: &str) -> String {
+    match t.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        Some(item) => format!("Vec<{}>", item),
+        None if t == "str" => String::from("String"),
+        None => t.to_string(),
+    }
+}
+/// An argument of a call into Rust: converted to the type it is hinted
+/// with, or to the one the callee takes, and borrowed if the hint is a
+/// reference type like `&i64` or `&str`. A literal is passed as it is, as
+/// the format string of a macro must be, and other arguments of a macro as
+/// values, which display the way `str` has them.
+fn translate_rust_arg(hint: &Option<String>, arg: &SyntaxNode, is_macro: bool) -> String {
+    match (hint, arg) {
+        (Some(t), _) if t.starts_with('&') => {
+            let t = t[1..].trim();
+            let owned = owned_type(t);
+            let borrow = if owned == t { "&" } else { "&*" };
+            format!(
+                "{}<{} as FromClj>::from_clj(&{})",
+                borrow,
+                owned,
+                translate(arg)
+            )
+        }
+        (Some(t), _) => format!("<{} as FromClj>::from_clj(&{})", t, translate(arg)),
+        (None, SyntaxNode::Const(AstVal::AstString(s))) => format!("{:?}", s),
+        (None, SyntaxNode::Const(AstVal::AstInt(i))) => i.to_string(),
+        (None, SyntaxNode::Const(AstVal::AstFloat(f))) => format!("{:?}", f),
+        (None, SyntaxNode::Const(AstVal::AstBool(b))) => b.to_string(),
+        (None, _) if is_macro => translate(arg),
+        (None, _) => format!("FromClj::from_clj(&{})", translate(arg)),
+    }
+}
+/// A call into Rust, whose result is converted back into a value. The
+/// object of a method is the value itself unless it is hinted.
+fn translate_rust_call(callee: &RustCallee, args: &[(Option<String>, SyntaxNode)]) -> String {
+    let is_macro = matches!(callee, RustCallee::Macro(_));
+    let mut items: Vec<String> = args
+        .iter()
+        .map(|(hint, arg)| translate_rust_arg(hint, arg, is_macro))
+        .collect();
+    let call = match callee {
+        RustCallee::Fn(path) => format!("{}({})", path, items.join(",")),
+        RustCallee::Macro(name) => format!("{}!({})", name, items.join(",")),
+        RustCallee::Method(method) => {
+            let object = match &args[0] {
+                (None, arg) => format!("({})", translate(arg)),
+                _ => format!("({})", items[0]),
+            };
+            items.remove(0);
+            format!("{}.{}({})", object, method, items.join(","))
+        }
+    };
+    format!("IntoClj::into_clj({})", call)
+}
+fn keyword(name: &str) -> String {
+    format!("CljVal::CljKeyword({:?}.to_string())", name)
+}
+/// A record is a struct of its fields, typed as they are hinted and values
+/// otherwise, which is a `Record` to be a value, and the functions making
+/// one from its fields and from a map.
+fn translate_record(name: &str, type_name: &str, fields: &[Field]) -> String {
+    let rust_name = mangle(name);
+    let mut declared = String::new();
+    let mut entries = String::new();
+    let mut setters = String::new();
+    let mut params = String::new();
+    let mut inits = String::new();
+    let mut from_map = String::new();
+    for field in fields {
+        let f = mangle(&field.name);
+        declared.push_str(&format!(
+            "pub {}: {},",
+            f,
+            field.hint.as_deref().unwrap_or("CljVal")
+        ));
+        entries.push_str(&format!(
+            "({},IntoClj::into_clj(self.{}.clone())),",
+            keyword(&field.name),
+            f
+        ));
+        setters.push_str(&format!(
+            "{:?} => r.{} = FromClj::from_clj(value),",
+            field.name, f
+        ));
+        params.push_str(&format!("{}:&CljVal,", f));
+        inits.push_str(&format!("{}: FromClj::from_clj({}),", f, f));
+        from_map.push_str(&format!("&get__2(m,&{}),", keyword(&field.name)));
+    }
+    format!(
+        "#[derive(Debug, Clone)]\npub struct {name} {{{declared}}}\n\
+         impl Record for {name} {{\n\
+         fn type_name(&self) -> &str {{{type_name:?}}}\n\
+         fn fields(&self) -> Vec<(CljVal, CljVal)> {{vec![{entries}]}}\n\
+         fn with_field(&self, key: &str, value: &CljVal) -> Option<std::rc::Rc<dyn Record>> {{\
+         let mut r = self.clone();match key {{{setters}_ => return None,}}Some(std::rc::Rc::new(r))}}\n\
+         fn as_any(&self) -> &dyn std::any::Any {{self}}\n}}\n\
+         pub fn {ctor}({params})->CljVal{{CljVal::record({name} {{{inits}}})}}\n\
+         pub fn {map_ctor}(m:&CljVal,)->CljVal{{assoc_all(&{ctor}({from_map}),m)}}",
+        name = rust_name,
+        declared = declared,
+        type_name = type_name,
+        entries = entries,
+        setters = setters,
+        ctor = mangle(&format!("->{}", name)),
+        map_ctor = mangle(&format!("map->{}", name)),
+        params = params,
+        inits = inits,
+        from_map = from_map
+    )
+}
+/// A field read from the struct of a record, or looked up in it like in a
+/// map if the value is not the record it is known to be after all.
+fn translate_get_field(record: &str, field: &str, target: &SyntaxNode) -> String {
+    format!(
+        "{{let r__ = &{};match r__.as_record::<{}>() {{Some(r__) => IntoClj::into_clj(r__.{}.clone()),None => get__2(r__,&{}),}}}}",
+        translate(target),
+        mangle(record),
+        mangle(field),
+        keyword(field)
+    )
+}
+/// The parameters of a method in its trait, the value it is called on
+/// being `self`.
+fn trait_params(method: &Method) -> String {
+    let mut params = vec!["&self".to_string()];
+    params.extend(method.params[1..].iter().map(|p| format!("{}: &CljVal", p)));
+    params.join(", ")
+}
+/// A protocol is a Rust trait, the methods of which take and return values.
+fn translate_protocol(protocol: &Protocol) -> String {
+    let mut s = rustdoc(&protocol.doc);
+    s.push_str(&format!("pub trait {} {{\n", mangle(&protocol.name)));
+    for method in &protocol.methods {
+        s.push_str(&rustdoc(&method.doc));
+        s.push_str(&format!(
+            "fn {}({}) -> CljVal;\n",
+            mangle(&method.name),
+            trait_params(method)
+        ));
+    }
+    s.push('}');
+    s
+}
+/// The functions implementing the methods of a protocol for a type, and
+/// the impl of the trait of the protocol for the Rust type of it if it has
+/// one, which calls the functions of the methods on it as a value.
+fn translate_extension(extension: &Extension) -> String {
+    let mut s = String::new();
+    for (method, arity) in &extension.methods {
+        let name = format!("{}__{}", extension.prefix, mangle(method));
+        s.push_str(&translate_fn_arity("pub ", &name, arity));
+        s.push('\n');
+    }
+    let rust_type = match &extension.rust_type {
+        Some(rust_type) => rust_type,
+        None => return s,
+    };
+    let this = match extension.record {
+        true => "&CljVal::record(self.clone())",
+        false => "&IntoClj::into_clj(self.clone())",
+    };
+    let protocol = &extension.protocol;
+    s.push_str(&format!(
+        "impl {} for {} {{\n",
+        protocol.path(&protocol.name),
+        rust_type
+    ));
+    for method in &protocol.methods {
+        let mut args = vec![this.to_string()];
+        args.extend(method.params[1..].iter().cloned());
+        s.push_str(&format!(
+            "fn {}({}) -> CljVal {{{}({})}}\n",
+            mangle(&method.name),
+            trait_params(method),
+            protocol.path(&method.name),
+            args.join(",")
+        ));
+    }
+    s.push('}');
     s
 }
 pub fn translate(n: &SyntaxNode) -> String {
-    if n.is_in_context(&n.this) {
-        match &n.this {
-            AstVal::AstSymbol(s) => return s.clone(),
-            _ => panic!("can not happen"),
+    match n {
+        SyntaxNode::Fn {
+            name,
+            public,
+            arities,
+            doc,
+        } => translate_fn(name, *public, arities, doc),
+        SyntaxNode::Closure {
+            name,
+            captures,
+            arities,
+        } => translate_closure(name.as_deref(), captures, arities),
+        SyntaxNode::Def {
+            name,
+            kind,
+            public,
+            value,
+        } => translate_def(name, *kind, *public, value),
+        SyntaxNode::Nothing => String::new(),
+        SyntaxNode::Do(body) => translate_do(body),
+        SyntaxNode::Let { bindings, body } => translate_let(bindings, body),
+        SyntaxNode::If {
+            test,
+            then,
+            otherwise,
+        } => translate_if(test, then, otherwise),
+        SyntaxNode::Loop { bindings, body } => translate_loop(bindings, body),
+        SyntaxNode::Recur { targets, args } => translate_recur(targets, args),
+        SyntaxNode::Case {
+            expr,
+            clauses,
+            default,
+        } => translate_case(expr, clauses, default),
+        SyntaxNode::Call { path, args } => translate_call(path, args),
+        SyntaxNode::Invoke { target, args } => match &**target {
+            SyntaxNode::LocalRef { name, .. } => translate_invoke(name, args),
+            target => translate_invoke(&translate(target), args),
+        },
+        SyntaxNode::Vector(items) => translate_seq("CljVal::CljVec", items),
+        SyntaxNode::List(items) => translate_seq("CljVal::CljList", items),
+        SyntaxNode::HashMap(pairs) => translate_hash_map(pairs),
+        SyntaxNode::Println(items) => translate_println(items),
+        SyntaxNode::Equal(a, b) => translate_equal(a, b),
+        SyntaxNode::Const(a) => translate_constant(a),
+        SyntaxNode::LocalRef { name, .. } => format!("{}.clone()", name),
+        SyntaxNode::GlobalRef { path, kind } => translate_ref(path, *kind),
+        SyntaxNode::RustCall { callee, args } => translate_rust_call(callee, args),
+        SyntaxNode::Record {
+            name,
+            type_name,
+            fields,
+            extensions,
+        } => {
+            let mut s = translate_record(name, type_name, fields);
+            for extension in extensions {
+                s.push('\n');
+                s.push_str(&translate_extension(extension));
+            }
+            s
+        }
+        SyntaxNode::Protocol(protocol) => translate_protocol(protocol),
+        SyntaxNode::Extend(extensions) => {
+            let items: Vec<String> = extensions.iter().map(translate_extension).collect();
+            items.join("\n")
         }
+        SyntaxNode::GetField {
+            record,
+            field,
+            target,
+        } => translate_get_field(record, field, target),
+        SyntaxNode::Try {
+            body,
+            catches,
+            finally,
+        } => translate_try(body, catches, finally),
+        SyntaxNode::Init { name, body } => format!("fn {}() {{{};}}", name, translate(body)),
     }
-    match &n.this {
-        AstVal::AstSymbol(t) => match t.as_str() {
-            "fn" => translate_fn(&n),
-            "let" => translate_let(&n),
-            "if" => translate_if(&n),
-            "do" => translate_do(&n),
-            "println" => translate_println(&n),
-            "=" => translate_equal(&n),
-            _ => translate_call(&n),
-        },
-        AstVal::AstNil => String::from("CljVal::CljNil"),
-        AstVal::AstBool(b) => format!("CljVal::CljBool({})", b),
-        AstVal::AstInt(i) => format!("CljVal::CljInt({})", i),
-        AstVal::AstFloat(f) => format!("CljVal::CljFloat({})", f),
-        AstVal::AstString(s) => format!("CljVal::CljString(\"{}\".to_string())", s.to_string()),
-        _ => panic!("not support yet!{:#?}", n),
+}
+
+/// The `__init` of a module, running the top-level forms that are not
+/// definitions once, after the `__init` of each module in `required`.
+pub fn translate_init(inits: &[String], required: &[String]) -> String {
+    let mut s = String::from(
+        "/// Runs the top-level forms of this module, once, after those of the modules it requires.\npub fn __init() {",
+    );
+    s.push_str(
+        "thread_local!(static DONE__: std::cell::Cell<bool> = std::cell::Cell::new(false));",
+    );
+    s.push_str("if DONE__.with(|done__| done__.replace(true)) {return;}");
+    for module in required {
+        s.push_str(&format!("{}::__init();", module));
+    }
+    for init in inits {
+        s.push_str(&format!("{}();", init));
+    }
+    s.push_str("}\n");
+    s
+}
+
+/// The typed wrappers of the functions with a `:rust/sig`, in a `typed`
+/// module next to them. Each converts its arguments into values, and the
+/// value the function returns into its return type.
+pub fn translate_signatures(signatures: &[Signature]) -> String {
+    if signatures.is_empty() {
+        return String::new();
+    }
+    let mut s = String::from(
+        "/// The functions of this module that have a Rust signature, taking and returning Rust types.\npub mod typed {\nuse clojure_rust_core::*;\n",
+    );
+    for sig in signatures {
+        let params: Vec<String> = sig
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, t)| format!("a{}: {}", i, t))
+            .collect();
+        // What is borrowed is converted from an owned copy.
+        let args: Vec<String> = sig
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, t)| match t.starts_with('&') {
+                true => format!("IntoClj::into_clj(a{}.to_owned())", i),
+                false => format!("IntoClj::into_clj(a{})", i),
+            })
+            .collect();
+        let call = match sig.dispatcher {
+            true => format!("super::{}(&[{}])", sig.name, args.join(",")),
+            false => {
+                let args: Vec<String> = args.iter().map(|a| format!("&{}", a)).collect();
+                format!("super::{}({})", sig.name, args.join(","))
+            }
+        };
+        s.push_str(&rustdoc(&sig.doc));
+        s.push_str(&match &sig.ret {
+            Some(ret) => format!(
+                "pub fn {}({}) -> {} {{<{} as FromClj>::from_clj(&{})}}\n",
+                sig.name,
+                params.join(", "),
+                ret,
+                ret,
+                call
+            ),
+            None => format!("pub fn {}({}) {{{};}}\n", sig.name, params.join(", "), call),
+        });
+    }
+    s.push_str("}\n");
+    s
+}
+
+/// The functions of the methods of protocols, each calling the one
+/// implementing it for the type of its first argument, by the name the
+/// runtime gives the type, and the function telling whether a value
+/// satisfies the protocol. They are generated once every type the
+/// protocols are extended to is known. Extending to `Object` covers every
+/// type but `nil`.
+pub fn translate_dispatchers(protocols: &[Protocol], extensions: &[Extension]) -> String {
+    let mut s = String::new();
+    for protocol in protocols {
+        let extensions: Vec<&Extension> = extensions
+            .iter()
+            .filter(|e| e.protocol.qualified == protocol.qualified)
+            .collect();
+        let object = extensions.iter().find(|e| e.type_name == "Object");
+        for method in &protocol.methods {
+            let this = &method.params[0];
+            let params: Vec<String> = method
+                .params
+                .iter()
+                .map(|p| format!("{}: &CljVal", p))
+                .collect();
+            let args = method.params.join(",");
+            let implements =
+                |e: &&&Extension| e.methods.iter().any(|(name, _)| *name == method.name);
+            s.push_str(&rustdoc(&method.doc));
+            s.push_str(&format!(
+                "pub fn {}({}) -> CljVal {{match {}.type_name() {{",
+                mangle(&method.name),
+                params.join(","),
+                this
+            ));
+            for e in extensions.iter().filter(implements) {
+                if e.type_name != "Object" {
+                    s.push_str(&format!(
+                        "{:?} => {}({}),",
+                        e.type_name,
+                        e.path(&method.name),
+                        args
+                    ));
+                }
+            }
+            let missing = format!(
+                "no_implementation({:?}, {:?}, {})",
+                method.name, protocol.qualified, this
+            );
+            match object.filter(implements) {
+                Some(e) => {
+                    if !extensions
+                        .iter()
+                        .filter(implements)
+                        .any(|e| e.type_name == "nil")
+                    {
+                        s.push_str(&format!("\"nil\" => {},", missing));
+                    }
+                    s.push_str(&format!("_ => {}({}),", e.path(&method.name), args));
+                }
+                None => s.push_str(&format!("_ => {},", missing)),
+            }
+            s.push_str("}}\n");
+        }
+        let types: Vec<String> = extensions
+            .iter()
+            .filter(|e| e.type_name != "Object")
+            .map(|e| format!("{:?}", e.type_name))
+            .collect();
+        let test = match object {
+            Some(_) if types.iter().any(|t| t == "\"nil\"") => "true".to_string(),
+            Some(_) => "x__.type_name() != \"nil\"".to_string(),
+            None if types.is_empty() => "false".to_string(),
+            None => format!("matches!(x__.type_name(), {})", types.join("|")),
+        };
+        s.push_str(&format!(
+            "pub fn {}(x__: &CljVal) -> CljVal {{CljVal::CljBool({})}}\n",
+            protocol.satisfies_name(),
+            test
+        ));
     }
+    s
+}
+
+/// What a crate root starts with: the lints generated code is not written
+/// for, the runtime crate, and the check that it is a version that runs the
+/// code of this compiler. `public` re-exports it, for
+/// the crates a library is used by.
+pub fn crate_prelude(public: bool) -> String {
+    format!(
+        "#![allow(warnings)]\n{}extern crate clojure_rust_core;\nconst _: () = clojure_rust_core::check_version(\"{}\");\n",
+        if public { "pub " } else { "" },
+        env!("CARGO_PKG_VERSION")
+    )
 }
@@ -0,0 +1,144 @@
+mod common;
+
+use common::{clojure_rust, clojure_rust_in, scratch};
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn exits_with_2_on_bad_usage() {
+    for args in [
+        &["--bogus"][..],
+        &["fmt"],
+        &["--emit", "llvm", "-"],
+        &["repl", "a.clj"],
+    ] {
+        let (code, stdout, stderr) = clojure_rust(args, "");
+        assert_eq!((code, stdout.as_str()), (2, ""), "{:?}", args);
+        assert!(stderr.contains("try '--help'"), "{}", stderr);
+    }
+    let (code, stdout, _) = clojure_rust(&["--version"], "");
+    assert_eq!((code, stdout.as_str()), (0, "clojure-rust 0.0.1\n"));
+}
+
+#[test]
+fn compiles_stdin_to_stdout() {
+    let (code, stdout, stderr) = clojure_rust(&["-"], "(defn main [] (println 1))\n");
+    assert_eq!((code, stderr.as_str()), (0, ""));
+    assert!(stdout.contains("pub fn main(){__init();"), "{}", stdout);
+    let (code, stdout, stderr) = clojure_rust(&["compile", "-"], "(foo)\n");
+    assert_eq!((code, stdout.as_str()), (1, ""));
+    assert!(
+        stderr.starts_with("error[E0001]: Unable to resolve symbol: foo"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn fmt_indents_stdin_and_files() {
+    let messy = "(defn f [x]\n(let [y 1]\n      y))\n";
+    let tidy = "(defn f [x]\n  (let [y 1]\n    y))\n";
+    assert_eq!(
+        clojure_rust(&["fmt", "-"], messy),
+        (0, tidy.to_string(), String::new())
+    );
+    let dir = scratch("fmt");
+    let file = dir.join("f.clj");
+    let path = file.to_str().unwrap();
+    fs::write(&file, messy).unwrap();
+    assert_eq!(
+        clojure_rust(&["fmt", "--check", path], ""),
+        (1, format!("{}\n", path), String::new())
+    );
+    assert_eq!(fs::read_to_string(&file).unwrap(), messy);
+    assert_eq!(clojure_rust(&["fmt", path], "").0, 0);
+    assert_eq!(fs::read_to_string(&file).unwrap(), tidy);
+    assert_eq!(clojure_rust(&["fmt", "--check", path], "").0, 0);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn packages_a_program_named_after_its_project() {
+    let dir = scratch("package").join("my-app");
+    let src = dir.join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(
+        src.join("main.clj"),
+        "(ns main (:require [lib :as l]))\n(defn main [] (println (l/twice 2)))\n",
+    )
+    .unwrap();
+    fs::write(
+        src.join("lib.clj"),
+        "(ns lib (:require [util]))\n(def twice util/twice)\n",
+    )
+    .unwrap();
+    fs::write(src.join("util.clj"), "(ns util)\n(defn twice [x] [x x])\n").unwrap();
+    let (code, stdout, stderr) = clojure_rust_in(&src, &["run", "main.clj"], "");
+    assert_eq!((code, stdout.as_str()), (0, "[2 2]\n"), "{}", stderr);
+    let manifest = fs::read_to_string(dir.join("Cargo.toml")).unwrap();
+    assert!(
+        manifest.starts_with("[package]\nname = \"my-app\"\n"),
+        "{}",
+        manifest
+    );
+    assert!(manifest.contains("clojure-rust-core = {"), "{}", manifest);
+    assert!(src.join("main/mod.rs").exists() && src.join("lib/mod.rs").exists());
+    assert!(!src.join("lib.rs").exists());
+    let library = scratch("library").join("src");
+    fs::create_dir_all(&library).unwrap();
+    fs::write(library.join("main.clj"), "(ns main)\n(defn f [] 1)\n").unwrap();
+    let (code, _, stderr) = clojure_rust_in(&library, &["build", "main.clj"], "");
+    assert_eq!(code, 0, "{}", stderr);
+    assert!(library.join("main/mod.rs").exists() && !library.join("main.rs").exists());
+    fs::remove_dir_all(library.parent().unwrap()).unwrap();
+    fs::write(
+        src.join("main.clj"),
+        "(defn main [] (throw (ex-info \"no\" {:a 1})))\n",
+    )
+    .unwrap();
+    assert_ne!(clojure_rust_in(&dir, &["run", "src/main.clj"], "").0, 0);
+    fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn typed_wrappers_are_callable_from_rust() {
+    let dir = scratch("typed");
+    fs::write(
+        dir.join("shop.clj"),
+        "(ns shop)\n\
+         (defn ^{:rust/sig \"fn(i64, &str) -> String\"} label\n  \"A label.\"\n  \
+         [n s] (rust-macro! format \"{}-{}\" n s))\n\
+         (defn ^{:rust/sig \"fn(Vec<i64>) -> Option<i64>\"} head [v] (get v 0))\n\
+         (defn ^{:rust/sig \"fn(i64)\"} ignore [x] nil)\n",
+    )
+    .unwrap();
+    let (code, _, stderr) =
+        clojure_rust_in(&dir, &["build", "--lib", "-o", "shop", "shop.clj"], "");
+    assert_eq!(code, 0, "{}", stderr);
+    let shop = dir.join("shop");
+    assert!(fs::read_to_string(shop.join("src/shop.rs"))
+        .unwrap()
+        .contains("/// A label.\npub fn label(a0: i64, a1: &str) -> String"));
+    fs::create_dir_all(shop.join("tests")).unwrap();
+    fs::write(
+        shop.join("tests/api.rs"),
+        "use shop::shop::typed::*;\n\
+         #[test]\nfn calls() {\n    \
+         assert_eq!(label(3, \"a\"), \"3-a\");\n    \
+         assert_eq!(head(vec![4, 5]), Some(4));\n    \
+         assert_eq!(head(vec![]), None);\n    \
+         ignore(1);\n}\n",
+    )
+    .unwrap();
+    let output = Command::new("cargo")
+        .args(["test", "--offline", "--quiet", "--manifest-path"])
+        .arg(shop.join("Cargo.toml"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
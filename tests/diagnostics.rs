@@ -0,0 +1,129 @@
+mod common;
+
+/// What `check` reports of a source read from stdin, as JSON, with the code
+/// it exits with.
+fn check(source: &str) -> (i32, String) {
+    let (code, stdout, stderr) =
+        common::clojure_rust(&["check", "-", "--error-format", "json"], source);
+    (code, stdout + &stderr)
+}
+
+#[test]
+fn types_with_one_name_in_rust_are_reported() {
+    let (code, report) = check("(defprotocol Shape (area [this]))\n(defrecord Shape [w])\n");
+    assert_eq!(code, 1);
+    assert!(report.contains(r#""code":"E0027""#), "{}", report);
+    assert!(report.contains("record Shape would have the same name in Rust as protocol Shape"));
+    let (code, report) = check("(defrecord String [w])\n");
+    assert_eq!(code, 1);
+    assert!(report.contains("as the Rust type String"), "{}", report);
+}
+
+#[test]
+fn names_differing_in_dashes_and_underscores_are_kept_apart() {
+    let (code, report) = check("(defn new-user [] 1)\n(defn new_user [] 2)\n(let [_ 1] _)\n");
+    assert_eq!((code, report.as_str()), (0, ""));
+}
+
+#[test]
+fn forms_the_compiler_can_not_take_are_reported_with_a_code() {
+    let (code, report) = check("(quote)\n");
+    assert_eq!(code, 1);
+    assert!(report.contains(r#""code":"E0002""#), "{}", report);
+    let (code, report) = check("(defn main [] (println ^:x 1))\n");
+    assert_eq!(code, 1);
+    assert!(report.contains(r#""code":"E0028""#), "{}", report);
+    let (code, report) = check("(def ^{} x '(a ^:x b))\n");
+    assert_eq!((code, report.as_str()), (0, ""));
+}
+
+#[test]
+fn case_tests_each_constant_once() {
+    let (code, report) = check("(case 1 (1 2) :a (3 2) :b :c)\n");
+    assert_eq!(code, 1);
+    assert!(
+        report.contains("Duplicate case test constant: 2"),
+        "{}",
+        report
+    );
+    let (code, report) = check("(case 1 (1 2) :a 3 :b :c)\n");
+    assert_eq!((code, report.as_str()), (0, ""));
+}
+
+#[test]
+fn rust_signatures_are_checked() {
+    for (source, message) in [
+        (
+            "(defn- ^{:rust/sig \"fn(i64) -> i64\"} f [x] x)\n",
+            "f is private, and so gets no Rust signature",
+        ),
+        (
+            "(defn ^{:rust/sig \"i64\"} f [x] x)\n",
+            "bad :rust/sig \\\"i64\\\" of f, expected a fn type",
+        ),
+        (
+            "(defn ^{:rust/sig \"fn(i64, i64) -> i64\"} f [x] x)\n",
+            "the :rust/sig of f takes 2 arguments, which it has no arity for",
+        ),
+        (
+            "(defn ^{:rust/sig \"fn(i64) -> &str\"} f [x] x)\n",
+            "f can not return the reference &str",
+        ),
+        (
+            "(defn ^{:rust/sig 1} f [x] x)\n",
+            "bad :rust/sig 1, expected a string",
+        ),
+    ] {
+        let (code, report) = check(source);
+        assert_eq!(code, 1);
+        assert!(report.contains(r#""code":"E0023""#), "{}", report);
+        assert!(report.contains(message), "{}", report);
+    }
+    let (code, report) = check("(defn ^{:rust/sig \"fn(&str, Vec<i64>)\"} f [s & more] s)\n");
+    assert_eq!((code, report.as_str()), (0, ""));
+}
+
+#[test]
+fn errors_point_at_the_form_read() {
+    let at =
+        |line: u32, column: u32| format!(r#""line_start":{},"column_start":{},"#, line, column);
+    for (source, line, column) in [
+        ("(defn f [] (let [x 1] x) x)\n", 1, 26),
+        ("(def a 1)\n(def b 2)\n(def m {:a 1 :b})\n", 3, 8),
+        ("(def a 1)\n(defn f [x]\n  (inc x)\n", 2, 1),
+        ("(def a 1)\n)\n", 2, 1),
+        ("(def a \"x\\q\")\n", 1, 10),
+        ("(defn f [x]\n  (let (y 1)\n    y))\n", 2, 3),
+    ] {
+        let (code, report) = check(source);
+        assert_eq!(code, 1);
+        assert!(report.contains(&at(line, column)), "{}", report);
+    }
+}
+
+#[test]
+fn core_macros_check_their_arity() {
+    for (form, message) in [
+        ("(case)", "Wrong number of args (0) passed to: case"),
+        ("(->)", "Wrong number of args (0) passed to: ->"),
+        ("(->> 1 ())", "nil can't be called"),
+        ("(condp =)", "Wrong number of args (1) passed to: condp"),
+        ("(doto)", "Wrong number of args (0) passed to: doto"),
+    ] {
+        let (code, report) = check(&format!("(defn main [] {})\n", form));
+        assert_eq!(code, 1);
+        assert!(report.contains(message), "{}", report);
+        assert!(!report.contains("E0000"), "{}", report);
+    }
+}
+
+#[test]
+fn rust_paths_have_no_empty_segment() {
+    for form in ["(rust/ 1)", "(String/ 1)", "(rust/std::cmp:: 1 2)"] {
+        let (code, report) = check(&format!("(defn main [] {})\n", form));
+        assert_eq!(code, 1);
+        assert!(report.contains("Malformed Rust path"), "{}", report);
+    }
+    let (code, report) = check("(defn main [] (rust/::std::cmp::max 1 2))\n");
+    assert_eq!((code, report.as_str()), (0, ""));
+}
@@ -0,0 +1,49 @@
+use std::process::Command;
+
+/// Every program of the examples and of tests/programs does the same
+/// interpreted and compiled with rustc.
+#[test]
+fn interpreted_and_compiled_programs_agree() {
+    let status = Command::new(env!("CARGO_BIN_EXE_clojure-rust"))
+        .args(["test", "example", "tests/programs"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+/// The Rust checked in for the hello world example is what the compiler
+/// makes of it now.
+#[test]
+fn example_is_generated_by_the_current_compiler() {
+    let output = Command::new(env!("CARGO_BIN_EXE_clojure-rust"))
+        .args(["compile", "example/hello_world.clj", "-o", "-"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let checked_in = std::fs::read_to_string("example/hello_world/src/main.rs").unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), checked_in);
+}
+
+/// Each program of tests/programs with a `main` has the output it should
+/// give in a `.out` file next to it.
+#[test]
+fn test_programs_have_expected_output() {
+    fn check(dir: &std::path::Path) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                check(&path);
+            } else if path.extension().is_some_and(|e| e == "clj") {
+                let source = std::fs::read_to_string(&path).unwrap();
+                if source.contains("(defn main") {
+                    assert!(
+                        path.with_extension("out").exists(),
+                        "{} has no .out file",
+                        path.display()
+                    );
+                }
+            }
+        }
+    }
+    check(std::path::Path::new("tests/programs"));
+}
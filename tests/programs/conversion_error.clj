@@ -0,0 +1,6 @@
+(defrecord Pixel [^u8 level])
+
+(defn main []
+  (println (->Pixel 255) (->Pixel 3.0))
+  (println (->Pixel 2.5))
+  (println "not reached"))
@@ -0,0 +1,45 @@
+(defn classify [x]
+  (cond (= x 1) "one"
+        (= x 2) "two"
+        :else "many"))
+
+(defn kind [x]
+  (case x
+    1 "int one"
+    (2 3) "two or three"
+    "s" "string s"
+    :k "keyword k"
+    (:a :b "c") "a b or c"
+    nil "nil"
+    "default"))
+
+(defn veccase [x]
+  (case x
+    [1 2] "vector"
+    "not vector"))
+
+(defmacro maccond [x]
+  (cond (symbol? x) "sym" (when (number? x) true) (-> x inc str) :else (and 1 nil)))
+
+(defn countdown [n]
+  (loop [i n acc []]
+    (if (= i 0) acc (recur (get {3 2 2 1 1 0} i) (case i 3 [3] 2 [3 2] [3 2 1])))))
+
+(defn main []
+  (println (classify 1) (classify 5))
+  (println (kind 1) (kind 3) (kind "s") (kind :k) (kind :b) (kind "c") (kind nil) (kind 9))
+  (println (veccase [1 2]) (veccase 3))
+  (when true (println "when"))
+  (when-not false (println "when-not"))
+  (println (condp = 2 1 "c1" 2 "c2" "cd"))
+  (println (-> {:a {:b 5}} :a :b))
+  (println (->> [1 2 3] (map inc)))
+  (println (as-> 1 x [x x] (get x 0)))
+  (println (some-> {:a 1} :a inc) (some-> {:a 1} :b inc))
+  (println (and 1 2) (and 1 nil 2) (or nil 3) (or nil false) (and) (or))
+  (println (if-let [x (get {:a 1} :a)] x "none") (if-let [[a b] nil] a "none"))
+  (when-let [{:keys [a]} {:a "wl"}] (println a))
+  (println (doto [1] println))
+  (dotimes [i 3] (println i))
+  (println (maccond y) (maccond 4) (maccond "s"))
+  (println (countdown 3)))
@@ -0,0 +1,17 @@
+(declare greeting show)
+(def ^:private limit 3)
+(def table {:a 1 :b 2})
+(def handler (fn [x] [x limit]))
+(defonce table {:a 99})
+(defonce once "first")
+(def doc_string "a docstring" once)
+(def big 5000000000)
+
+(defn main []
+  (println limit table (get table :b) greeting doc_string)
+  (println (handler 1) (map handler [5]))
+  (println (show 1 2))
+  (println (inc big) 0.1 (inc 0.5)))
+
+(def greeting "hi")
+(defn show [a b] [a b greeting])
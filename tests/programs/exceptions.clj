@@ -0,0 +1,42 @@
+;; Exceptions thrown, caught by class or data, and cleaned up after.
+(defn fetch [m k]
+  (if (get m k)
+    (get m k)
+    (throw (ex-info "not found" {:type :not-found :key k}))))
+
+(defn lookup [m k]
+  (try
+    (fetch m k)
+    (catch {:type :not-found} e
+      [:missing (:key (ex-data e))])
+    (finally
+      (println "looked up" k))))
+
+(defn risky [v i]
+  (try
+    (nth v i)
+    (catch ExceptionInfo e :info)
+    (catch Exception e [:error (ex-data e)])))
+
+(defn wrapped []
+  (try
+    (throw (ex-info "inner" {:level 1}))
+    (catch clojure.lang.ExceptionInfo e
+      (throw (ex-info "outer" {:level 2} e)))))
+
+(defn main []
+  (println (lookup {:a 1} :a) (lookup {:a 1} :b))
+  (println (risky [1 2] 1) (risky [1 2] 5))
+  (let [e (try (wrapped) (catch :default e e))]
+    (println (ex-message e) (ex-data e) (ex-message (ex-cause e)) (ex-data (ex-cause e))))
+  (println (ex-data "not an exception") (ex-message nil))
+  (println (try 1 (finally (println "finally runs"))))
+  (println (loop [i 0 seen {:from 0}]
+             (if (< i 3)
+               (recur (inc i) (try (if (= i 1) (throw (ex-info "skip" {:i i})) (assoc seen i i))
+                                   (catch ExceptionInfo e (assoc seen i (ex-data e)))))
+               seen)))
+  (try
+    (throw (ex-info "unhandled" {:code 42}))
+    (catch {:code 0} e :wrong-code)
+    (finally (println "cleanup"))))
@@ -0,0 +1,13 @@
+;; Vars named like the locals of the generated code.
+(def caught 1)
+(def this 2)
+(def x 3)
+(def done 4)
+(defprotocol P (area [this x]))
+(defrecord R [w] P (area [this x] [(:w this) x]))
+(defn f ([] 0) ([n] n))
+(def n 7)
+(defn main []
+  (println (area (->R 5) 6) (satisfies? P 1) (try (throw (ex-info "e" {})) (catch Exception e [caught done]))
+           (map f [1 2]) (:w (->R x)))
+  (println (f) (f n)))
@@ -0,0 +1,14 @@
+(defn main []
+  (let [a 7
+        s "hello"]
+    (println (rust/std::cmp::max ^i64 a 3))
+    (println (.len ^String s))
+    (println (.len [1 2 3]) (.len s) (.len nil))
+    (println (rust/str::len ^&str s) (.starts_with ^String s ^&str "he"))
+    (println (.len ^"&[i64]" [1 2]) (.contains ^"&[i64]" [1 2 3] ^&i64 5))
+    (println (.to_uppercase ^String s))
+    (println (String/from "x"))
+    (println (rust-macro! format "{}-{}" a s))
+    (println (rust/i64::pow ^i64 a 2))
+    (println (.contains ^Vec<i64> [1 2 3] ^&i64 2))
+    (rust-macro! println "from {}" "rust")))
@@ -0,0 +1,35 @@
+(defn helper [x] (list 'println x))
+
+(defmacro unless [test & body]
+  `(if ~test nil (do ~@body)))
+
+(defmacro twice [x]
+  (let [v# 1]
+    `(let [y# ~x] [y# y#])))
+
+(defmacro show-form [& args]
+  (list 'println (pr-str &form)))
+
+(defmacro locals []
+  (vec (keys &env)))
+
+(defmacro call-helper [x]
+  (helper x))
+
+(defmacro my-let [[a b] & body]
+  `(let [~a ~b] ~@body))
+
+(defmacro swap-args
+  ([f] (list f))
+  ([f a b] (list f b a)))
+
+(defn main []
+  (unless false (println "unless ok"))
+  (println (twice 3))
+  (show-form 1 2)
+  (let [q 1 r 2] (println (locals)))
+  (call-helper "from helper")
+  (my-let [z "my-let"] (println z))
+  (println (swap-args vector 1 2))
+  (println (macroexpand-1 '(unless a b)))
+  (println (macroexpand '(my-let [z 1] z))))
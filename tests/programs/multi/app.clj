@@ -0,0 +1,11 @@
+(ns multi.app
+  (:require [multi.shapes :as s :refer [area]]))
+
+(defmethod s/area :round [c] [:round (:r c)])
+(defmethod area :default [x] :no-area)
+
+(println "registered")
+
+(defn main []
+  (println (area {:shape :square :side 2}) (area {:shape :circle :r 1}))
+  (println (area {:shape :blob}) (isa? :circle :round)))
@@ -0,0 +1,7 @@
+(ns multi.shapes)
+
+(defmulti area :shape)
+
+(defmethod area :square [s] [:square (:side s)])
+
+(derive :circle :round)
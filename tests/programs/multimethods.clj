@@ -0,0 +1,43 @@
+;; Multimethods dispatching on a key, with a hierarchy and preferences.
+(defmulti describe "What a thing is." :kind)
+
+(defmethod describe :dog [x] [:dog (:name x)])
+(defmethod describe :animal [x] [:some-animal (:name x)])
+(defmethod describe :default [x] [:unknown x])
+
+(derive :dog :animal)
+(derive :cat :animal)
+(derive :dog :pet)
+(derive :cat :pet)
+(defmethod describe :pet [x] [:pet (:name x)])
+(prefer-method describe :pet :animal)
+
+(defmulti combine (fn [a b] [(:kind a) (:kind b)]) :default :none)
+(defmethod combine [:animal :animal] [a b] :friends)
+(defmethod combine [:dog :cat] [a b] :chase)
+(defmethod combine :none [a b] :strangers)
+
+(defmulti arity (fn [& args] (nth [:zero :one :two] (reduce (fn [n _] (inc n)) 0 args))))
+(defmethod arity :zero [] "none")
+(defmethod arity :one ([x] [:one x]))
+(defmethod arity :two [x y] [:two x y])
+
+(defn main []
+  (let [rex {:kind :dog :name "Rex"}
+        tom {:kind :cat :name "Tom"}]
+    (println (describe rex) (describe tom) (describe {:kind :rock}))
+    (println (combine rex tom) (combine tom rex) (combine rex {:kind :rock}))
+    (println (isa? :dog :animal) (isa? [:dog :cat] [:animal :pet]) (isa? :animal :dog))
+    (println (parents :dog) (descendants :lizard))
+    (println (arity) (arity 1) (arity 1 2))
+    (println (= (get-method describe :dog) (get-method describe :dog)) (get-method combine :rock))
+    (remove-method describe :dog)
+    (println (describe rex))
+    (underive :cat :pet)
+    (println (describe tom))
+    (derive :bird :animal)
+    (derive :bird :pet)
+    (println (describe {:kind :bird :name "Tweety"}))
+    (defmethod combine [:cat :animal] [a b] :hiss)
+    (defmethod combine [:animal :cat] [a b] :stalk)
+    (combine tom tom)))
@@ -0,0 +1,12 @@
+;; A named fn literal calls itself by its name, which shadows the vars and
+;; locals around it.
+(def up 0)
+
+(defn main []
+  (let [limit 5
+        up (fn up [n] (if (< n limit) (up (inc n)) n))
+        pair (fn pair ([x] (pair x x)) ([x y] [x y]))
+        itself (fn itself [] itself)]
+    (println (up 1) (pair 1) (pair 1 2))
+    (println (= itself (itself)))
+    (map (fn step [x] (if (< x 3) (step (inc x)) x)) [1 2 3 4])))
@@ -0,0 +1,12 @@
+(def *stars* 3)
+(def new-user "ann")
+(defn valid? [x] (= x 1))
+(defn swap! [a b] [b a])
+(defn a->b [type] (let [match type loop [match]] loop))
+(defn 中文 [值] [值 值])
+(defn f
+  ([] (f 1))
+  ([x] x))
+(defn main []
+  (println (valid? 1) (swap! 1 2) (a->b 5) *stars* new-user (中文 9) (map valid? [1 2]) (f))
+  (println (let [self 4 g (fn [crate] [self crate])] (g 5))))
@@ -0,0 +1,7 @@
+(ns nsdemo.app
+  (:require [nsdemo.util :as u :refer [twice unless]]))
+
+(defn main []
+  (unless false (println "ns unless"))
+  (u/unless false (println "alias unless"))
+  (println (twice 4) (u/call-twice 5)))
@@ -0,0 +1,7 @@
+(ns nsdemo.util)
+
+(defn- helper [x] x)
+(defn add [a b] (helper 0) [a b])
+(defn twice [x] (add x x))
+(defmacro unless [t & body] `(if ~t nil (do ~@body)))
+(defmacro call-twice [x] `(twice ~x))
@@ -0,0 +1,39 @@
+(defprotocol Shape
+  "Something with a size."
+  (size [this] "The size of the shape.")
+  (grow [this]))
+
+(defrecord Square [^i64 side]
+  Shape
+  (size [_] side)
+  (grow [this] (->Square (inc side))))
+
+(defrecord Circle [r])
+
+(extend-type Circle
+  Shape
+  (size [c] [:radius (:r c)])
+  (grow [c] (assoc c :r (inc (:r c)))))
+
+(defprotocol Describe
+  (describe [x]))
+
+(extend-protocol Describe
+  String
+  (describe [s] [:string s])
+  Long
+  (describe [n] [:number n])
+  nil
+  (describe [_] "nothing")
+  Square
+  (describe [s] [:square (.-side s)]))
+
+(defn main []
+  (let [s (->Square 2)
+        c (->Circle 1)]
+    (println (size s) (size (grow s)) (grow s))
+    (println (size c) (grow c))
+    (println (map size [s c]))
+    (println (describe "a") (describe 42) (describe nil) (describe s))
+    (println (satisfies? Shape s) (satisfies? Shape "a") (satisfies? Describe nil))
+    (describe c)))
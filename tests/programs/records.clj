@@ -0,0 +1,22 @@
+(defrecord User [^i64 id name])
+
+(defrecord Point [x y])
+
+(defn describe [u]
+  (println (:name u) "is user" (:id u)))
+
+(defn main []
+  (let [u (->User 1 "ann")
+        p (map->Point {:x 1 :y 2 :z 3})
+        v (assoc u :name "bob")]
+    (println u)
+    (println (:name u) (.-id u))
+    (println p)
+    (println (keys p) (vals p))
+    (println v (get v :name))
+    (println (assoc u :role :admin))
+    (println (= u (->User 1 "ann")) (= u v) (= u {:id 1 :name "ann"}))
+    (println (= (->Point 1 2) (map->Point {:x 1 :y 2})))
+    (describe v)
+    (println (->User 2.0 "cid") (:id (->User 2.0 "cid")))
+    (println (:missing u))))
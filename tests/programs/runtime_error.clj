@@ -0,0 +1,7 @@
+(defn pick [v i]
+  (nth v i))
+
+(defn main []
+  (println (pick [1 2 3] 1))
+  (println (pick [1 2 3] 5))
+  (println "not reached"))
@@ -0,0 +1,33 @@
+(ns scope)
+(defn twice [x] [x x])
+(defn g [twice]
+  (twice 3))
+(defn h [x]
+  (let [x (inc x)
+        f (fn [] x)
+        x "shadow"]
+    [(f) x]))
+(defn adder [n]
+  (fn [x] [n x]))
+(defn new-user [] :dashed)
+(defn new_user [] :underscored)
+(def a 1)
+(def A 2)
+(def n 3)
+(def args [4])
+(defn vars [a]
+  (let [n (inc a)
+        f (fn [args] [args n])]
+    (f [a A n args])))
+(defn main []
+  (println ((fn [x] (inc x)) 1) ((adder 2) 3))
+  (println (g (fn [y] (inc y))))
+  (println (h 1))
+  (println (loop [x 0 acc []]
+             (let [x (inc x)]
+               (if (< x 4) (recur x [acc x]) acc))))
+  (println (let [map 5] map))
+  (println (let [args 1 f (fn [x] [args x])] (f 2)))
+  (println (twice 2) (let [a :local] [scope/a a]))
+  (println (vars 10) [a A n args])
+  (println (new-user) (new_user) (let [_ 1 a_b 2 a-b 3] [_ a_b a-b])))
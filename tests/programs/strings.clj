@@ -0,0 +1,7 @@
+(def quoted "q\"uote")
+
+(defn main []
+  (println quoted "back\\slash" "tab\tand\nnewline")
+  (println ["q\"uote" "back\\slash"] (= quoted "q\"uote"))
+  (println (get {"k\"ey" 1} "k\"ey") '("in\"quote" sym) :kw)
+  "last\\value")
@@ -0,0 +1,44 @@
+mod common;
+
+/// What the REPL prints on stdout of the lines typed in `input`, without
+/// its prompts.
+fn repl(input: &str) -> Vec<String> {
+    let (code, stdout, _) = common::clojure_rust(&["repl"], input);
+    assert_eq!(code, 0);
+    stdout
+        .split("user=> ")
+        .map(|s| s.replace("  #_=> ", "").trim_end().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+#[test]
+fn keeps_the_last_values_and_error() {
+    let input = "(def a 1)\n(inc a)\n(inc 2)\n[*1 *2]\n*3\n\
+                 (foo)\n(ex-data *e)\n(throw (ex-info \"boom\" {:a 1}))\n(ex-message *e)\n\
+                 (defn f [] 4)\n(*1)\n(doc f)\n[*1 *2]\n";
+    assert_eq!(
+        repl(input),
+        [
+            "#'user/a",
+            "2",
+            "3",
+            "[3 2]",
+            "2",
+            "{:code \"E0001\"}",
+            "\"boom\"",
+            "#'user/f",
+            "4",
+            "-------------------------\nuser/f\n([])\nnil",
+            "[nil 4]"
+        ]
+    );
+}
+
+#[test]
+fn reads_forms_over_several_lines() {
+    assert_eq!(
+        repl("(vector 1\n  \"a\nb\"\n  2)\n(inc\n 1)\n\"a\nb\"\n"),
+        ["[1 \"a\\nb\" 2]", "2", "\"a\\nb\""]
+    );
+}